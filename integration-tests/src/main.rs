@@ -1,56 +1,70 @@
-// use halo2_mpt_circuits::serde::SMTTrace;
-// use halo2_proofs::dev::MockProver;
-// pub use halo2_proofs::halo2curves::bn256::Fr as Fp;
-// use std::fs::File;
-// use std::io::Read;
+//! Runs the mock prover on each update in the mptwitness of a saved l2geth block trace.
+//!
+//! cargo run --release --features test-circuit --bin integration-test -- <block trace json>
+use halo2_mpt_circuits::{
+    hash_traces, serde::SMTTrace, types::Proof, MPTProofType, MptCircuitConfig, TestCircuit,
+};
+use halo2_proofs::{dev::MockProver, halo2curves::bn256::Fr};
+use serde::Deserialize;
+use std::{fs, process::exit};
 
-// use serde::Deserialize;
-
-// #[derive(Deserialize, Default)]
-// pub struct BlockResult {
-//     #[serde(rename = "mptwitness", default)]
-//     pub mpt_witness: Vec<SMTTrace>,
-// }
-
-fn main() {
-    // let mut buffer = Vec::new();
-    // let mut f = File::open("integration-tests/trace.json").unwrap();
-    // f.read_to_end(&mut buffer).unwrap();
-
-    // let traces: Vec<SMTTrace> = serde_json::from_slice::<BlockResult>(&buffer)
-    //     .unwrap()
-    //     .mpt_witness;
-    // let ops: Vec<AccountOp<Fp>> = traces.iter().map(|tr| tr.try_into().unwrap()).collect();
-
-    // let mut data: EthTrie<Fp> = Default::default();
-    // data.add_ops(ops);
-
-    // let (rows, hash_rows) = data.use_rows();
-    // let log2_ceil = |n| u32::BITS - (n as u32).leading_zeros() - (n & (n - 1) == 0) as u32;
-    // let k = log2_ceil(rows) + 1;
-    // let k = k.max(6);
+#[derive(Deserialize, Default)]
+struct BlockResult {
+    #[serde(rename = "mptwitness", default)]
+    mpt_witness: Vec<SMTTrace>,
+}
 
-    // println!(
-    //     "start proving trace with mpt-circuit, has {rows} rows, {hash_rows} hash_rows and base k is {k}",
-    // );
+fn log2_ceil(n: usize) -> u32 {
+    usize::BITS - n.saturating_sub(1).leading_zeros()
+}
 
-    // let final_root = data.final_root();
+fn fail(index: usize, message: String) -> ! {
+    eprintln!("update {index} failed: {message}");
+    exit(1)
+}
 
-    // let (circuit, hash_circuit) = match k {
-    //     6 => data.circuits(40),
-    //     7 => data.circuits(90),
-    //     8 => data.circuits(200),
-    //     9 => data.circuits(450),
-    //     10 => data.circuits(900),
-    //     11 => data.circuits(1950),
-    //     _ => panic!("too large k {k}"),
-    // };
+fn main() {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "integration-tests/trace.json".to_string());
+    let json = fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("cannot read {path}: {e}");
+        exit(1)
+    });
+    let traces = serde_json::from_str::<BlockResult>(&json)
+        .unwrap_or_else(|e| {
+            eprintln!("cannot parse {path}: {e}");
+            exit(1)
+        })
+        .mpt_witness;
+    println!("{} updates in {path}", traces.len());
 
-    // let prover_mpt = MockProver::<Fp>::run(k, &circuit, vec![]).unwrap();
-    // let prover_hash = MockProver::<Fp>::run(k + 6, &hash_circuit, vec![]).unwrap();
+    for (index, trace) in traces.iter().enumerate() {
+        let proof_type = MPTProofType::infer(trace)
+            .unwrap_or_else(|| fail(index, "cannot infer proof type".to_string()));
+        let proof = Proof::try_from((&proof_type, trace))
+            .unwrap_or_else(|e| fail(index, format!("{proof_type:?}: {e}")));
 
-    // assert_eq!(prover_mpt.verify(), Ok(()));
-    // assert_eq!(prover_hash.verify(), Ok(()));
+        let proofs = vec![proof];
+        let n_rows = MptCircuitConfig::n_rows_required(&proofs);
+        // Leave room for the poseidon table and the rows halo2 reserves for blinding.
+        let k = log2_ceil(n_rows.max(hash_traces(&proofs).len())) + 1;
 
-    // println!("done, final hash {final_root:?}");
+        let circuit = TestCircuit::from_proofs(n_rows, proofs);
+        let prover = MockProver::<Fr>::run(k, &circuit, vec![])
+            .unwrap_or_else(|e| fail(index, format!("{proof_type:?}: {e:?}")));
+        if let Err(errors) = prover.verify() {
+            for error in &errors {
+                eprintln!("{error}");
+            }
+            fail(
+                index,
+                format!("{proof_type:?}: {} constraints not satisfied", errors.len()),
+            );
+        }
+        println!(
+            "update {index}: {proof_type:?} for {}, {n_rows} rows, k = {k}, ok",
+            trace.address
+        );
+    }
 }
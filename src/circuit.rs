@@ -21,6 +21,10 @@ impl TestCircuit {
             proofs: traces.into_iter().map(Proof::from).collect(),
         }
     }
+
+    pub fn from_proofs(n_rows: usize, proofs: Vec<Proof>) -> Self {
+        Self { n_rows, proofs }
+    }
 }
 
 impl Circuit<Fr> for TestCircuit {
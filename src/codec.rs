@@ -0,0 +1,388 @@
+//! Versioned binary encoding of witnesses, which is smaller and faster to parse than their JSON
+//!
+//! A witness is the magic bytes, the format version and the number of its traces, followed by each
+//! trace prefixed by its length in bytes and its proof type (the discriminant of `MPTProofType`). A
+//! trace without a proof type is encoded on its own behind separate magic bytes and the version.
+//! Counts and lengths are u32 and integers are u64, both little endian. Hashes are canonical 32
+//! byte field elements, little endian as in the JSON traces, while storage keys and values, account
+//! fields and path parts are 32 byte big endian integers.
+use crate::{
+    serde::{AccountData, HexBytes, SMTNode, SMTPath, SMTTrace, StateData},
+    types::{Proof, TraceError},
+    MPTProofType,
+};
+use halo2_proofs::halo2curves::bn256::Fr;
+use num_bigint::BigUint;
+
+/// Bytes at the start of an encoded witness.
+pub const MAGIC_BYTES: &[u8; 4] = b"MPTW";
+/// Bytes at the start of an encoded trace without a proof type.
+pub const TRACE_MAGIC_BYTES: &[u8; 4] = b"MPTT";
+/// Version of the encoding, which is bumped whenever it changes.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Errors encoding or decoding a witness.
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    #[error("witness does not start with the magic bytes")]
+    /// input is not an encoded witness
+    MissingMagicBytes,
+    #[error("unsupported format version {0}")]
+    /// witness was encoded by another version of the format
+    UnsupportedVersion(u8),
+    #[error("unexpected end of input")]
+    /// input is truncated
+    UnexpectedEnd,
+    #[error("{0} trailing bytes")]
+    /// witness or trace is followed by bytes which are not part of it
+    TrailingBytes(usize),
+    #[error("{0} is not a canonical field element")]
+    /// hash is not the little endian encoding of a field element
+    NonCanonical(&'static str),
+    #[error("{0} does not fit into 32 bytes")]
+    /// integer is too large to encode
+    Overflow(&'static str),
+    #[error("{0} does not fit into u32")]
+    /// count or length is too large to encode
+    TooLong(&'static str),
+    #[error("invalid option tag {0}")]
+    /// option is neither absent (0) nor present (1)
+    InvalidTag(u8),
+    #[error("unknown proof type {0}")]
+    /// proof type is not the discriminant of a MPTProofType
+    UnknownProofType(u8),
+    #[error(transparent)]
+    /// decoded trace cannot be converted into a proof
+    Trace(#[from] TraceError),
+}
+
+/// Encodes the traces, with their proof types.
+pub fn encode(witness: &[(MPTProofType, SMTTrace)]) -> Result<Vec<u8>, CodecError> {
+    let mut encoder = Encoder::default();
+    encoder.bytes.extend_from_slice(MAGIC_BYTES);
+    encoder.u8(FORMAT_VERSION);
+    encoder.length("witness", witness.len())?;
+    for (proof_type, trace) in witness {
+        let mut trace_encoder = Encoder::default();
+        trace_encoder.u8(*proof_type as u8);
+        trace_encoder.trace(trace)?;
+        encoder.length("trace", trace_encoder.bytes.len())?;
+        encoder.bytes.extend(trace_encoder.bytes);
+    }
+    Ok(encoder.bytes)
+}
+
+/// Decodes traces encoded by `encode`.
+pub fn decode(bytes: &[u8]) -> Result<Vec<(MPTProofType, SMTTrace)>, CodecError> {
+    let mut decoder = Decoder { bytes };
+    decoder.header(MAGIC_BYTES)?;
+    let n_traces = decoder.u32()?;
+    let witness = (0..n_traces)
+        .map(|_| {
+            let length = decoder.u32()?;
+            let mut trace_decoder = Decoder {
+                bytes: decoder.take(length as usize)?,
+            };
+            let proof_type = trace_decoder.proof_type()?;
+            let trace = trace_decoder.trace()?;
+            trace_decoder.finish()?;
+            Ok((proof_type, trace))
+        })
+        .collect::<Result<_, CodecError>>()?;
+    decoder.finish()?;
+    Ok(witness)
+}
+
+/// Decodes traces encoded by `encode` and converts them into proofs.
+pub fn decode_proofs(bytes: &[u8]) -> Result<Vec<Proof>, CodecError> {
+    decode(bytes)?
+        .iter()
+        .map(|(proof_type, trace)| Ok(Proof::try_from((proof_type, trace))?))
+        .collect()
+}
+
+/// Encodes a single trace without a proof type, such as the traces in src/traces.
+pub fn encode_trace(trace: &SMTTrace) -> Result<Vec<u8>, CodecError> {
+    let mut encoder = Encoder::default();
+    encoder.bytes.extend_from_slice(TRACE_MAGIC_BYTES);
+    encoder.u8(FORMAT_VERSION);
+    encoder.trace(trace)?;
+    Ok(encoder.bytes)
+}
+
+/// Decodes a trace encoded by `encode_trace`.
+pub fn decode_trace(bytes: &[u8]) -> Result<SMTTrace, CodecError> {
+    let mut decoder = Decoder { bytes };
+    decoder.header(TRACE_MAGIC_BYTES)?;
+    let trace = decoder.trace()?;
+    decoder.finish()?;
+    Ok(trace)
+}
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn length(&mut self, name: &'static str, length: usize) -> Result<(), CodecError> {
+        let length = u32::try_from(length).map_err(|_| CodecError::TooLong(name))?;
+        self.bytes.extend_from_slice(&length.to_le_bytes());
+        Ok(())
+    }
+
+    fn field(&mut self, name: &'static str, x: HexBytes<32>) -> Result<(), CodecError> {
+        if Option::<Fr>::from(Fr::from_bytes(&x.0)).is_none() {
+            return Err(CodecError::NonCanonical(name));
+        }
+        self.bytes.extend_from_slice(&x.0);
+        Ok(())
+    }
+
+    fn uint(&mut self, name: &'static str, x: &BigUint) -> Result<(), CodecError> {
+        let bytes = x.to_bytes_be();
+        if bytes.len() > 32 {
+            return Err(CodecError::Overflow(name));
+        }
+        self.bytes.resize(self.bytes.len() + 32 - bytes.len(), 0);
+        self.bytes.extend(bytes);
+        Ok(())
+    }
+
+    fn option<T>(
+        &mut self,
+        x: Option<&T>,
+        mut f: impl FnMut(&mut Self, &T) -> Result<(), CodecError>,
+    ) -> Result<(), CodecError> {
+        match x {
+            None => self.u8(0),
+            Some(x) => {
+                self.u8(1);
+                f(self, x)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, node: &SMTNode) -> Result<(), CodecError> {
+        self.field("node value", node.value)?;
+        self.field("node sibling", node.sibling)?;
+        self.u64(node.node_type);
+        Ok(())
+    }
+
+    fn path(&mut self, path: &SMTPath) -> Result<(), CodecError> {
+        self.field("root", path.root)?;
+        self.option(path.leaf.as_ref(), Self::node)?;
+        self.length("path", path.path.len())?;
+        for node in &path.path {
+            self.node(node)?;
+        }
+        self.uint("path part", &path.path_part)
+    }
+
+    fn account(&mut self, account: &AccountData) -> Result<(), CodecError> {
+        self.u64(account.nonce);
+        self.uint("balance", &account.balance)?;
+        self.uint("code hash", &account.code_hash)?;
+        self.uint("poseidon code hash", &account.poseidon_code_hash)?;
+        self.u64(account.code_size);
+        self.option(account.storage_root.as_ref(), |encoder, root| {
+            encoder.field("storage root", *root)
+        })
+    }
+
+    fn state_data(&mut self, data: &StateData) -> Result<(), CodecError> {
+        self.bytes.extend_from_slice(&data.key.0);
+        self.bytes.extend_from_slice(&data.value.0);
+        Ok(())
+    }
+
+    fn trace(&mut self, trace: &SMTTrace) -> Result<(), CodecError> {
+        self.bytes.extend_from_slice(&trace.address.0);
+        self.field("account key", trace.account_key)?;
+        for path in &trace.account_path {
+            self.path(path)?;
+        }
+        for account in &trace.account_update {
+            self.option(account.as_ref(), Self::account)?;
+        }
+        for path in &trace.state_path {
+            self.option(path.as_ref(), Self::path)?;
+        }
+        self.option(trace.common_state_root.as_ref(), |encoder, root| {
+            encoder.field("common state root", *root)
+        })?;
+        self.option(trace.state_key.as_ref(), |encoder, key| {
+            encoder.field("state key", *key)
+        })?;
+        self.option(trace.state_update.as_ref(), |encoder, update| {
+            for data in update {
+                encoder.option(data.as_ref(), Self::state_data)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], CodecError> {
+        if self.bytes.len() < n {
+            return Err(CodecError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn header(&mut self, magic_bytes: &[u8]) -> Result<(), CodecError> {
+        if self.take(magic_bytes.len()).ok() != Some(magic_bytes) {
+            return Err(CodecError::MissingMagicBytes);
+        }
+        let version = self.u8()?;
+        if version != FORMAT_VERSION {
+            return Err(CodecError::UnsupportedVersion(version));
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), CodecError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            n => Err(CodecError::TrailingBytes(n)),
+        }
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, CodecError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, CodecError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, CodecError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn field(&mut self, name: &'static str) -> Result<HexBytes<32>, CodecError> {
+        let bytes = self.array()?;
+        if Option::<Fr>::from(Fr::from_bytes(&bytes)).is_none() {
+            return Err(CodecError::NonCanonical(name));
+        }
+        Ok(HexBytes(bytes))
+    }
+
+    fn uint(&mut self) -> Result<BigUint, CodecError> {
+        Ok(BigUint::from_bytes_be(self.take(32)?))
+    }
+
+    fn option<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> Result<T, CodecError>,
+    ) -> Result<Option<T>, CodecError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(f(self)?)),
+            tag => Err(CodecError::InvalidTag(tag)),
+        }
+    }
+
+    fn proof_type(&mut self) -> Result<MPTProofType, CodecError> {
+        Ok(match self.u8()? {
+            0 => MPTProofType::NonceChanged,
+            1 => MPTProofType::BalanceChanged,
+            2 => MPTProofType::CodeHashExists,
+            3 => MPTProofType::PoseidonCodeHashExists,
+            4 => MPTProofType::CodeSizeExists,
+            5 => MPTProofType::AccountDoesNotExist,
+            6 => MPTProofType::StorageChanged,
+            7 => MPTProofType::StorageDoesNotExist,
+            8 => MPTProofType::AccountDestructed,
+            9 => MPTProofType::AccountCreated,
+            10 => MPTProofType::NonceRead,
+            11 => MPTProofType::BalanceRead,
+            12 => MPTProofType::StorageRead,
+            13 => MPTProofType::NonceAndFieldChanged,
+            14 => MPTProofType::StorageBatchChanged,
+            15 => MPTProofType::StorageWiped,
+            16 => MPTProofType::AccountIsEmpty,
+            17 => MPTProofType::KeyValueChanged,
+            proof_type => return Err(CodecError::UnknownProofType(proof_type)),
+        })
+    }
+
+    fn node(&mut self) -> Result<SMTNode, CodecError> {
+        Ok(SMTNode {
+            value: self.field("node value")?,
+            sibling: self.field("node sibling")?,
+            node_type: self.u64()?,
+        })
+    }
+
+    fn path(&mut self) -> Result<SMTPath, CodecError> {
+        let root = self.field("root")?;
+        let leaf = self.option(Self::node)?;
+        let path = (0..self.u32()?)
+            .map(|_| self.node())
+            .collect::<Result<_, _>>()?;
+        Ok(SMTPath {
+            root,
+            leaf,
+            path,
+            path_part: self.uint()?,
+        })
+    }
+
+    fn account(&mut self) -> Result<AccountData, CodecError> {
+        Ok(AccountData {
+            nonce: self.u64()?,
+            balance: self.uint()?,
+            code_hash: self.uint()?,
+            poseidon_code_hash: self.uint()?,
+            code_size: self.u64()?,
+            storage_root: self.option(|decoder| decoder.field("storage root"))?,
+        })
+    }
+
+    fn state_data(&mut self) -> Result<StateData, CodecError> {
+        Ok(StateData {
+            key: HexBytes(self.array()?),
+            value: HexBytes(self.array()?),
+        })
+    }
+
+    fn trace(&mut self) -> Result<SMTTrace, CodecError> {
+        Ok(SMTTrace {
+            address: HexBytes(self.array()?),
+            account_key: self.field("account key")?,
+            account_path: [self.path()?, self.path()?],
+            account_update: [self.option(Self::account)?, self.option(Self::account)?],
+            state_path: [self.option(Self::path)?, self.option(Self::path)?],
+            common_state_root: self.option(|decoder| decoder.field("common state root"))?,
+            state_key: self.option(|decoder| decoder.field("state key"))?,
+            state_update: self.option(|decoder| {
+                Ok([
+                    decoder.option(Self::state_data)?,
+                    decoder.option(Self::state_data)?,
+                ])
+            })?,
+        })
+    }
+}
@@ -4,7 +4,10 @@ mod segment;
 mod word_rlc;
 pub use path::PathType;
 use segment::SegmentType;
-use word_rlc::{assign as assign_word_rlc, configure as configure_word_rlc};
+use word_rlc::{
+    assign as assign_word_rlc, assign_limbs, balance_u128s, configure as configure_word_rlc,
+    configure_balance as configure_balance_limbs, configure_balance_rlc,
+};
 
 use super::{
     byte_representation::{BytesLookup, RlcLookup},
@@ -22,12 +25,15 @@ use crate::{
     types::{
         storage::{StorageLeaf, StorageProof},
         trie::{next_domain, TrieRows},
-        ClaimKind, HashDomain, Proof,
+        AccountField, Claim, ClaimKind, FieldUpdate, HashDomain, Proof,
+    },
+    util::{
+        account_key, domain_hash, empty_keccak_code_hash, empty_poseidon_code_hash,
+        lagrange_polynomial, rlc, u256_hi_lo, u256_to_big_endian,
     },
-    util::{account_key, domain_hash, lagrange_polynomial, rlc, u256_hi_lo, u256_to_big_endian},
     MPTProofType,
 };
-use ethers_core::types::Address;
+use ethers_core::types::{Address, U256};
 use halo2_proofs::circuit::Layouter;
 use halo2_proofs::{
     arithmetic::Field,
@@ -43,6 +49,12 @@ lazy_static! {
     static ref ZERO_PAIR_HASH: Fr = domain_hash(Fr::zero(), Fr::zero(), HashDomain::Pair);
     static ref ZERO_STORAGE_ROOT_KECCAK_CODEHASH_HASH: Fr =
         domain_hash(Fr::zero(), *ZERO_PAIR_HASH, HashDomain::AccountFields);
+    static ref ZERO_NONCE_CODE_SIZE_BALANCE_HASH: Fr =
+        domain_hash(Fr::zero(), Fr::zero(), HashDomain::AccountFields);
+    static ref EMPTY_KECCAK_CODE_HASH_HASH: Fr = {
+        let (high, low) = u256_hi_lo(&empty_keccak_code_hash());
+        domain_hash(Fr::from_u128(high), Fr::from_u128(low), HashDomain::Pair)
+    };
 }
 
 pub trait MptUpdateLookup<F: FromUniformBytes<64> + Ord> {
@@ -59,6 +71,8 @@ pub struct MptUpdateConfig {
     new_value: SecondPhaseAdviceColumn,
     proof_type: OneHot<MPTProofType>,
     storage_key_rlc: SecondPhaseAdviceColumn,
+    // Field updated together with the nonce in a NonceAndFieldChanged proof.
+    account_field: OneHot<AccountField>,
 
     segment_type: OneHot<SegmentType>,
     path_type: OneHot<PathType>,
@@ -79,12 +93,39 @@ pub struct MptUpdateConfig {
 
 impl<F: FromUniformBytes<64> + Ord> MptUpdateLookup<F> for MptUpdateConfig {
     fn lookup(&self) -> [Query<F>; 7] {
-        let is_start = || self.segment_type.current_matches(&[SegmentType::Start]);
+        // The start rows of the storage updates in a StorageBatchChanged proof are not exposed.
+        // These are the only start rows followed by a storage root row. The account path is only
+        // proven once for the whole batch, so the state roots between its storage updates are not
+        // known, and the batch is exposed as a single update by the start row before its account
+        // path.
+        let is_start = || {
+            self.segment_type
+                .current_matches(&[SegmentType::Start])
+                .and(!self.segment_type.next_matches(&[SegmentType::AccountLeaf3]))
+        };
         // Note that one non-start rows, all 7 queries will be 0. This corresponds to a valid
         // mpt proof in that in an empty trie, the zero address has nonce = 0.
         let old_root_rlc = self.second_phase_intermediate_values[0].current() * is_start();
         let new_root_rlc = self.second_phase_intermediate_values[1].current() * is_start();
-        let proof_type = self.proof_type.current() * is_start();
+        // A NonceAndFieldChanged proof has two start rows, which are exposed as the update of the
+        // other field and the NonceChanged update it is made of.
+        let is_nonce_and_field = self
+            .proof_type
+            .current_matches(&[MPTProofType::NonceAndFieldChanged]);
+        let is_field_update = is_nonce_and_field
+            .clone()
+            .and(self.segment_type.next_matches(&[SegmentType::Start]));
+        let field_proof_type = AccountField::iter().fold(Query::zero(), |sum, field| {
+            sum + Query::from(self.account_field.current_matches(&[field]))
+                * Query::from(MPTProofType::from(field) as u64 - MPTProofType::NonceChanged as u64)
+        });
+        let proof_type = (self.proof_type.current()
+            - Query::from(is_nonce_and_field)
+                * Query::from(
+                    MPTProofType::NonceAndFieldChanged as u64 - MPTProofType::NonceChanged as u64,
+                )
+            + Query::from(is_field_update) * field_proof_type)
+            * is_start();
         let old_value = self.old_value.current() * is_start();
         let new_value = self.new_value.current() * is_start();
         let [address_high, address_low, ..] = self.intermediate_values;
@@ -116,6 +157,7 @@ impl MptUpdateConfig {
         fr_rlc: &impl FrRlcLookup,
     ) -> Self {
         let proof_type: OneHot<MPTProofType> = OneHot::configure(cs, cb);
+        let account_field = OneHot::configure(cs, cb);
         let [storage_key_rlc, old_value, new_value] = cb.second_phase_advice_columns(cs);
         let [domain, old_hash, new_hash, depth, key, other_key, direction, sibling] =
             cb.advice_columns(cs);
@@ -131,24 +173,33 @@ impl MptUpdateConfig {
         let path_type = OneHot::configure(cs, cb);
 
         let is_start = segment_type.current_matches(&[SegmentType::Start]);
+        // The storage root rows in a StorageBatchChanged proof also have path type Start, because
+        // the account path above them is only proven once for the whole batch.
+        let is_storage_root = segment_type
+            .current_matches(&[SegmentType::AccountLeaf3])
+            .and(segment_type.previous_matches(&[SegmentType::Start]));
         cb.assert_equal(
-            "segment is Start iff path is Start",
-            is_start.clone().into(),
+            "path is Start iff segment is Start or storage root row",
+            Query::from(is_start.clone()) + Query::from(is_storage_root),
             path_type.current_matches(&[PathType::Start]).into(),
         );
         cb.condition(is_start.clone().and(cb.every_row_selector()), |cb| {
             let [address_high, address_low, ..] = intermediate_values;
             let [old_hash_rlc, new_hash_rlc, ..] = second_phase_intermediate_values;
-            cb.poseidon_lookup(
-                "account mpt key = h(address_high, address_low << 96)",
-                [
-                    address_high.current(),
-                    address_low.current() * Query::Constant(F::from_u128(1 << 96)),
-                    Query::from(u64::from(HashDomain::Pair)),
-                    key.current(),
-                ],
-                poseidon,
-            );
+            // Keys of key/value tries are not derived from an address.
+            let is_key_value = proof_type.current_matches(&[MPTProofType::KeyValueChanged]);
+            cb.condition(!is_key_value, |cb| {
+                cb.poseidon_lookup(
+                    "account mpt key = h(address_high, address_low << 96)",
+                    [
+                        address_high.current(),
+                        address_low.current() * Query::Constant(F::from_u128(1 << 96)),
+                        Query::from(u64::from(HashDomain::Pair)),
+                        key.current(),
+                    ],
+                    poseidon,
+                );
+            });
             cb.add_lookup(
                 "address_high is 16 bytes",
                 [address_high.current(), Query::from(15)],
@@ -164,11 +215,26 @@ impl MptUpdateConfig {
                 [old_hash.current(), old_hash_rlc.current()],
                 fr_rlc.lookup(),
             );
-            cb.add_lookup(
-                "rlc_new_root = rlc(new_root)",
-                [new_hash.current(), new_hash_rlc.current()],
-                fr_rlc.lookup(),
-            );
+            // Reads have new_root = old_root, so the lookup for the old root also covers it.
+            let is_read = proof_type.current_matches(&[
+                MPTProofType::NonceRead,
+                MPTProofType::BalanceRead,
+                MPTProofType::StorageRead,
+            ]);
+            cb.condition(is_read.clone(), |cb| {
+                cb.assert_equal(
+                    "rlc_new_root = rlc_old_root for reads",
+                    new_hash_rlc.current(),
+                    old_hash_rlc.current(),
+                );
+            });
+            cb.condition(!is_read, |cb| {
+                cb.add_lookup(
+                    "rlc_new_root = rlc(new_root)",
+                    [new_hash.current(), new_hash_rlc.current()],
+                    fr_rlc.lookup(),
+                );
+            });
         });
         cb.condition(!is_start, |cb| {
             cb.assert_equal(
@@ -248,6 +314,7 @@ impl MptUpdateConfig {
             old_value,
             new_value,
             storage_key_rlc,
+            account_field,
             segment_type,
             path_type,
             other_key,
@@ -303,7 +370,9 @@ impl MptUpdateConfig {
                 configure_segment_transitions(cb, &config.segment_type, proof_type);
                 match proof_type {
                     MPTProofType::NonceChanged => configure_nonce(cb, &config, bytes, poseidon),
-                    MPTProofType::BalanceChanged => configure_balance(cb, &config, poseidon, rlc),
+                    MPTProofType::BalanceChanged => {
+                        configure_balance(cb, &config, poseidon, bytes, rlc, rlc_randomness.query())
+                    }
                     MPTProofType::CodeSizeExists => {
                         configure_code_size(cb, &config, bytes, poseidon)
                     }
@@ -332,7 +401,58 @@ impl MptUpdateConfig {
                         rlc,
                         rlc_randomness.query(),
                     ),
-                    MPTProofType::AccountDestructed => cb.assert_unreachable("unimplemented!"),
+                    MPTProofType::AccountDestructed => configure_self_destruct(cb, &config),
+                    MPTProofType::AccountCreated => {
+                        configure_account_created(cb, &config, bytes, poseidon)
+                    }
+                    MPTProofType::NonceRead => {
+                        configure_nonce(cb, &config, bytes, poseidon);
+                        configure_read(cb, &config);
+                    }
+                    MPTProofType::BalanceRead => {
+                        configure_balance(
+                            cb,
+                            &config,
+                            poseidon,
+                            bytes,
+                            rlc,
+                            rlc_randomness.query(),
+                        );
+                        configure_read(cb, &config);
+                    }
+                    MPTProofType::StorageRead => {
+                        configure_storage(
+                            cb,
+                            &config,
+                            poseidon,
+                            bytes,
+                            rlc,
+                            rlc_randomness.query(),
+                        );
+                        configure_read(cb, &config);
+                    }
+                    MPTProofType::NonceAndFieldChanged => configure_nonce_and_field(
+                        cb,
+                        &config,
+                        bytes,
+                        poseidon,
+                        rlc,
+                        rlc_randomness.query(),
+                    ),
+                    MPTProofType::StorageBatchChanged => configure_storage_batch(
+                        cb,
+                        &config,
+                        poseidon,
+                        bytes,
+                        rlc,
+                        rlc_randomness.query(),
+                    ),
+                    MPTProofType::StorageWiped => configure_storage_wiped(cb, &config),
+                    MPTProofType::AccountIsEmpty => {
+                        configure_account_is_empty(cb, &config);
+                        configure_read(cb, &config);
+                    }
+                    MPTProofType::KeyValueChanged => configure_key_value(cb, &config, poseidon),
                 }
             };
             cb.condition(
@@ -384,20 +504,84 @@ impl MptUpdateConfig {
         randomness: Value<Fr>,
         mut offset: usize,
     ) {
+        let n_storage_batch_rows = match &proof.storage {
+            StorageProof::Batch(updates) => {
+                self.assign_storage_batch(region, offset, proof, updates, randomness)
+            }
+            _ => 0,
+        };
+        offset += n_storage_batch_rows;
+
         let proof_type = MPTProofType::from(proof.claim);
-        let storage_key =
-            randomness.map(|r| rlc(&u256_to_big_endian(&proof.claim.storage_key()), r));
+        let storage_key = match proof.claim.kind {
+            // Key/value proofs expose their key instead of the rlc of a storage key.
+            ClaimKind::KeyValue { key, .. } => Value::known(key),
+            _ => randomness.map(|r| rlc(&u256_to_big_endian(&proof.claim.storage_key()), r)),
+        };
         let old_value = randomness.map(|r| proof.claim.old_value_assignment(r));
         let new_value = randomness.map(|r| proof.claim.new_value_assignment(r));
 
-        for i in 0..proof.n_rows() {
+        // A NonceAndField proof has an extra start row for the update of the other field before the
+        // start row for the nonce update. The values of the other field are also carried along the
+        // remaining rows.
+        let field_values = match proof.claim.kind {
+            ClaimKind::NonceAndField { field, .. } => {
+                let field_claim = Claim {
+                    kind: field.claim_kind(),
+                    ..proof.claim
+                };
+                Some((
+                    randomness.map(|r| field_claim.old_value_assignment(r)),
+                    randomness.map(|r| field_claim.new_value_assignment(r)),
+                ))
+            }
+            _ => None,
+        };
+        let start_rows = match (proof.intermediate_root(), field_values) {
+            (Some(intermediate_root), Some((old_field_value, new_field_value))) => vec![
+                (
+                    intermediate_root,
+                    proof.claim.new_root,
+                    old_field_value,
+                    new_field_value,
+                ),
+                (
+                    proof.claim.old_root,
+                    intermediate_root,
+                    old_value,
+                    new_value,
+                ),
+            ],
+            _ => vec![(
+                proof.claim.old_root,
+                proof.claim.new_root,
+                old_value,
+                new_value,
+            )],
+        };
+
+        for i in 0..proof.n_rows() - n_storage_batch_rows {
+            let (_, _, old_value, new_value) = start_rows[i.min(start_rows.len() - 1)];
             self.proof_type.assign(region, offset + i, proof_type);
             self.storage_key_rlc.assign(region, offset + i, storage_key);
             self.old_value.assign(region, offset + i, old_value);
             self.new_value.assign(region, offset + i, new_value);
         }
+        if let ClaimKind::NonceAndField { field, .. } = proof.claim.kind {
+            for i in 0..proof.n_rows() {
+                self.account_field.assign(region, offset + i, field.field());
+            }
+        }
+        if let Some((old_field_value, new_field_value)) = field_values {
+            let [_, _, old_field_value_column, new_field_value_column, ..] =
+                self.second_phase_intermediate_values;
+            for i in 1..proof.n_rows() {
+                old_field_value_column.assign(region, offset + i, old_field_value);
+                new_field_value_column.assign(region, offset + i, new_field_value);
+            }
+        }
 
-        let key = account_key(proof.claim.address);
+        let key = proof.claim.key();
         let (other_key, other_leaf_data_hash) =
             // checking if type 1 or type 2
             if proof.old.key != key {
@@ -411,48 +595,54 @@ impl MptUpdateConfig {
                 // handle type 0 and type 2 paths here:
                 (proof.old.key, proof.new.leaf_data_hash.unwrap_or_default())
             };
-        // Assign start row
-        self.segment_type.assign(region, offset, SegmentType::Start);
-        self.path_type.assign(region, offset, PathType::Start);
-        self.old_hash.assign(region, offset, proof.claim.old_root);
-        self.new_hash.assign(region, offset, proof.claim.new_root);
-
-        self.key.assign(region, offset, key);
-        self.other_key.assign(region, offset, other_key);
-        self.domain.assign(region, offset, HashDomain::Pair);
-
-        self.intermediate_values[0].assign(
-            region,
-            offset,
-            Fr::from_u128(address_high(proof.claim.address)),
-        );
-        self.intermediate_values[1].assign(
-            region,
-            offset,
-            u64::from(address_low(proof.claim.address)),
-        );
-
         let rlc_fr = |x: Fr| {
             let mut bytes = x.to_bytes();
             bytes.reverse();
             randomness.map(|r| rlc(&bytes, r))
         };
 
-        self.second_phase_intermediate_values[0].assign(
-            region,
-            offset,
-            rlc_fr(proof.claim.old_root),
-        );
-        self.second_phase_intermediate_values[1].assign(
-            region,
-            offset,
-            rlc_fr(proof.claim.new_root),
-        );
+        // Assign start rows
+        for (old_root, new_root, _, _) in start_rows {
+            self.segment_type.assign(region, offset, SegmentType::Start);
+            self.path_type.assign(region, offset, PathType::Start);
+            self.old_hash.assign(region, offset, old_root);
+            self.new_hash.assign(region, offset, new_root);
+
+            self.key.assign(region, offset, key);
+            self.other_key.assign(region, offset, other_key);
+            self.domain.assign(region, offset, HashDomain::Pair);
+
+            self.intermediate_values[0].assign(
+                region,
+                offset,
+                Fr::from_u128(address_high(proof.claim.address)),
+            );
+            self.intermediate_values[1].assign(
+                region,
+                offset,
+                u64::from(address_low(proof.claim.address)),
+            );
+
+            self.second_phase_intermediate_values[0].assign(region, offset, rlc_fr(old_root));
+            self.second_phase_intermediate_values[1].assign(region, offset, rlc_fr(new_root));
 
-        offset += 1;
+            offset += 1;
+        }
 
-        let n_account_trie_rows =
-            self.assign_account_trie_rows(region, offset, &proof.account_trie_rows);
+        let n_account_trie_rows = match proof.intermediate_account_trie_rows() {
+            Some(intermediate_account_trie_rows) => {
+                // The new hashes are the ones after the nonce update, so the hashes after the
+                // update of the other field go into a separate column, as do the siblings.
+                let [.., field_updated_hash, new_sibling, _, _, _, _] = self.intermediate_values;
+                field_updated_hash.assign(region, offset - 1, proof.claim.new_root);
+                for (i, row) in proof.account_trie_rows.0.iter().enumerate() {
+                    field_updated_hash.assign(region, offset + i, row.new);
+                    new_sibling.assign(region, offset + i, row.sibling);
+                }
+                self.assign_account_trie_rows(region, offset, &intermediate_account_trie_rows)
+            }
+            None => self.assign_account_trie_rows(region, offset, &proof.account_trie_rows),
+        };
         for i in 0..n_account_trie_rows {
             self.key.assign(region, offset + i, key);
             self.other_key.assign(region, offset + i, other_key);
@@ -476,7 +666,7 @@ impl MptUpdateConfig {
             Some((_, _, old_hash, new_hash, _, _, _)) => (*old_hash, *new_hash),
         };
 
-        if proof.old_account.is_none() && proof.new_account.is_none() {
+        if !proof.leaf_exists() {
             offset -= 1;
             self.is_zero_gadgets[2].assign_value_and_inverse(region, offset, key - other_key);
             self.is_zero_gadgets[3].assign_value_and_inverse(region, offset, final_old_hash);
@@ -510,17 +700,25 @@ impl MptUpdateConfig {
         };
 
         let directions = match proof_type {
-            MPTProofType::NonceChanged | MPTProofType::CodeSizeExists => {
-                vec![true, false, false, false]
+            MPTProofType::NonceChanged
+            | MPTProofType::NonceRead
+            | MPTProofType::CodeSizeExists
+            | MPTProofType::AccountCreated
+            | MPTProofType::NonceAndFieldChanged => vec![true, false, false, false],
+            MPTProofType::BalanceChanged | MPTProofType::BalanceRead => {
+                vec![true, false, false, true]
             }
-            MPTProofType::BalanceChanged => vec![true, false, false, true],
             MPTProofType::PoseidonCodeHashExists => vec![true, true],
-            MPTProofType::CodeHashExists => vec![true, false, true, true],
-            MPTProofType::StorageChanged | MPTProofType::StorageDoesNotExist => {
-                vec![true, false, true, false]
+            MPTProofType::CodeHashExists | MPTProofType::AccountIsEmpty => {
+                vec![true, false, true, true]
             }
+            MPTProofType::StorageChanged
+            | MPTProofType::StorageRead
+            | MPTProofType::StorageDoesNotExist
+            | MPTProofType::StorageBatchChanged
+            | MPTProofType::StorageWiped => vec![true, false, true, false],
+            MPTProofType::AccountDestructed | MPTProofType::KeyValueChanged => vec![true],
             MPTProofType::AccountDoesNotExist => unreachable!(),
-            MPTProofType::AccountDestructed => unimplemented!(),
         };
         let next_offset = offset + directions.len();
 
@@ -536,7 +734,17 @@ impl MptUpdateConfig {
             izip!(segment_types, siblings, old_hashes, new_hashes, directions).enumerate()
         {
             if i == 0 {
-                self.is_zero_gadgets[3].assign_value_and_inverse(region, offset, old_hash);
+                // For deletions, the nonexistence proof is for the new path instead of the old one.
+                let hash_is_zero_input = if leaf_path_type == PathType::ExtensionOld {
+                    new_hash
+                } else {
+                    old_hash
+                };
+                self.is_zero_gadgets[3].assign_value_and_inverse(
+                    region,
+                    offset,
+                    hash_is_zero_input,
+                );
                 self.domain.assign(region, offset + i, HashDomain::Leaf);
             } else {
                 self.domain
@@ -559,8 +767,9 @@ impl MptUpdateConfig {
                     other_leaf_data_hash_column.assign(region, offset, other_leaf_data_hash);
                 }
                 SegmentType::AccountLeaf3 => {
-                    if let ClaimKind::Storage { key, .. } | ClaimKind::IsEmpty(Some(key)) =
-                        proof.claim.kind
+                    if let ClaimKind::Storage { key, .. }
+                    | ClaimKind::StorageRead { key, .. }
+                    | ClaimKind::IsEmpty(Some(key)) = proof.claim.kind
                     {
                         self.key.assign(region, offset + 3, proof.storage.key());
                         let [storage_key_high, storage_key_low, new_domain, ..] =
@@ -611,6 +820,39 @@ impl MptUpdateConfig {
                 );
             }
         };
+        if let ClaimKind::AccountCreated {
+            nonce,
+            code_hash,
+            code_size,
+            ..
+        } = proof.claim.kind
+        {
+            let [code_hash_high, code_hash_low, code_hash_hash, ..] = self.intermediate_values;
+            let (high, low) = u256_hi_lo(&code_hash);
+            code_hash_high.assign(region, offset + 2, Fr::from_u128(high));
+            code_hash_low.assign(region, offset + 2, Fr::from_u128(low));
+            code_hash_hash.assign(region, offset + 2, proof.new_account_hash_traces[0][2]);
+
+            let [nonce_column, code_size_column, ..] = self.intermediate_values;
+            nonce_column.assign(region, offset + 3, nonce);
+            code_size_column.assign(region, offset + 3, code_size);
+        }
+        if let (Some(field_updated_hashes), Some(new_siblings)) = (
+            proof.nonce_and_field_leaf_hashes(),
+            proof.nonce_and_field_leaf_siblings(),
+        ) {
+            let [.., field_updated_hash, new_sibling, _, _, _, _] = self.intermediate_values;
+            for (i, (hash, sibling)) in field_updated_hashes
+                .into_iter()
+                .zip_eq(new_siblings)
+                .enumerate()
+            {
+                field_updated_hash.assign(region, offset + i, hash);
+                new_sibling.assign(region, offset + i, sibling);
+            }
+        }
+        self.assign_code_hash_update(region, offset + 2, proof, randomness);
+        self.assign_balance_limbs(region, offset + 3, proof, randomness);
         self.assign_storage(region, next_offset, &proof.storage, randomness);
     }
 
@@ -731,6 +973,120 @@ impl MptUpdateConfig {
         rows.len()
     }
 
+    // Assigns the 16 byte limbs of the balances in the AccountLeaf3 row at offset.
+    fn assign_balance_limbs(
+        &self,
+        region: &mut Region<'_, Fr>,
+        offset: usize,
+        proof: &Proof,
+        randomness: Value<Fr>,
+    ) {
+        let [old_high, old_low, new_high, new_low, ..] = self.intermediate_values;
+        match proof.claim.kind {
+            ClaimKind::Balance { .. } | ClaimKind::BalanceRead(_) => {
+                let [rlc_old_high, rlc_old_low, rlc_new_high, rlc_new_low, ..] =
+                    self.second_phase_intermediate_values;
+                for (account, limbs, rlc_limbs) in [
+                    (
+                        proof.old_account,
+                        [old_high, old_low],
+                        [rlc_old_high, rlc_old_low],
+                    ),
+                    (
+                        proof.new_account,
+                        [new_high, new_low],
+                        [rlc_new_high, rlc_new_low],
+                    ),
+                ] {
+                    if let Some(account) = account {
+                        assign_word_rlc(
+                            region,
+                            offset,
+                            account.balance,
+                            limbs,
+                            rlc_limbs,
+                            randomness,
+                        );
+                    }
+                }
+            }
+            ClaimKind::NonceAndField {
+                field:
+                    FieldUpdate::Balance {
+                        old: old_balance,
+                        new: new_balance,
+                    },
+                ..
+            } => {
+                // The first four second phase columns hold the root and balance rlc's.
+                let [_, _, _, _, rlc_old_high, rlc_old_low, rlc_new_high, rlc_new_low, ..] =
+                    self.second_phase_intermediate_values;
+                assign_word_rlc(
+                    region,
+                    offset,
+                    old_balance,
+                    [old_high, old_low],
+                    [rlc_old_high, rlc_old_low],
+                    randomness,
+                );
+                assign_word_rlc(
+                    region,
+                    offset,
+                    new_balance,
+                    [new_high, new_low],
+                    [rlc_new_high, rlc_new_low],
+                    randomness,
+                );
+            }
+            ClaimKind::AccountCreated { balance, .. } => {
+                // The first two columns hold the nonce and code size.
+                assign_limbs(region, offset, balance, [new_high, new_low]);
+            }
+            _ => {}
+        }
+    }
+
+    // Assigns the limbs and hashes of the code hashes in the AccountLeaf2 row at offset, for the
+    // code hash update of a NonceAndField proof.
+    fn assign_code_hash_update(
+        &self,
+        region: &mut Region<'_, Fr>,
+        offset: usize,
+        proof: &Proof,
+        randomness: Value<Fr>,
+    ) {
+        if let ClaimKind::NonceAndField {
+            field: FieldUpdate::CodeHash { old, new },
+            ..
+        } = proof.claim.kind
+        {
+            let [old_high, old_low, new_high, new_low, ..] = self.intermediate_values;
+            let [.., storage_root, old_code_hash_hash, new_code_hash_hash, _] =
+                self.intermediate_values;
+            let [_, _, _, _, rlc_old_high, rlc_old_low, rlc_new_high, rlc_new_low, ..] =
+                self.second_phase_intermediate_values;
+            assign_word_rlc(
+                region,
+                offset,
+                old,
+                [old_high, old_low],
+                [rlc_old_high, rlc_old_low],
+                randomness,
+            );
+            assign_word_rlc(
+                region,
+                offset,
+                new,
+                [new_high, new_low],
+                [rlc_new_high, rlc_new_low],
+                randomness,
+            );
+            storage_root.assign(region, offset, proof.old_account_hash_traces[1][0]);
+            old_code_hash_hash.assign(region, offset, proof.old_account_hash_traces[0][2]);
+            new_code_hash_hash.assign(region, offset, proof.new_account_hash_traces[0][2]);
+        }
+    }
+
     fn assign_storage(
         &self,
         region: &mut Region<'_, Fr>,
@@ -739,7 +1095,8 @@ impl MptUpdateConfig {
         randomness: Value<Fr>,
     ) -> usize {
         match storage {
-            StorageProof::Root(_) => 0,
+            // The updates of a storage batch are assigned before its account path.
+            StorageProof::Root(_) | StorageProof::Batch(_) | StorageProof::Wiped(_) => 0,
             StorageProof::Update {
                 key,
                 trie_rows,
@@ -770,6 +1127,125 @@ impl MptUpdateConfig {
         }
     }
 
+    /// Assigns the rows of the storage updates in a storage batch, which come before its account
+    /// path, and the values carried along all rows of the batch. Returns the number of rows used
+    /// by the storage updates.
+    fn assign_storage_batch(
+        &self,
+        region: &mut Region<'_, Fr>,
+        starting_offset: usize,
+        proof: &Proof,
+        updates: &[StorageProof],
+        randomness: Value<Fr>,
+    ) -> usize {
+        let [.., old_root, new_root, old_storage_root, _, _, _] = self.intermediate_values;
+        let [.., storage_root, account_key_column, _] = self.intermediate_values;
+        let [old_root_rlc, new_root_rlc, ..] = self.second_phase_intermediate_values;
+        let rlc_fr = |x: Fr| {
+            let mut bytes = x.to_bytes();
+            bytes.reverse();
+            randomness.map(|r| rlc(&bytes, r))
+        };
+        let word_rlc = |word: U256| randomness.map(|r| rlc(&u256_to_big_endian(&word), r));
+
+        let key = account_key(proof.claim.address);
+        let old_storage_root_value = proof.storage.old_root();
+
+        let mut offset = starting_offset;
+        for update in updates {
+            let (storage_key, old_value, new_value) = match update {
+                StorageProof::Update {
+                    storage_key,
+                    old_leaf,
+                    new_leaf,
+                    ..
+                } => (*storage_key, old_leaf.value(), new_leaf.value()),
+                _ => unreachable!("storage batch can only contain storage updates"),
+            };
+
+            self.segment_type.assign(region, offset, SegmentType::Start);
+            self.path_type.assign(region, offset, PathType::Start);
+            self.old_hash.assign(region, offset, proof.claim.old_root);
+            self.new_hash.assign(region, offset, proof.claim.new_root);
+            self.key.assign(region, offset, key);
+            self.other_key.assign(region, offset, key);
+            self.domain.assign(region, offset, HashDomain::Pair);
+            self.intermediate_values[0].assign(
+                region,
+                offset,
+                Fr::from_u128(address_high(proof.claim.address)),
+            );
+            self.intermediate_values[1].assign(
+                region,
+                offset,
+                u64::from(address_low(proof.claim.address)),
+            );
+            old_root_rlc.assign(region, offset, rlc_fr(proof.claim.old_root));
+            new_root_rlc.assign(region, offset, rlc_fr(proof.claim.new_root));
+
+            let storage_root_offset = offset + 1;
+            self.segment_type
+                .assign(region, storage_root_offset, SegmentType::AccountLeaf3);
+            self.path_type
+                .assign(region, storage_root_offset, PathType::Start);
+            self.old_hash
+                .assign(region, storage_root_offset, update.old_root());
+            self.new_hash
+                .assign(region, storage_root_offset, update.new_root());
+            self.domain
+                .assign(region, storage_root_offset, HashDomain::AccountFields);
+            self.key.assign(region, storage_root_offset, update.key());
+            self.other_key
+                .assign(region, storage_root_offset, update.other_key());
+            let [storage_key_high, storage_key_low, ..] = self.intermediate_values;
+            let [rlc_storage_key_high, rlc_storage_key_low, ..] =
+                self.second_phase_intermediate_values;
+            assign_word_rlc(
+                region,
+                storage_root_offset,
+                storage_key,
+                [storage_key_high, storage_key_low],
+                [rlc_storage_key_high, rlc_storage_key_low],
+                randomness,
+            );
+
+            let n_rows = 2 + self.assign_storage(region, offset + 2, update, randomness);
+            for j in 0..n_rows {
+                self.proof_type
+                    .assign(region, offset + j, MPTProofType::StorageBatchChanged);
+                self.storage_key_rlc
+                    .assign(region, offset + j, word_rlc(storage_key));
+                self.old_value
+                    .assign(region, offset + j, word_rlc(old_value));
+                self.new_value
+                    .assign(region, offset + j, word_rlc(new_value));
+                storage_root.assign(
+                    region,
+                    offset + j,
+                    if j == 0 {
+                        update.old_root()
+                    } else {
+                        update.new_root()
+                    },
+                );
+            }
+            offset += n_rows;
+        }
+        let n_storage_rows = offset - starting_offset;
+
+        for i in n_storage_rows..proof.n_rows() {
+            storage_root.assign(region, starting_offset + i, proof.storage.new_root());
+        }
+        for i in 0..proof.n_rows() {
+            old_root.assign(region, starting_offset + i, proof.claim.old_root);
+            new_root.assign(region, starting_offset + i, proof.claim.new_root);
+            old_storage_root.assign(region, starting_offset + i, old_storage_root_value);
+            account_key_column.assign(region, starting_offset + i, key);
+        }
+
+        n_storage_rows
+    }
+
     fn assign_empty_storage_proof(
         &self,
         region: &mut Region<'_, Fr>,
@@ -980,6 +1456,16 @@ fn configure_segment_transitions<F: FromUniformBytes<64> + Ord>(
     }
 }
 
+// Reads prove old_hash = new_hash and old_value = new_value in configure_read, so the lookups for
+// the new side of their rows are left out.
+fn is_read<F: FromUniformBytes<64> + Ord>(config: &MptUpdateConfig) -> BinaryQuery<F> {
+    config.proof_type.current_matches(&[
+        MPTProofType::NonceRead,
+        MPTProofType::BalanceRead,
+        MPTProofType::StorageRead,
+    ])
+}
+
 fn configure_common_path<F: FromUniformBytes<64> + Ord>(
     cb: &mut ConstraintBuilder<F>,
     config: &MptUpdateConfig,
@@ -1000,16 +1486,19 @@ fn configure_common_path<F: FromUniformBytes<64> + Ord>(
                 ],
                 poseidon,
             );
-            cb.poseidon_lookup(
-                "poseidon hash correct for new common path",
-                [
-                    new_left(config),
-                    new_right(config),
-                    config.domain.current(),
-                    config.new_hash.previous(),
-                ],
-                poseidon,
-            );
+            // For reads, new_hash = old_hash, so the old path also proves the new one.
+            cb.condition(!is_read(config), |cb| {
+                cb.poseidon_lookup(
+                    "poseidon hash correct for new common path",
+                    [
+                        new_left(config),
+                        new_right(config),
+                        config.domain.current(),
+                        config.new_hash.previous(),
+                    ],
+                    poseidon,
+                );
+            });
         },
     );
     cb.condition(
@@ -1185,15 +1674,30 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
     poseidon: &impl PoseidonLookup,
 ) {
     cb.assert(
-        "can only delete existing storage trie nodes for storage proofs",
+        "can only delete existing storage trie nodes for storage proofs or account leaves for \
+        account destructed proofs",
         config
             .proof_type
-            .current_matches(&[MPTProofType::StorageChanged])
+            .current_matches(&[
+                MPTProofType::StorageChanged,
+                MPTProofType::StorageBatchChanged,
+            ])
             .and(
                 config
                     .segment_type
                     .current_matches(&[SegmentType::StorageTrie, SegmentType::StorageLeaf0]),
-            ),
+            )
+            .or(config
+                .proof_type
+                .current_matches(&[
+                    MPTProofType::AccountDestructed,
+                    MPTProofType::KeyValueChanged,
+                ])
+                .and(
+                    config
+                        .segment_type
+                        .current_matches(&[SegmentType::AccountTrie, SegmentType::AccountLeaf0]),
+                )),
     );
     cb.assert_zero(
         "new value is 0 when deleting node",
@@ -1215,28 +1719,31 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
         poseidon,
     );
     cb.assert(
-        "common -> extension old switch only allowed in storage trie segments",
+        "common -> extension old switch only allowed in trie and leaf segments",
         config
             .path_type
             .previous_matches(&[PathType::ExtensionOld])
-            .or(config
-                .segment_type
-                .current_matches(&[SegmentType::StorageTrie, SegmentType::StorageLeaf0])),
-    );
-    let is_storage_trie_segment = config
-        .segment_type
-        .current_matches(&[SegmentType::StorageTrie]);
-    cb.condition(is_storage_trie_segment, |cb| {
-        let is_final_storage_trie_segment = config
-            .segment_type
-            .next_matches(&[SegmentType::StorageLeaf0]);
-        cb.condition(!is_final_storage_trie_segment.clone(), |cb| {
-            cb.assert_zero(
-                "sibling is zero for non-final old extension path segments",
+            .or(config.segment_type.current_matches(&[
+                SegmentType::AccountTrie,
+                SegmentType::AccountLeaf0,
+                SegmentType::StorageTrie,
+                SegmentType::StorageLeaf0,
+            ])),
+    );
+    let is_trie_segment = config
+        .segment_type
+        .current_matches(&[SegmentType::AccountTrie, SegmentType::StorageTrie]);
+    cb.condition(is_trie_segment, |cb| {
+        let is_final_trie_segment = config
+            .segment_type
+            .next_matches(&[SegmentType::AccountLeaf0, SegmentType::StorageLeaf0]);
+        cb.condition(!is_final_trie_segment.clone(), |cb| {
+            cb.assert_zero(
+                "sibling is zero for non-final old extension path segments",
                 config.sibling.current(),
             );
         });
-        cb.condition(is_final_storage_trie_segment, |cb| {
+        cb.condition(is_final_trie_segment, |cb| {
             cb.assert_equal(
                 "sibling is new leaf hash for final new extension path segments",
                 config.sibling.current(),
@@ -1247,7 +1754,7 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
     cb.condition(
         config
             .segment_type
-            .current_matches(&[SegmentType::StorageLeaf0]),
+            .current_matches(&[SegmentType::AccountLeaf0, SegmentType::StorageLeaf0]),
         |cb| {
             let [.., key_equals_other_key, new_hash_is_zero] = config.is_zero_gadgets;
             let [.., other_leaf_data_hash] = config.intermediate_values;
@@ -1272,22 +1779,39 @@ fn configure_extension_new<F: FromUniformBytes<64> + Ord>(
     poseidon: &impl PoseidonLookup,
 ) {
     cb.assert(
-        "can only add new nodes for nonce, balance and storage proofs",
+        "can only add new nodes for nonce, balance, account created and storage proofs",
         config.proof_type.current_matches(&[
             MPTProofType::NonceChanged,
             MPTProofType::BalanceChanged,
             MPTProofType::StorageChanged,
+            MPTProofType::AccountCreated,
+            MPTProofType::StorageBatchChanged,
+            MPTProofType::KeyValueChanged,
         ]),
     );
     cb.assert_zero(
         "old value is 0 if old account is empty",
         config.old_value.current(),
     );
-    cb.assert_equal(
-        "old_hash unchanged for path_type=New",
-        config.old_hash.current(),
-        config.old_hash.previous(),
-    );
+    // When a storage update inserts the account leaf, the old hash of the account leaf rows is not
+    // the old storage root, which is empty because the account did not exist before.
+    let is_new_account_storage_root = config
+        .segment_type
+        .previous_matches(&[SegmentType::AccountLeaf3])
+        .and(config.path_type.previous_matches(&[PathType::ExtensionNew]));
+    cb.condition(is_new_account_storage_root.clone(), |cb| {
+        cb.assert_zero(
+            "old storage root is empty for new account",
+            config.old_hash.current(),
+        );
+    });
+    cb.condition(!is_new_account_storage_root, |cb| {
+        cb.assert_equal(
+            "old_hash unchanged for path_type=New",
+            config.old_hash.current(),
+            config.old_hash.previous(),
+        );
+    });
     cb.poseidon_lookup(
         "poseidon hash correct for new extension path",
         [
@@ -1428,11 +1952,13 @@ fn configure_nonce<F: FromUniformBytes<64> + Ord>(
 
                 let new_code_size = (config.new_hash.current() - config.new_value.current())
                     * Query::Constant(F::from(1 << 32).square().invert().unwrap());
-                cb.add_lookup(
-                    "new nonce is 8 bytes",
-                    [config.new_value.current(), Query::from(7)],
-                    bytes.lookup(),
-                );
+                cb.condition(!is_read(config), |cb| {
+                    cb.add_lookup(
+                        "new nonce is 8 bytes",
+                        [config.new_value.current(), Query::from(7)],
+                        bytes.lookup(),
+                    );
+                });
                 cb.condition(
                     config.path_type.current_matches(&[PathType::Common]),
                     |cb| {
@@ -1571,7 +2097,9 @@ fn configure_balance<F: FromUniformBytes<64> + Ord>(
     cb: &mut ConstraintBuilder<F>,
     config: &MptUpdateConfig,
     poseidon: &impl PoseidonLookup,
+    bytes: &impl BytesLookup,
     rlc: &impl RlcLookup,
+    randomness: Query<F>,
 ) {
     for variant in SegmentType::iter() {
         let conditional_constraints = |cb: &mut ConstraintBuilder<F>| match variant {
@@ -1645,29 +2173,33 @@ fn configure_balance<F: FromUniformBytes<64> + Ord>(
             }
             SegmentType::AccountLeaf3 => {
                 cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+
+                let [old_high, old_low, new_high, new_low, ..] = config.intermediate_values;
+                let [rlc_old_high, rlc_old_low, rlc_new_high, rlc_new_low, ..] =
+                    config.second_phase_intermediate_values;
                 cb.condition(
                     config.path_type.current_matches(&[PathType::Common]),
                     |cb| {
-                        cb.add_lookup(
-                            "old balance is rlc(old_hash) and fits into 31 bytes",
-                            [
-                                config.old_hash.current(),
-                                Query::from(30),
-                                config.old_value.current(),
-                            ],
-                            rlc.lookup(),
+                        configure_balance_rlc(
+                            cb,
+                            [config.old_hash, old_high, old_low],
+                            [config.old_value, rlc_old_high, rlc_old_low],
+                            bytes,
+                            rlc,
+                            randomness.clone(),
                         );
                     },
                 );
-                cb.add_lookup(
-                    "new balance is rlc(new_hash) and fits into 31 bytes",
-                    [
-                        config.new_hash.current(),
-                        Query::from(30),
-                        config.new_value.current(),
-                    ],
-                    rlc.lookup(),
-                );
+                cb.condition(!is_read(config), |cb| {
+                    configure_balance_rlc(
+                        cb,
+                        [config.new_hash, new_high, new_low],
+                        [config.new_value, rlc_new_high, rlc_new_low],
+                        bytes,
+                        rlc,
+                        randomness.clone(),
+                    );
+                });
                 cb.condition(
                     config.path_type.current_matches(&[PathType::ExtensionNew]),
                     |cb| {
@@ -1825,23 +2357,64 @@ fn configure_storage<F: FromUniformBytes<64> + Ord>(
             }
             SegmentType::AccountLeaf1 => {
                 cb.assert_zero("direction is 0", config.direction.current());
+                cb.condition(
+                    config.path_type.current_matches(&[PathType::ExtensionNew]),
+                    |cb| {
+                        cb.assert_zero(
+                            "poseidon code hash is 0 for storage extension new at AccountLeaf1",
+                            config.sibling.current(),
+                        )
+                    },
+                );
             }
             SegmentType::AccountLeaf2 => {
                 cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+                cb.condition(
+                    config.path_type.current_matches(&[PathType::ExtensionNew]),
+                    |cb| {
+                        cb.assert_equal(
+                            "sibling is hash(0, 0) for storage extension new at AccountLeaf2",
+                            config.sibling.current(),
+                            Query::from(*ZERO_NONCE_CODE_SIZE_BALANCE_HASH),
+                        )
+                    },
+                );
             }
             SegmentType::AccountLeaf3 => {
                 cb.assert_zero("direction is 0", config.direction.current());
-                let [key_high, key_low, ..] = config.intermediate_values;
-                let [rlc_key_high, rlc_key_low, ..] = config.second_phase_intermediate_values;
-                configure_word_rlc(
-                    cb,
-                    [config.key, key_high, key_low],
-                    [config.storage_key_rlc, rlc_key_high, rlc_key_low],
-                    poseidon,
-                    bytes,
-                    rlc,
-                    randomness.clone(),
+                cb.condition(
+                    config.path_type.current_matches(&[PathType::ExtensionNew]),
+                    |cb| {
+                        cb.assert_equal(
+                            "sibling is hash(0, 0) for storage extension new at AccountLeaf3",
+                            config.sibling.current(),
+                            Query::from(*ZERO_PAIR_HASH),
+                        )
+                    },
                 );
+                // The account leaf of a storage batch is not for any one of its storage keys,
+                // which are checked on its storage root rows instead.
+                let is_storage_batch_account_leaf = config
+                    .proof_type
+                    .current_matches(&[MPTProofType::StorageBatchChanged])
+                    .and(
+                        config
+                            .segment_type
+                            .previous_matches(&[SegmentType::AccountLeaf2]),
+                    );
+                cb.condition(!is_storage_batch_account_leaf, |cb| {
+                    let [key_high, key_low, ..] = config.intermediate_values;
+                    let [rlc_key_high, rlc_key_low, ..] = config.second_phase_intermediate_values;
+                    configure_word_rlc(
+                        cb,
+                        [config.key, key_high, key_low],
+                        [config.storage_key_rlc, rlc_key_high, rlc_key_low],
+                        poseidon,
+                        bytes,
+                        rlc,
+                        randomness.clone(),
+                    );
+                });
             }
             SegmentType::StorageLeaf0 => {
                 cb.assert_equal("direction is 1", config.direction.current(), Query::one());
@@ -1861,15 +2434,17 @@ fn configure_storage<F: FromUniformBytes<64> + Ord>(
                             rlc,
                             randomness.clone(),
                         );
-                        configure_word_rlc(
-                            cb,
-                            [config.new_hash, new_high, new_low],
-                            [config.new_value, rlc_new_high, rlc_new_low],
-                            poseidon,
-                            bytes,
-                            rlc,
-                            randomness.clone(),
-                        );
+                        cb.condition(!is_read(config), |cb| {
+                            configure_word_rlc(
+                                cb,
+                                [config.new_hash, new_high, new_low],
+                                [config.new_value, rlc_new_high, rlc_new_low],
+                                poseidon,
+                                bytes,
+                                rlc,
+                                randomness.clone(),
+                            );
+                        });
                     },
                 );
 
@@ -2035,63 +2610,889 @@ fn configure_empty_account<F: FromUniformBytes<64> + Ord>(
     }
 }
 
-fn address_high(a: Address) -> u128 {
-    let high_bytes: [u8; 16] = a.0[..16].try_into().unwrap();
-    u128::from_be_bytes(high_bytes)
+fn configure_self_destruct<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+) {
+    cb.assert_zero("old value is 0", config.old_value.current());
+    cb.assert_zero("new value is 0", config.new_value.current());
+    cb.condition(
+        config
+            .segment_type
+            .current_matches(&[SegmentType::AccountLeaf0]),
+        |cb| {
+            cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+            cb.assert_equal(
+                "sibling is account key for destructed account",
+                config.sibling.current(),
+                config.key.current(),
+            );
+            cb.assert(
+                "account leaf is deleted for destructed account",
+                config.path_type.current_matches(&[PathType::ExtensionOld]),
+            );
+        },
+    );
 }
 
-fn address_low(a: Address) -> u32 {
-    let low_bytes: [u8; 4] = a.0[16..].try_into().unwrap();
-    u32::from_be_bytes(low_bytes)
+fn configure_read<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+) {
+    cb.assert(
+        "only common paths for reads",
+        config
+            .path_type
+            .current_matches(&[PathType::Start, PathType::Common]),
+    );
+    cb.assert_equal(
+        "old hash = new hash for reads",
+        config.old_hash.current(),
+        config.new_hash.current(),
+    );
+    cb.assert_equal(
+        "old value = new value for reads",
+        config.old_value.current(),
+        config.new_value.current(),
+    );
 }
 
-// ... the return traces: ([inp;2], domain, hash)
-pub fn hash_traces(proofs: &[Proof]) -> Vec<([Fr; 2], Fr, Fr)> {
-    let mut hash_traces = vec![(
-        [Fr::zero(), Fr::zero()],
-        HashDomain::Pair.into(),
-        *ZERO_PAIR_HASH,
-    )];
-    for proof in proofs.iter() {
-        for (left, right, domain, hash) in proof.account_trie_rows.poseidon_lookups() {
-            hash_traces.push(([left, right], Fr::from(domain), hash));
-        }
+fn configure_account_created<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+    bytes: &impl BytesLookup,
+    poseidon: &impl PoseidonLookup,
+) {
+    cb.assert_zero("old value is 0", config.old_value.current());
+    for variant in SegmentType::iter() {
+        let conditional_constraints = |cb: &mut ConstraintBuilder<F>| match variant {
+            SegmentType::AccountLeaf0 => {
+                cb.assert(
+                    "account leaf is added for created account",
+                    config.path_type.current_matches(&[PathType::ExtensionNew]),
+                );
+                cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+                cb.assert_equal(
+                    "sibling is account key for created account",
+                    config.sibling.current(),
+                    config.key.current(),
+                );
+                cb.assert_equal(
+                    "new value is new account hash",
+                    config.new_value.current(),
+                    config.new_hash.current(),
+                );
+            }
+            SegmentType::AccountLeaf1 => {
+                cb.assert_zero("direction is 0", config.direction.current());
+            }
+            SegmentType::AccountLeaf2 => {
+                cb.assert_zero("direction is 0", config.direction.current());
 
-        hash_traces.extend(
-            proof
-                .storage
-                .poseidon_lookups()
-                .into_iter()
-                .map(|(left, right, domain, h)| ([left, right], Fr::from(domain), h)),
+                let [code_hash_high, code_hash_low, code_hash_hash, ..] =
+                    config.intermediate_values;
+                cb.add_lookup(
+                    "code hash high is 16 bytes",
+                    [code_hash_high.current(), Query::from(15)],
+                    bytes.lookup(),
+                );
+                cb.add_lookup(
+                    "code hash low is 16 bytes",
+                    [code_hash_low.current(), Query::from(15)],
+                    bytes.lookup(),
+                );
+                cb.poseidon_lookup(
+                    "code_hash_hash = h(code_hash_high, code_hash_low)",
+                    [
+                        code_hash_high.current(),
+                        code_hash_low.current(),
+                        Query::from(u64::from(HashDomain::Pair)),
+                        code_hash_hash.current(),
+                    ],
+                    poseidon,
+                );
+                cb.poseidon_lookup(
+                    "sibling = h(0, code_hash_hash) for empty storage root",
+                    [
+                        Query::zero(),
+                        code_hash_hash.current(),
+                        Query::from(u64::from(HashDomain::AccountFields)),
+                        config.sibling.current(),
+                    ],
+                    poseidon,
+                );
+            }
+            SegmentType::AccountLeaf3 => {
+                cb.assert_zero("direction is 0", config.direction.current());
+
+                let [nonce, code_size, ..] = config.intermediate_values;
+                cb.assert_equal(
+                    "new_hash = nonce + code_size * 2^64",
+                    config.new_hash.current(),
+                    nonce.current()
+                        + code_size.current() * Query::Constant(F::from(1 << 32).square()),
+                );
+                cb.add_lookup(
+                    "nonce is 8 bytes",
+                    [nonce.current(), Query::from(7)],
+                    bytes.lookup(),
+                );
+                cb.add_lookup(
+                    "code size is 8 bytes",
+                    [code_size.current(), Query::from(7)],
+                    bytes.lookup(),
+                );
+                let [_, _, balance_high, balance_low, ..] = config.intermediate_values;
+                configure_balance_limbs(cb, [config.sibling, balance_high, balance_low], bytes);
+            }
+            _ => {}
+        };
+        cb.condition(
+            config.segment_type.current_matches(&[variant]),
+            conditional_constraints,
         );
+    }
+}
 
-        let key = account_key(proof.claim.address);
-        hash_traces.push((
-            [
-                Fr::from_u128(address_high(proof.claim.address)),
-                Fr::from_u128(u128::from(address_low(proof.claim.address)) << 96),
-            ],
-            HashDomain::Pair.into(),
-            key,
-        ));
+fn configure_nonce_and_field<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+    bytes: &impl BytesLookup,
+    poseidon: &impl PoseidonLookup,
+    rlc: &impl RlcLookup,
+    randomness: Query<F>,
+) {
+    // The old and new hashes are for the nonce update, and the hashes after the update of the
+    // other field are in field_updated_hash. The two updates share the siblings on the account
+    // path, except at the row of the other field, so the siblings after the update of the other
+    // field are in new_sibling. The code size is in the same field element as the nonce, so it is
+    // updated in field_updated_hash at AccountLeaf3 instead.
+    let [address_high, address_low, _, _, field_updated_hash, new_sibling, ..] =
+        config.intermediate_values;
+    let [_, _, old_field_value, new_field_value, ..] = config.second_phase_intermediate_values;
+    let is_field = |field| config.account_field.current_matches(&[field]);
 
-        if let Some(data_hash) = proof.old.leaf_data_hash {
-            hash_traces.push((
-                [proof.old.key, data_hash],
-                HashDomain::Leaf.into(),
-                domain_hash(proof.old.key, data_hash, HashDomain::Leaf),
-            ));
-        }
-        if let Some(data_hash) = proof.new.leaf_data_hash {
-            hash_traces.push((
-                [proof.new.key, data_hash],
-                HashDomain::Leaf.into(),
-                domain_hash(proof.new.key, data_hash, HashDomain::Leaf),
-            ));
-        }
+    cb.assert(
+        "nonce and other field can only be updated together for existing accounts",
+        config
+            .path_type
+            .current_matches(&[PathType::Start, PathType::Common]),
+    );
+
+    let is_start = config.segment_type.current_matches(&[SegmentType::Start]);
+    cb.condition(is_start.clone(), |cb| {
+        let is_nonce_update = config
+            .segment_type
+            .previous_matches(&[SegmentType::Start])
+            .and(
+                config
+                    .proof_type
+                    .previous_matches(&[MPTProofType::NonceAndFieldChanged]),
+            );
+        cb.condition(!is_nonce_update.clone(), |cb| {
+            cb.assert(
+                "start row for other field update is followed by start row for nonce update",
+                config.segment_type.next_matches(&[SegmentType::Start]).and(
+                    config
+                        .proof_type
+                        .next_matches(&[MPTProofType::NonceAndFieldChanged]),
+                ),
+            );
+            cb.assert_equal(
+                "address_high is the same for nonce and other field updates",
+                address_high.current(),
+                address_high.next(),
+            );
+            cb.assert_equal(
+                "address_low is the same for nonce and other field updates",
+                address_low.current(),
+                address_low.next(),
+            );
+            cb.assert_equal(
+                "old root for other field update is new root for nonce update",
+                config.old_hash.current(),
+                config.new_hash.next(),
+            );
+        });
+        cb.condition(is_nonce_update, |cb| {
+            cb.assert(
+                "start row for nonce update is followed by account path",
+                !config.segment_type.next_matches(&[SegmentType::Start]),
+            );
+            cb.assert_equal(
+                "field_updated_hash is new root for other field update",
+                field_updated_hash.current(),
+                config.new_hash.previous(),
+            );
+            cb.assert_equal(
+                "account field is the same for both start rows",
+                config.account_field.current(),
+                config.account_field.previous(),
+            );
+            cb.assert_equal(
+                "old_field_value is old value for other field update",
+                old_field_value.current(),
+                config.old_value.previous(),
+            );
+            cb.assert_equal(
+                "new_field_value is new value for other field update",
+                new_field_value.current(),
+                config.new_value.previous(),
+            );
+        });
+    });
+    cb.condition(!is_start, |cb| {
+        cb.assert_equal(
+            "account field does not change",
+            config.account_field.current(),
+            config.account_field.previous(),
+        );
+        cb.assert_equal(
+            "old_field_value does not change",
+            old_field_value.current(),
+            old_field_value.previous(),
+        );
+        cb.assert_equal(
+            "new_field_value does not change",
+            new_field_value.current(),
+            new_field_value.previous(),
+        );
+    });
+
+    for variant in SegmentType::iter() {
+        let conditional_constraints = |cb: &mut ConstraintBuilder<F>| match variant {
+            SegmentType::AccountTrie
+            | SegmentType::AccountLeaf0
+            | SegmentType::AccountLeaf1
+            | SegmentType::AccountLeaf2 => {
+                // The sibling at the row of the other field changes, all others are shared.
+                let changed_sibling_field = match variant {
+                    SegmentType::AccountLeaf0 => {
+                        cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+                        None
+                    }
+                    SegmentType::AccountLeaf1 => {
+                        cb.assert_zero("direction is 0", config.direction.current());
+                        cb.condition(is_field(AccountField::PoseidonCodeHash), |cb| {
+                            cb.assert_equal(
+                                "old poseidon code hash is old value for other field update",
+                                config.sibling.current(),
+                                old_field_value.current(),
+                            );
+                            cb.assert_equal(
+                                "new poseidon code hash is new value for other field update",
+                                new_sibling.current(),
+                                new_field_value.current(),
+                            );
+                        });
+                        Some(AccountField::PoseidonCodeHash)
+                    }
+                    SegmentType::AccountLeaf2 => {
+                        cb.assert_zero("direction is 0", config.direction.current());
+                        cb.condition(is_field(AccountField::CodeHash), |cb| {
+                            configure_code_hash_update(
+                                cb,
+                                config,
+                                poseidon,
+                                bytes,
+                                rlc,
+                                randomness.clone(),
+                            );
+                        });
+                        Some(AccountField::CodeHash)
+                    }
+                    _ => None,
+                };
+                let is_sibling_shared =
+                    changed_sibling_field.map_or_else(BinaryQuery::one, |field| !is_field(field));
+                cb.condition(is_sibling_shared, |cb| {
+                    cb.assert_equal(
+                        "sibling does not change in other field update",
+                        new_sibling.current(),
+                        config.sibling.current(),
+                    );
+                });
+                cb.poseidon_lookup(
+                    "poseidon hash correct for path after other field update",
+                    [
+                        config.direction.current() * new_sibling.current()
+                            + (Query::one() - config.direction.current())
+                                * field_updated_hash.current(),
+                        config.direction.current() * field_updated_hash.current()
+                            + (Query::one() - config.direction.current()) * new_sibling.current(),
+                        config.domain.current(),
+                        field_updated_hash.previous(),
+                    ],
+                    poseidon,
+                );
+            }
+            SegmentType::AccountLeaf3 => {
+                cb.assert_zero("direction is 0", config.direction.current());
+
+                cb.add_lookup(
+                    "old nonce is 8 bytes",
+                    [config.old_value.current(), Query::from(7)],
+                    bytes.lookup(),
+                );
+                cb.add_lookup(
+                    "new nonce is 8 bytes",
+                    [config.new_value.current(), Query::from(7)],
+                    bytes.lookup(),
+                );
+                let old_code_size = (config.old_hash.current() - config.old_value.current())
+                    * Query::Constant(F::from(1 << 32).square().invert().unwrap());
+                let new_code_size = (config.new_hash.current() - config.new_value.current())
+                    * Query::Constant(F::from(1 << 32).square().invert().unwrap());
+                cb.assert_equal(
+                    "old_code_size = new_code_size for nonce update",
+                    old_code_size.clone(),
+                    new_code_size,
+                );
+                cb.add_lookup(
+                    "existing code size is 8 bytes",
+                    [old_code_size.clone(), Query::from(7)],
+                    bytes.lookup(),
+                );
+
+                cb.condition(is_field(AccountField::CodeSize), |cb| {
+                    cb.assert_equal(
+                        "old code size is old value for other field update",
+                        old_code_size,
+                        old_field_value.current(),
+                    );
+                    cb.add_lookup(
+                        "new code size is 8 bytes",
+                        [new_field_value.current(), Query::from(7)],
+                        bytes.lookup(),
+                    );
+                    cb.assert_equal(
+                        "field_updated_hash = new nonce + new code size * 2^64",
+                        field_updated_hash.current(),
+                        config.new_value.current()
+                            + new_field_value.current()
+                                * Query::Constant(F::from(1 << 32).square()),
+                    );
+                });
+                cb.condition(!is_field(AccountField::CodeSize), |cb| {
+                    cb.assert_equal(
+                        "nonce and code size do not change in other field update",
+                        field_updated_hash.current(),
+                        config.new_hash.current(),
+                    );
+                });
+                cb.condition(!is_field(AccountField::Balance), |cb| {
+                    cb.assert_equal(
+                        "balance does not change in other field update",
+                        new_sibling.current(),
+                        config.sibling.current(),
+                    );
+                });
+                cb.poseidon_lookup(
+                    "poseidon hash correct for path after other field update",
+                    [
+                        field_updated_hash.current(),
+                        new_sibling.current(),
+                        config.domain.current(),
+                        field_updated_hash.previous(),
+                    ],
+                    poseidon,
+                );
+                cb.condition(is_field(AccountField::Balance), |cb| {
+                    let [old_high, old_low, new_high, new_low, ..] = config.intermediate_values;
+                    let [_, _, _, _, rlc_old_high, rlc_old_low, rlc_new_high, rlc_new_low, ..] =
+                        config.second_phase_intermediate_values;
+                    configure_balance_rlc(
+                        cb,
+                        [config.sibling, old_high, old_low],
+                        [old_field_value, rlc_old_high, rlc_old_low],
+                        bytes,
+                        rlc,
+                        randomness.clone(),
+                    );
+                    configure_balance_rlc(
+                        cb,
+                        [new_sibling, new_high, new_low],
+                        [new_field_value, rlc_new_high, rlc_new_low],
+                        bytes,
+                        rlc,
+                        randomness.clone(),
+                    );
+                });
+            }
+            _ => {}
+        };
+        cb.condition(
+            config.segment_type.current_matches(&[variant]),
+            conditional_constraints,
+        );
+    }
+}
+
+// At the AccountLeaf2 row of a NonceAndField proof, where the sibling is h(storage root, code hash
+// hash), proves that the sibling and new sibling are for the old and new code hashes.
+fn configure_code_hash_update<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+    poseidon: &impl PoseidonLookup,
+    bytes: &impl BytesLookup,
+    rlc: &impl RlcLookup,
+    randomness: Query<F>,
+) {
+    let [old_high, old_low, new_high, new_low, _, new_sibling, ..] = config.intermediate_values;
+    let [.., storage_root, old_code_hash_hash, new_code_hash_hash, _] = config.intermediate_values;
+    let [_, _, old_field_value, new_field_value, ..] = config.second_phase_intermediate_values;
+    let [_, _, _, _, rlc_old_high, rlc_old_low, rlc_new_high, rlc_new_low, ..] =
+        config.second_phase_intermediate_values;
+    configure_word_rlc(
+        cb,
+        [old_code_hash_hash, old_high, old_low],
+        [old_field_value, rlc_old_high, rlc_old_low],
+        poseidon,
+        bytes,
+        rlc,
+        randomness.clone(),
+    );
+    configure_word_rlc(
+        cb,
+        [new_code_hash_hash, new_high, new_low],
+        [new_field_value, rlc_new_high, rlc_new_low],
+        poseidon,
+        bytes,
+        rlc,
+        randomness,
+    );
+    cb.poseidon_lookup(
+        "sibling = h(storage_root, old code hash hash)",
+        [
+            storage_root.current(),
+            old_code_hash_hash.current(),
+            Query::from(u64::from(HashDomain::AccountFields)),
+            config.sibling.current(),
+        ],
+        poseidon,
+    );
+    cb.poseidon_lookup(
+        "new_sibling = h(storage_root, new code hash hash)",
+        [
+            storage_root.current(),
+            new_code_hash_hash.current(),
+            Query::from(u64::from(HashDomain::AccountFields)),
+            new_sibling.current(),
+        ],
+        poseidon,
+    );
+}
+
+fn configure_account_is_empty<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+) {
+    // The account leaf is opened down to the keccak code hash, so that the poseidon code hash and
+    // the nonce, code size, and balance are siblings along the way. The code hashes of an account
+    // without code are either 0, if it was created by setting its nonce or balance, or the hashes
+    // of empty code.
+    cb.assert_zero("value is 0 for empty account", config.old_value.current());
+    for variant in SegmentType::iter() {
+        let conditional_constraints = |cb: &mut ConstraintBuilder<F>| match variant {
+            SegmentType::AccountLeaf0 => {
+                cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+            }
+            SegmentType::AccountLeaf1 => {
+                cb.assert_zero("direction is 0", config.direction.current());
+                cb.assert_zero(
+                    "poseidon code hash is 0 or hash of empty code",
+                    config.sibling.current()
+                        * (config.sibling.current() - empty_poseidon_code_hash()),
+                );
+            }
+            SegmentType::AccountLeaf2 => {
+                cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+                cb.assert_equal(
+                    "nonce, code size, and balance are 0",
+                    config.sibling.current(),
+                    Query::from(*ZERO_NONCE_CODE_SIZE_BALANCE_HASH),
+                );
+            }
+            SegmentType::AccountLeaf3 => {
+                cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+                cb.assert_zero(
+                    "keccak code hash is 0 or hash of empty code",
+                    (config.old_hash.current() - *ZERO_PAIR_HASH)
+                        * (config.old_hash.current() - *EMPTY_KECCAK_CODE_HASH_HASH),
+                );
+            }
+            _ => {}
+        };
+        cb.condition(
+            config.segment_type.current_matches(&[variant]),
+            conditional_constraints,
+        );
+    }
+}
+
+fn configure_storage_wiped<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+) {
+    cb.assert(
+        "storage can only be wiped for existing accounts",
+        config
+            .path_type
+            .current_matches(&[PathType::Start, PathType::Common]),
+    );
+    cb.assert_zero(
+        "old value is 0 for storage wipe",
+        config.old_value.current(),
+    );
+    cb.assert_zero(
+        "new value is 0 for storage wipe",
+        config.new_value.current(),
+    );
+    for variant in SegmentType::iter() {
+        let conditional_constraints = |cb: &mut ConstraintBuilder<F>| match variant {
+            SegmentType::AccountLeaf0 | SegmentType::AccountLeaf2 => {
+                cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+            }
+            SegmentType::AccountLeaf1 => {
+                cb.assert_zero("direction is 0", config.direction.current());
+            }
+            SegmentType::AccountLeaf3 => {
+                cb.assert_zero("direction is 0", config.direction.current());
+                cb.assert_zero("new storage root is empty", config.new_hash.current());
+            }
+            _ => {}
+        };
+        cb.condition(
+            config.segment_type.current_matches(&[variant]),
+            conditional_constraints,
+        );
+    }
+}
+
+fn configure_key_value<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+    poseidon: &impl PoseidonLookup,
+) {
+    for variant in SegmentType::iter() {
+        let conditional_constraints = |cb: &mut ConstraintBuilder<F>| match variant {
+            SegmentType::Start | SegmentType::AccountTrie => {
+                if variant == SegmentType::Start {
+                    let [address_high, address_low, ..] = config.intermediate_values;
+                    cb.assert_zero("address_high is 0 for key/value", address_high.current());
+                    cb.assert_zero("address_low is 0 for key/value", address_low.current());
+                    cb.assert_equal(
+                        "storage_key_rlc is key for key/value",
+                        config.storage_key_rlc.current(),
+                        config.key.current(),
+                    );
+                }
+                let is_final_segment = config.segment_type.next_matches(&[SegmentType::Start]);
+                cb.condition(is_final_segment, |cb| {
+                    let [.., key_equals_other_key, hash_is_zero] = config.is_zero_gadgets;
+                    let [_, _, _, other_leaf_data_hash, ..] = config.intermediate_values;
+                    cb.assert_equal(
+                        "new_hash = old_hash if key does not exist",
+                        config.old_hash.current(),
+                        config.new_hash.current(),
+                    );
+                    cb.assert_equal(
+                        "old value = new value if key does not exist",
+                        config.old_value.current(),
+                        config.new_value.current(),
+                    );
+                    nonexistence_proof::configure(
+                        cb,
+                        config.old_value,
+                        config.key,
+                        config.other_key,
+                        key_equals_other_key,
+                        config.new_hash,
+                        hash_is_zero,
+                        other_leaf_data_hash,
+                        poseidon,
+                    );
+                });
+            }
+            SegmentType::AccountLeaf0 => {
+                cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+                cb.assert_equal(
+                    "sibling is key for key/value leaf",
+                    config.sibling.current(),
+                    config.key.current(),
+                );
+                cb.condition(
+                    !config.path_type.current_matches(&[PathType::ExtensionNew]),
+                    |cb| {
+                        cb.assert_equal(
+                            "old_hash is old value hash",
+                            config.old_hash.current(),
+                            config.old_value.current(),
+                        );
+                    },
+                );
+                cb.condition(
+                    !config.path_type.current_matches(&[PathType::ExtensionOld]),
+                    |cb| {
+                        cb.assert_equal(
+                            "new_hash is new value hash",
+                            config.new_hash.current(),
+                            config.new_value.current(),
+                        );
+                    },
+                );
+            }
+            _ => {}
+        };
+        cb.condition(
+            config.segment_type.current_matches(&[variant]),
+            conditional_constraints,
+        );
+    }
+}
+
+fn configure_storage_batch<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+    poseidon: &impl PoseidonLookup,
+    bytes: &impl BytesLookup,
+    rlc: &impl RlcLookup,
+    randomness: Query<F>,
+) {
+    // Each storage update has a start row, a storage root row, and its storage trie rows. They are
+    // followed by a start row and the account path from the first storage root to the last one.
+    // The account path is not proven for the intermediate storage roots, so all start rows have
+    // the old and new state roots of the whole batch, and only the last one is exposed in the mpt
+    // table.
+    configure_storage(cb, config, poseidon, bytes, rlc, randomness);
+
+    let [.., old_root, new_root, old_storage_root, _, _, _] = config.intermediate_values;
+    let [.., storage_root, account_key, _] = config.intermediate_values;
+
+    let is_start = config.segment_type.current_matches(&[SegmentType::Start]);
+    let is_storage_root = config
+        .segment_type
+        .current_matches(&[SegmentType::AccountLeaf3])
+        .and(config.segment_type.previous_matches(&[SegmentType::Start]));
+    cb.condition(is_start.clone(), |cb| {
+        cb.assert_equal(
+            "new_root is new root of start row",
+            new_root.current(),
+            config.new_hash.current(),
+        );
+        cb.assert_equal(
+            "account_key is key of start row",
+            account_key.current(),
+            config.key.current(),
+        );
+
+        let follows_storage_update = config
+            .proof_type
+            .previous_matches(&[MPTProofType::StorageBatchChanged])
+            .and(
+                config
+                    .segment_type
+                    .previous_matches(&[SegmentType::StorageLeaf0]),
+            );
+        cb.condition(follows_storage_update.clone(), |cb| {
+            for (name, column) in [
+                ("old_root does not change", old_root),
+                ("old_storage_root does not change", old_storage_root),
+                ("storage_root does not change", storage_root),
+            ] {
+                cb.assert_equal(name, column.current(), column.previous());
+            }
+            cb.assert_equal(
+                "account key does not change",
+                config.key.current(),
+                account_key.previous(),
+            );
+            cb.assert_equal(
+                "old root is old root of first storage update",
+                config.old_hash.current(),
+                old_root.previous(),
+            );
+            cb.assert_equal(
+                "new root is new root of first storage update",
+                config.new_hash.current(),
+                new_root.previous(),
+            );
+        });
+        cb.condition(!follows_storage_update, |cb| {
+            cb.assert(
+                "storage batch starts with a storage update",
+                config
+                    .segment_type
+                    .next_matches(&[SegmentType::AccountLeaf3]),
+            );
+            cb.assert_equal(
+                "old_root is old root of first storage update",
+                old_root.current(),
+                config.old_hash.current(),
+            );
+            cb.assert_equal(
+                "storage_root is old_storage_root before first storage update",
+                storage_root.current(),
+                old_storage_root.current(),
+            );
+        });
+    });
+    cb.condition(!is_start, |cb| {
+        for (name, column) in [
+            ("old_root does not change", old_root),
+            ("new_root does not change", new_root),
+            ("old_storage_root does not change", old_storage_root),
+            ("account_key does not change", account_key),
+        ] {
+            cb.assert_equal(name, column.current(), column.previous());
+        }
+        cb.condition(!is_storage_root.clone(), |cb| {
+            cb.assert_equal(
+                "storage_root does not change",
+                storage_root.current(),
+                storage_root.previous(),
+            );
+        });
+    });
+    cb.condition(is_storage_root, |cb| {
+        cb.assert(
+            "storage root row is followed by storage trie",
+            !config.segment_type.next_matches(&[SegmentType::Start]),
+        );
+        cb.assert_equal(
+            "old storage root is storage root after previous storage update",
+            config.old_hash.current(),
+            storage_root.previous(),
+        );
+        cb.assert_equal(
+            "storage_root is new storage root",
+            storage_root.current(),
+            config.new_hash.current(),
+        );
+    });
+    cb.condition(
+        config
+            .segment_type
+            .current_matches(&[SegmentType::StorageLeaf0]),
+        |cb| {
+            cb.assert(
+                "storage update is followed by next storage update or account path",
+                config
+                    .proof_type
+                    .next_matches(&[MPTProofType::StorageBatchChanged]),
+            );
+        },
+    );
+    cb.condition(
+        config.segment_type.current_matches(&[
+            SegmentType::AccountTrie,
+            SegmentType::AccountLeaf0,
+            SegmentType::AccountLeaf1,
+            SegmentType::AccountLeaf2,
+        ]),
+        |cb| {
+            cb.assert(
+                "account path is common for storage batch",
+                config.path_type.current_matches(&[PathType::Common]),
+            );
+        },
+    );
+    cb.condition(
+        config
+            .segment_type
+            .current_matches(&[SegmentType::AccountLeaf3])
+            .and(
+                config
+                    .segment_type
+                    .previous_matches(&[SegmentType::AccountLeaf2]),
+            ),
+        |cb| {
+            cb.assert(
+                "account path is common for storage batch",
+                config.path_type.current_matches(&[PathType::Common]),
+            );
+            cb.assert(
+                "account leaf is last row of storage batch",
+                config.segment_type.next_matches(&[SegmentType::Start]),
+            );
+            cb.assert_equal(
+                "old storage root is old storage root of first storage update",
+                config.old_hash.current(),
+                old_storage_root.current(),
+            );
+            cb.assert_equal(
+                "new storage root is new storage root of last storage update",
+                config.new_hash.current(),
+                storage_root.current(),
+            );
+        },
+    );
+}
+
+fn address_high(a: Address) -> u128 {
+    let high_bytes: [u8; 16] = a.0[..16].try_into().unwrap();
+    u128::from_be_bytes(high_bytes)
+}
+
+fn address_low(a: Address) -> u32 {
+    let low_bytes: [u8; 4] = a.0[16..].try_into().unwrap();
+    u32::from_be_bytes(low_bytes)
+}
+
+// ... the return traces: ([inp;2], domain, hash)
+pub fn hash_traces(proofs: &[Proof]) -> Vec<([Fr; 2], Fr, Fr)> {
+    let mut hash_traces = vec![(
+        [Fr::zero(), Fr::zero()],
+        HashDomain::Pair.into(),
+        *ZERO_PAIR_HASH,
+    )];
+    for proof in proofs.iter() {
+        for (left, right, domain, hash) in proof.account_trie_rows.poseidon_lookups() {
+            hash_traces.push(([left, right], Fr::from(domain), hash));
+        }
+        if let Some(rows) = proof.intermediate_account_trie_rows() {
+            for (left, right, domain, hash) in rows.poseidon_lookups() {
+                hash_traces.push(([left, right], Fr::from(domain), hash));
+            }
+        }
+
+        hash_traces.extend(
+            proof
+                .storage
+                .poseidon_lookups()
+                .into_iter()
+                .map(|(left, right, domain, h)| ([left, right], Fr::from(domain), h)),
+        );
+
+        let key = account_key(proof.claim.address);
+        hash_traces.push((
+            [
+                Fr::from_u128(address_high(proof.claim.address)),
+                Fr::from_u128(u128::from(address_low(proof.claim.address)) << 96),
+            ],
+            HashDomain::Pair.into(),
+            key,
+        ));
+
+        if let Some(data_hash) = proof.old.leaf_data_hash {
+            hash_traces.push((
+                [proof.old.key, data_hash],
+                HashDomain::Leaf.into(),
+                domain_hash(proof.old.key, data_hash, HashDomain::Leaf),
+            ));
+        }
+        if let Some(data_hash) = proof.new.leaf_data_hash {
+            hash_traces.push((
+                [proof.new.key, data_hash],
+                HashDomain::Leaf.into(),
+                domain_hash(proof.new.key, data_hash, HashDomain::Leaf),
+            ));
+        }
 
         for account_leaf_hash_traces in
             [proof.old_account_hash_traces, proof.new_account_hash_traces]
+                .into_iter()
+                .chain(proof.intermediate_account_hash_traces)
         {
             for [left, right, digest] in account_leaf_hash_traces {
                 if domain_hash(left, right, HashDomain::AccountFields) == digest {
@@ -2119,7 +3520,7 @@ pub fn key_bit_lookups(proofs: &[Proof]) -> Vec<(Fr, usize, bool)> {
             match (is_padding_open, is_padding_close) {
                 (false, false) => {
                     let mut lookup_keys = vec![proof.old.key, proof.new.key];
-                    let key = account_key(proof.claim.address);
+                    let key = proof.claim.key();
                     if !lookup_keys.contains(&key) {
                         lookup_keys.push(key);
                     }
@@ -2149,30 +3550,33 @@ pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>,
     let mut u32s = vec![];
     let mut u64s = vec![];
     let mut u128s = vec![0];
-    let mut frs = vec![];
+    // Balances are split into 16 byte limbs, so no values need 31 byte representations.
+    let frs = vec![];
 
     for proof in proofs {
         u128s.push(address_high(proof.claim.address));
         u32s.push(address_low(proof.claim.address));
+        // The new values of reads are not looked up.
+        let new_account = proof.new_account.filter(|_| !proof.claim.is_read());
         match MPTProofType::from(proof.claim) {
-            MPTProofType::NonceChanged | MPTProofType::CodeSizeExists => {
+            MPTProofType::NonceChanged | MPTProofType::NonceRead | MPTProofType::CodeSizeExists => {
                 u128s.push(address_high(proof.claim.address));
                 if let Some(account) = proof.old_account {
                     u64s.push(account.nonce);
                     u64s.push(account.code_size);
                 };
-                if let Some(account) = proof.new_account {
+                if let Some(account) = new_account {
                     u64s.push(account.nonce);
                     u64s.push(account.code_size);
                 };
             }
-            MPTProofType::BalanceChanged => {
+            MPTProofType::BalanceChanged | MPTProofType::BalanceRead => {
                 u128s.push(address_high(proof.claim.address));
                 if let Some(account) = proof.old_account {
-                    frs.push(account.balance);
+                    u128s.extend(balance_u128s(account.balance));
                 };
-                if let Some(account) = proof.new_account {
-                    frs.push(account.balance);
+                if let Some(account) = new_account {
+                    u128s.extend(balance_u128s(account.balance));
                 };
             }
             MPTProofType::PoseidonCodeHashExists => {
@@ -2191,25 +3595,25 @@ pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>,
                     u128s.push(lo);
                 };
             }
-            MPTProofType::StorageChanged => {
+            MPTProofType::StorageChanged | MPTProofType::StorageRead => {
                 u128s.push(address_high(proof.claim.address));
                 let (storage_key_high, storage_key_low) = u256_hi_lo(&proof.claim.storage_key());
                 u128s.push(storage_key_high);
                 u128s.push(storage_key_low);
 
                 match &proof.storage {
-                    StorageProof::Root(_) => unreachable!(),
+                    StorageProof::Root(_) | StorageProof::Batch(_) | StorageProof::Wiped(_) => {
+                        unreachable!()
+                    }
                     StorageProof::Update {
                         old_leaf, new_leaf, ..
                     } => {
                         let (old_value_high, old_value_low) = u256_hi_lo(&old_leaf.value());
-                        let (new_value_high, new_value_low) = u256_hi_lo(&new_leaf.value());
-                        u128s.extend(vec![
-                            old_value_high,
-                            old_value_low,
-                            new_value_high,
-                            new_value_low,
-                        ]);
+                        u128s.extend(vec![old_value_high, old_value_low]);
+                        if !proof.claim.is_read() {
+                            let (new_value_high, new_value_low) = u256_hi_lo(&new_leaf.value());
+                            u128s.extend(vec![new_value_high, new_value_low]);
+                        }
                     }
                 }
             }
@@ -2219,6 +3623,57 @@ pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>,
                 u128s.push(storage_key_high);
                 u128s.push(storage_key_low);
             }
+            MPTProofType::AccountCreated => {
+                if let Some(account) = proof.new_account {
+                    u64s.push(account.nonce);
+                    u64s.push(account.code_size);
+                    u128s.extend(balance_u128s(account.balance));
+                    let (hi, lo) = u256_hi_lo(&account.keccak_codehash);
+                    u128s.push(hi);
+                    u128s.push(lo);
+                };
+            }
+            MPTProofType::NonceAndFieldChanged => {
+                for account in [proof.old_account, proof.new_account].into_iter().flatten() {
+                    u64s.push(account.nonce);
+                    u64s.push(account.code_size);
+                    match proof.claim.kind {
+                        ClaimKind::NonceAndField {
+                            field: FieldUpdate::Balance { .. },
+                            ..
+                        } => u128s.extend(balance_u128s(account.balance)),
+                        ClaimKind::NonceAndField {
+                            field: FieldUpdate::CodeHash { .. },
+                            ..
+                        } => {
+                            let (hi, lo) = u256_hi_lo(&account.keccak_codehash);
+                            u128s.push(hi);
+                            u128s.push(lo);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            MPTProofType::StorageBatchChanged => {
+                u128s.push(address_high(proof.claim.address));
+                if let StorageProof::Batch(updates) = &proof.storage {
+                    for update in updates {
+                        if let StorageProof::Update {
+                            storage_key,
+                            old_leaf,
+                            new_leaf,
+                            ..
+                        } = update
+                        {
+                            for word in [*storage_key, old_leaf.value(), new_leaf.value()] {
+                                let (high, low) = u256_hi_lo(&word);
+                                u128s.push(high);
+                                u128s.push(low);
+                            }
+                        }
+                    }
+                }
+            }
             _ => {}
         }
     }
@@ -2232,9 +3687,6 @@ pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>,
     u128s.sort();
     u128s.dedup();
 
-    frs.sort();
-    frs.dedup();
-
     (u32s, u64s, u128s, frs)
 }
 
@@ -2244,10 +3696,11 @@ pub fn mpt_update_keys(proofs: &[Proof]) -> Vec<Fr> {
     for proof in proofs.iter() {
         keys.push(proof.old.key);
         keys.push(proof.new.key);
-        keys.push(account_key(proof.claim.address));
+        keys.push(proof.claim.key());
         keys.extend(proof.storage.key_lookups());
         keys.push(proof.claim.old_root);
         keys.push(proof.claim.new_root);
+        keys.extend(proof.intermediate_root());
     }
     keys.sort();
     keys.dedup();
@@ -60,7 +60,7 @@ pub fn transitions(proof: MPTProofType) -> HashMap<SegmentType, Vec<SegmentType>
             (SegmentType::AccountLeaf1, vec![SegmentType::Start]),
         ]
         .into(),
-        MPTProofType::StorageChanged => [
+        MPTProofType::StorageChanged | MPTProofType::StorageRead => [
             (
                 SegmentType::Start,
                 vec![
@@ -136,7 +136,130 @@ pub fn transitions(proof: MPTProofType) -> HashMap<SegmentType, Vec<SegmentType>
             ),
         ]
         .into(),
-        MPTProofType::AccountDestructed => [].into(),
+        MPTProofType::AccountDestructed => [
+            (
+                SegmentType::Start,
+                vec![
+                    SegmentType::AccountTrie,  // mpt has > 1 account
+                    SegmentType::AccountLeaf0, // mpt has 1 account
+                ],
+            ),
+            (
+                SegmentType::AccountTrie,
+                vec![SegmentType::AccountTrie, SegmentType::AccountLeaf0],
+            ),
+            (SegmentType::AccountLeaf0, vec![SegmentType::Start]),
+        ]
+        .into(),
+        MPTProofType::KeyValueChanged => [
+            (
+                SegmentType::Start,
+                vec![
+                    SegmentType::Start,        // key does not exist in an empty trie
+                    SegmentType::AccountTrie,  // trie has > 1 leaf
+                    SegmentType::AccountLeaf0, // trie has <= 1 leaf
+                ],
+            ),
+            (
+                SegmentType::AccountTrie,
+                vec![
+                    SegmentType::AccountTrie,
+                    SegmentType::AccountLeaf0,
+                    SegmentType::Start, // key does not exist before or after
+                ],
+            ),
+            (SegmentType::AccountLeaf0, vec![SegmentType::Start]),
+        ]
+        .into(),
+        MPTProofType::NonceRead
+        | MPTProofType::BalanceRead
+        | MPTProofType::StorageWiped
+        | MPTProofType::AccountIsEmpty => [
+            (
+                SegmentType::Start,
+                vec![
+                    SegmentType::AccountTrie,  // mpt has > 1 account
+                    SegmentType::AccountLeaf0, // mpt has 1 account
+                ],
+            ),
+            (
+                SegmentType::AccountTrie,
+                vec![SegmentType::AccountTrie, SegmentType::AccountLeaf0],
+            ),
+            (SegmentType::AccountLeaf0, vec![SegmentType::AccountLeaf1]),
+            (SegmentType::AccountLeaf1, vec![SegmentType::AccountLeaf2]),
+            (SegmentType::AccountLeaf2, vec![SegmentType::AccountLeaf3]),
+            (SegmentType::AccountLeaf3, vec![SegmentType::Start]),
+        ]
+        .into(),
+        MPTProofType::AccountCreated => [
+            (
+                SegmentType::Start,
+                vec![
+                    SegmentType::AccountTrie,  // mpt has > 1 account
+                    SegmentType::AccountLeaf0, // mpt has <= 1 account
+                ],
+            ),
+            (
+                SegmentType::AccountTrie,
+                vec![SegmentType::AccountTrie, SegmentType::AccountLeaf0],
+            ),
+            (SegmentType::AccountLeaf0, vec![SegmentType::AccountLeaf1]),
+            (SegmentType::AccountLeaf1, vec![SegmentType::AccountLeaf2]),
+            (SegmentType::AccountLeaf2, vec![SegmentType::AccountLeaf3]),
+            (SegmentType::AccountLeaf3, vec![SegmentType::Start]),
+        ]
+        .into(),
+        MPTProofType::NonceAndFieldChanged => [
+            (
+                SegmentType::Start,
+                vec![
+                    SegmentType::Start,        // nonce update follows update of other field
+                    SegmentType::AccountTrie,  // mpt has > 1 account
+                    SegmentType::AccountLeaf0, // mpt has 1 account
+                ],
+            ),
+            (
+                SegmentType::AccountTrie,
+                vec![SegmentType::AccountTrie, SegmentType::AccountLeaf0],
+            ),
+            (SegmentType::AccountLeaf0, vec![SegmentType::AccountLeaf1]),
+            (SegmentType::AccountLeaf1, vec![SegmentType::AccountLeaf2]),
+            (SegmentType::AccountLeaf2, vec![SegmentType::AccountLeaf3]),
+            (SegmentType::AccountLeaf3, vec![SegmentType::Start]),
+        ]
+        .into(),
+        MPTProofType::StorageBatchChanged => [
+            (
+                SegmentType::Start,
+                vec![
+                    SegmentType::AccountLeaf3, // storage root row for the next storage update
+                    SegmentType::AccountTrie,  // mpt has > 1 account
+                    SegmentType::AccountLeaf0, // mpt has 1 account
+                ],
+            ),
+            (
+                SegmentType::AccountTrie,
+                vec![SegmentType::AccountTrie, SegmentType::AccountLeaf0],
+            ),
+            (SegmentType::AccountLeaf0, vec![SegmentType::AccountLeaf1]),
+            (SegmentType::AccountLeaf1, vec![SegmentType::AccountLeaf2]),
+            (SegmentType::AccountLeaf2, vec![SegmentType::AccountLeaf3]),
+            (
+                SegmentType::AccountLeaf3,
+                vec![
+                    SegmentType::Start,        // account path ends after the storage updates
+                    SegmentType::StorageTrie,  // existing storage has > 1 entry
+                    SegmentType::StorageLeaf0, // existing storage <= 1 entry
+                ],
+            ),
+            (
+                SegmentType::StorageTrie,
+                vec![SegmentType::StorageTrie, SegmentType::StorageLeaf0],
+            ),
+            (SegmentType::StorageLeaf0, vec![SegmentType::Start]),
+        ]
+        .into(),
     }
 }
 
@@ -42,7 +42,70 @@ pub fn configure<F: FromUniformBytes<64> + Ord>(
         ],
         poseidon,
     );
+    configure_rlc(
+        cb,
+        [high, low],
+        [rlc_word, rlc_high, rlc_low],
+        rlc,
+        randomness,
+    );
+}
 
+// The account leaf holds the balance as a single field element instead of the hash of its limbs.
+// The high limb is range checked to 15 bytes, so high * 2^128 + low cannot wrap around the modulus
+// and each balance has exactly one pair of limbs.
+pub fn configure_balance<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    [balance, high, low]: [AdviceColumn; 3],
+    bytes: &impl BytesLookup,
+) {
+    cb.add_lookup(
+        "balance high is 16 bytes",
+        [high.current(), Query::from(15)],
+        bytes.lookup(),
+    );
+    cb.add_lookup(
+        "balance low is 16 bytes",
+        [low.current(), Query::from(15)],
+        bytes.lookup(),
+    );
+    cb.add_lookup(
+        "balance high * 256 is 16 bytes, so balance high is 15 bytes",
+        [high.current() * 256, Query::from(15)],
+        bytes.lookup(),
+    );
+    cb.assert_equal(
+        "balance = high * 2^128 + low",
+        balance.current(),
+        high.current() * Query::Constant(F::from(1 << 32).square().square()) + low.current(),
+    );
+}
+
+pub fn configure_balance_rlc<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    [balance, high, low]: [AdviceColumn; 3],
+    [rlc_balance, rlc_high, rlc_low]: [SecondPhaseAdviceColumn; 3],
+    bytes: &impl BytesLookup,
+    rlc: &impl RlcLookup,
+    randomness: Query<F>,
+) {
+    configure_balance(cb, [balance, high, low], bytes);
+    configure_rlc(
+        cb,
+        [high, low],
+        [rlc_balance, rlc_high, rlc_low],
+        rlc,
+        randomness,
+    );
+}
+
+fn configure_rlc<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    [high, low]: [AdviceColumn; 2],
+    [rlc_word, rlc_high, rlc_low]: [SecondPhaseAdviceColumn; 3],
+    rlc: &impl RlcLookup,
+    randomness: Query<F>,
+) {
     cb.add_lookup(
         "rlc_high = rlc(high) and high is 16 bytes",
         [high.current(), Query::from(15), rlc_high.current()],
@@ -69,9 +132,7 @@ pub fn assign(
     [rlc_high, rlc_low]: [SecondPhaseAdviceColumn; 2],
     randomness: Value<Fr>,
 ) {
-    let (high, low) = u256_hi_lo(&word);
-    high_column.assign(region, offset, Fr::from_u128(high));
-    low_column.assign(region, offset, Fr::from_u128(low));
+    let (high, low) = assign_limbs(region, offset, word, [high_column, low_column]);
     rlc_high.assign(
         region,
         offset,
@@ -83,3 +144,21 @@ pub fn assign(
         randomness.map(|r| rlc(&low.to_be_bytes(), r)),
     );
 }
+
+pub fn assign_limbs(
+    region: &mut Region<'_, Fr>,
+    offset: usize,
+    word: U256,
+    [high_column, low_column]: [AdviceColumn; 2],
+) -> (u128, u128) {
+    let (high, low) = u256_hi_lo(&word);
+    high_column.assign(region, offset, Fr::from_u128(high));
+    low_column.assign(region, offset, Fr::from_u128(low));
+    (high, low)
+}
+
+// Values that the byte representations must include for the balance range checks.
+pub fn balance_u128s(balance: U256) -> [u128; 3] {
+    let (high, low) = u256_hi_lo(&balance);
+    [high, low, high << 8]
+}
@@ -1,11 +1,11 @@
 use crate::constraint_builder::{AdviceColumn, FixedColumn};
 use halo2_proofs::plonk::{Advice, Column, Fixed};
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 use halo2_proofs::{circuit::Region, halo2curves::bn256::Fr, plonk::ConstraintSystem};
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 use hash_circuit::hash::Hashable;
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 const MAX_POSEIDON_ROWS: usize = 200;
 
 /// Lookup  represent the poseidon table in zkevm circuit
@@ -20,7 +20,7 @@ pub trait PoseidonLookup {
     }
 }
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 #[derive(Clone, Copy)]
 pub struct PoseidonTable {
     q_enable: FixedColumn,
@@ -32,7 +32,7 @@ pub struct PoseidonTable {
     head_mark: AdviceColumn,
 }
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 impl PoseidonTable {
     pub fn configure<F: halo2_proofs::halo2curves::ff::FromUniformBytes<64> + Ord>(
         cs: &mut ConstraintSystem<F>,
@@ -82,7 +82,7 @@ impl PoseidonTable {
     }
 }
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 impl PoseidonLookup for PoseidonTable {
     fn lookup_columns(&self) -> (FixedColumn, [AdviceColumn; 6]) {
         (
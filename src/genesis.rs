@@ -0,0 +1,127 @@
+//! Genesis allocations, from which the initial account and storage tries are built
+//!
+use crate::{
+    serde::AccountData,
+    util::{fr_to_biguint, keccak_code_hash, poseidon_code_hash, u256_to_biguint},
+    zktrie::AccountTrie,
+};
+use ethers_core::types::{Address, U256};
+use halo2_proofs::halo2curves::bn256::Fr;
+use serde::{
+    de::{Deserializer, Error},
+    Deserialize,
+};
+use std::collections::HashMap;
+
+/// Genesis file, of which only the allocation is used.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Genesis {
+    /// accounts at genesis
+    pub alloc: HashMap<Address, GenesisAccount>,
+}
+
+/// Account in the genesis allocation. Quantities can be hex or decimal, as in geth.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct GenesisAccount {
+    /// balance
+    #[serde(default, deserialize_with = "de_quantity")]
+    pub balance: U256,
+    /// nonce
+    #[serde(default, deserialize_with = "de_nonce")]
+    pub nonce: u64,
+    /// code
+    #[serde(default, deserialize_with = "de_code")]
+    pub code: Vec<u8>,
+    /// storage slots
+    #[serde(default, deserialize_with = "de_storage")]
+    pub storage: HashMap<U256, U256>,
+}
+
+impl GenesisAccount {
+    /// Account fields, with the code hashes and code size computed from the code.
+    pub fn account_data(&self) -> AccountData {
+        AccountData {
+            nonce: self.nonce,
+            balance: u256_to_biguint(self.balance),
+            code_hash: u256_to_biguint(keccak_code_hash(&self.code)),
+            poseidon_code_hash: fr_to_biguint(poseidon_code_hash(&self.code)),
+            code_size: self.code.len() as u64,
+            storage_root: None,
+        }
+    }
+}
+
+impl Genesis {
+    /// Parses a genesis file.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Account trie at genesis, built in bulk rather than by inserting the accounts one at a time.
+    pub fn account_trie(&self) -> AccountTrie {
+        AccountTrie::from_accounts(
+            self.alloc.iter().map(|(address, account)| {
+                (*address, account.account_data(), account.storage.clone())
+            }),
+        )
+    }
+
+    /// Root of the account trie at genesis.
+    pub fn state_root(&self) -> Fr {
+        self.account_trie().root()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Quantity {
+    Number(u64),
+    String(String),
+}
+
+fn parse_quantity<E: Error>(x: &str) -> Result<U256, E> {
+    match x.strip_prefix("0x") {
+        Some("") => Ok(U256::zero()),
+        Some(hex) => U256::from_str_radix(hex, 16).map_err(E::custom),
+        None => U256::from_dec_str(x).map_err(E::custom),
+    }
+}
+
+fn de_quantity<'de, D>(deserializer: D) -> Result<U256, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Quantity::deserialize(deserializer)? {
+        Quantity::Number(x) => Ok(U256::from(x)),
+        Quantity::String(x) => parse_quantity(&x),
+    }
+}
+
+fn de_nonce<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let nonce = de_quantity(deserializer)?;
+    if nonce > U256::from(u64::MAX) {
+        return Err(D::Error::custom("nonce does not fit into u64"));
+    }
+    Ok(nonce.as_u64())
+}
+
+fn de_code<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let code = String::deserialize(deserializer)?;
+    hex::decode(code.strip_prefix("0x").unwrap_or(&code)).map_err(D::Error::custom)
+}
+
+fn de_storage<'de, D>(deserializer: D) -> Result<HashMap<U256, U256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, String>::deserialize(deserializer)?
+        .iter()
+        .map(|(key, value)| Ok((parse_quantity(key)?, parse_quantity(value)?)))
+        .collect()
+}
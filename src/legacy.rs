@@ -0,0 +1,235 @@
+//! Conversion of ops in the legacy row format into `SMTPath`s and `SMTTrace`s
+//!
+//! Each row of an op is a level of the path to its key, starting at the root. Middle rows are
+//! branches, with the row value the child along the path and `sib` the other child. A leaf or empty
+//! row ends the path on its side. When an insertion extends a leaf into new branches, the side
+//! without those branches has LeafExt rows (LeafExtFinal in the last of them) holding the leaf
+//! being extended, which ends the path on that side like a leaf row. The rows do not record branch
+//! node types, so they are recovered from the row hashes.
+use crate::{
+    serde::{
+        AccountData, Address, Hash, HashType, KeyValueTrace, Row, SMTNode, SMTPath, SMTTrace,
+        StateData,
+    },
+    types::HashDomain,
+    util::{domain_hash, fr_to_hash, storage_key_hash, u256_to_hex, Bit},
+};
+use ethers_core::types::U256;
+use halo2_proofs::halo2curves::bn256::Fr;
+use num_bigint::BigUint;
+use num_traits::identities::{One, Zero};
+
+/// Errors converting legacy rows.
+#[derive(Debug, thiserror::Error)]
+pub enum RowError {
+    #[error("op has no rows")]
+    /// op is empty
+    EmptyOp,
+    #[error("rows of the op are for different keys")]
+    /// rows of one op must all have its key
+    KeyMismatch,
+    #[error("{side} hash of row {row} is not the hash of a branch with its value and sibling")]
+    /// no branch domain hashes the row value and sibling to the row hash
+    BranchHashMismatch {
+        /// old or new
+        side: &'static str,
+        /// index of the row in the op
+        row: usize,
+    },
+    #[error("{side} hash of row {row} is not the hash of a leaf with its value")]
+    /// leaf row does not hash to the row hash
+    LeafHashMismatch {
+        /// old or new
+        side: &'static str,
+        /// index of the row in the op
+        row: usize,
+    },
+    #[error("{field} of row {row} is not a canonical field element")]
+    /// hash is not the little endian encoding of a field element
+    NonCanonical {
+        /// which hash of the row
+        field: &'static str,
+        /// index of the row in the op
+        row: usize,
+    },
+}
+
+/// Storage of the account in a converted trace.
+#[derive(Clone, Copy, Debug)]
+pub enum LegacyStorage<'a> {
+    /// storage is unchanged, with this root
+    Root(Fr),
+    /// op of a storage slot update, with the old and new values of the slot
+    Update {
+        rows: &'a [Row],
+        key: U256,
+        values: [U256; 2],
+    },
+}
+
+/// Old and new paths to the key of an op.
+pub fn paths(rows: &[Row]) -> Result<[SMTPath; 2], RowError> {
+    let key = op_key(rows)?;
+    Ok([
+        path(key, rows, "old", |row| {
+            (row.old_hash_type, row.old_hash, row.old_value)
+        })?,
+        path(key, rows, "new", |row| {
+            (row.new_hash_type, row.new_hash, row.new_value)
+        })?,
+    ])
+}
+
+impl TryFrom<&[Row]> for KeyValueTrace {
+    type Error = RowError;
+
+    fn try_from(rows: &[Row]) -> Result<Self, Self::Error> {
+        Ok(Self {
+            key: fr_to_hash(op_key(rows)?),
+            path: paths(rows)?,
+        })
+    }
+}
+
+/// Trace of an account op, with the account fields before and after it, which the rows only have
+/// the hashes of. For storage updates, the storage op and the values of the slot are also needed.
+pub fn smt_trace(
+    address: Address,
+    account_rows: &[Row],
+    account_update: [Option<AccountData>; 2],
+    storage: LegacyStorage,
+) -> Result<SMTTrace, RowError> {
+    let mut trace = SMTTrace {
+        address,
+        account_key: fr_to_hash(op_key(account_rows)?),
+        account_path: paths(account_rows)?,
+        account_update,
+        state_path: [None, None],
+        common_state_root: None,
+        state_key: None,
+        state_update: None,
+    };
+    match storage {
+        LegacyStorage::Root(root) => trace.common_state_root = Some(fr_to_hash(root)),
+        LegacyStorage::Update { rows, key, values } => {
+            let [old_path, new_path] = paths(rows)?;
+            let [old_value, new_value] = values.map(|value| StateData {
+                key: u256_to_hex(key),
+                value: u256_to_hex(value),
+            });
+            trace.state_path = [Some(old_path), Some(new_path)];
+            trace.state_key = Some(fr_to_hash(storage_key_hash(key)));
+            trace.state_update = Some([Some(old_value), Some(new_value)]);
+        }
+    }
+    Ok(trace)
+}
+
+fn op_key(rows: &[Row]) -> Result<Fr, RowError> {
+    let first = rows.first().ok_or(RowError::EmptyOp)?;
+    if rows.iter().any(|row| row.key != first.key) {
+        return Err(RowError::KeyMismatch);
+    }
+    row_fr(first.key, "key", 0)
+}
+
+fn path(
+    key: Fr,
+    rows: &[Row],
+    side: &'static str,
+    side_fields: impl Fn(&Row) -> (HashType, Hash, Hash),
+) -> Result<SMTPath, RowError> {
+    let mut path = vec![];
+    let mut path_part = BigUint::zero();
+    let mut leaf = None;
+    for (depth, row) in rows.iter().enumerate() {
+        let (hash_type, hash, value) = side_fields(row);
+        let hash = row_fr(hash, "hash", depth)?;
+        let value = row_fr(value, "value", depth)?;
+        let direction = key.bit(depth);
+        let domain = match hash_type {
+            HashType::Empty => break,
+            HashType::Leaf => None,
+            HashType::Start | HashType::Middle => Some(
+                branch_domain(key, depth, row, hash, value)?
+                    .ok_or(RowError::BranchHashMismatch { side, row: depth })?,
+            ),
+            // Dumps mark the side with the extended leaf, which is not a branch of the row value
+            // and sibling.
+            HashType::LeafExt | HashType::LeafExtFinal => {
+                branch_domain(key, depth, row, hash, value)?
+            }
+        };
+        let domain = match domain {
+            Some(domain) => domain,
+            None => {
+                // The row key is the key of the op, which differs from the key of the leaf for type
+                // 1 nonexistence proofs and extended leafs, where the accumulated path has the full
+                // leaf key.
+                let leaf_key = [Some(key), biguint_to_fr(&row.path_acc)]
+                    .into_iter()
+                    .flatten()
+                    .find(|leaf_key| domain_hash(*leaf_key, value, HashDomain::Leaf) == hash)
+                    .ok_or(RowError::LeafHashMismatch { side, row: depth })?;
+                leaf = Some(SMTNode {
+                    value: fr_to_hash(value),
+                    sibling: fr_to_hash(leaf_key),
+                    node_type: HashDomain::Leaf.into(),
+                });
+                break;
+            }
+        };
+        path.push(SMTNode {
+            value: fr_to_hash(value),
+            sibling: row.sib,
+            node_type: domain.into(),
+        });
+        if direction {
+            path_part += BigUint::one() << depth;
+        }
+    }
+    let (_, root, _) = side_fields(&rows[0]);
+    Ok(SMTPath {
+        root,
+        leaf,
+        path,
+        path_part,
+    })
+}
+
+// Branch domain which hashes the row value and sibling into the row hash, if any.
+fn branch_domain(
+    key: Fr,
+    depth: usize,
+    row: &Row,
+    hash: Fr,
+    value: Fr,
+) -> Result<Option<HashDomain>, RowError> {
+    let sibling = row_fr(row.sib, "sibling", depth)?;
+    let (left, right) = if key.bit(depth) {
+        (sibling, value)
+    } else {
+        (value, sibling)
+    };
+    Ok([
+        HashDomain::Branch0,
+        HashDomain::Branch1,
+        HashDomain::Branch2,
+        HashDomain::Branch3,
+    ]
+    .into_iter()
+    .find(|domain| domain_hash(left, right, *domain) == hash))
+}
+
+fn row_fr(x: Hash, field: &'static str, row: usize) -> Result<Fr, RowError> {
+    Option::from(Fr::from_bytes(&x.0)).ok_or(RowError::NonCanonical { field, row })
+}
+
+fn biguint_to_fr(x: &BigUint) -> Option<Fr> {
+    let mut bytes = x.to_bytes_le();
+    if bytes.len() > 32 {
+        return None;
+    }
+    bytes.resize(32, 0);
+    Option::from(Fr::from_bytes(&bytes.try_into().unwrap()))
+}
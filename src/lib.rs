@@ -1,10 +1,13 @@
 #![allow(clippy::too_many_arguments)]
 #![deny(unsafe_code, unused_imports)]
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-circuit"))]
 mod circuit;
+pub mod codec;
 pub mod constraint_builder;
 pub mod gadgets;
+pub mod genesis;
+pub mod legacy;
 mod mpt_table;
 #[cfg(test)]
 mod tests;
@@ -13,10 +16,13 @@ mod util;
 
 pub mod mpt;
 pub mod serde;
+pub mod state_db;
+pub mod stream;
+pub mod zktrie;
 
 pub use gadgets::mpt_update::hash_traces;
 pub use mpt::MptCircuitConfig;
-pub use mpt_table::MPTProofType;
+pub use mpt_table::{MPTProofType, MptTableRow};
 
-#[cfg(feature = "bench")]
+#[cfg(feature = "test-circuit")]
 pub use circuit::TestCircuit;
@@ -23,6 +23,50 @@ use halo2_proofs::{
 use itertools::Itertools;
 use std::time::Instant;
 
+/// Errors assigning the mpt circuit.
+#[derive(Debug, thiserror::Error)]
+pub enum AssignError {
+    #[error("{gadget} requires {required} rows, but only {available} rows are available")]
+    /// proofs don't fit into one of the sub-gadgets of the circuit
+    NotEnoughRows {
+        /// sub-gadget that overflowed
+        gadget: &'static str,
+        /// rows the sub-gadget needs for the proofs
+        required: usize,
+        /// rows in the circuit
+        available: usize,
+    },
+    #[error(transparent)]
+    /// error from the layouter
+    Synthesis(#[from] Error),
+}
+
+impl AssignError {
+    /// Number of rows the circuit is short by, or 0 for other errors.
+    pub fn missing_rows(&self) -> usize {
+        match self {
+            Self::NotEnoughRows {
+                required,
+                available,
+                ..
+            } => required - available,
+            Self::Synthesis(_) => 0,
+        }
+    }
+}
+
+impl From<AssignError> for Error {
+    fn from(e: AssignError) -> Self {
+        match e {
+            AssignError::Synthesis(e) => e,
+            AssignError::NotEnoughRows { .. } => {
+                log::error!("{e}");
+                Error::Synthesis
+            }
+        }
+    }
+}
+
 /// Config for MptCircuit
 #[derive(Clone)]
 pub struct MptCircuitConfig {
@@ -111,12 +155,18 @@ impl MptCircuitConfig {
         }
     }
 
+    /// Assigns the proofs into a circuit with n_rows rows, failing with
+    /// `AssignError::NotEnoughRows` before anything is assigned if they don't fit. Callers which
+    /// convert the error into a halo2 `Error` lose which gadget overflowed, since `NotEnoughRows`
+    /// becomes `Error::Synthesis` (after being logged).
     pub fn assign(
         &self,
         layouter: &mut impl Layouter<Fr>,
         proofs: &[Proof],
         n_rows: usize,
-    ) -> Result<(), Error> {
+    ) -> Result<(), AssignError> {
+        Self::check_n_rows(proofs, n_rows)?;
+
         let randomness = self.rlc_randomness.value(layouter);
         let (u32s, u64s, u128s, frs) = byte_representations(proofs);
 
@@ -134,7 +184,12 @@ impl MptCircuitConfig {
                             self.mpt_update.assign_padding_row(&mut region, offset);
                         }
                     } else {
-                        for offset in 0..(n_rows - (1 + n_assigned_rows)) {
+                        // check_n_rows above guarantees that this doesn't underflow, but a
+                        // mismatch with assign_par should not wrap around into a huge region.
+                        let n_padding_rows = n_rows
+                            .checked_sub(1 + n_assigned_rows)
+                            .ok_or(Error::Synthesis)?;
+                        for offset in 0..n_padding_rows {
                             self.mpt_update.assign_padding_row(&mut region, offset);
                         }
                     }
@@ -146,13 +201,6 @@ impl MptCircuitConfig {
                 || "mpt update",
                 |mut region| {
                     let n_assigned_rows = self.mpt_update.assign(&mut region, proofs, randomness);
-
-                    assert!(
-                        2 + n_assigned_rows <= n_rows,
-                        "mpt circuit requires {n_assigned_rows} rows for mpt updates + 1 initial \
-                    all-zero row + at least 1 final padding row. Only {n_rows} rows available."
-                    );
-
                     for offset in (1 + n_assigned_rows)..n_rows {
                         self.mpt_update.assign_padding_row(&mut region, offset);
                     }
@@ -176,22 +224,10 @@ impl MptCircuitConfig {
             log::debug!("mpt key_bit assignment took {:?}", key_bit_time);
         }
 
-        // pad canonical_representation to fixed count
-        // notice each input cost 32 rows in canonical_representation, and inside
-        // assign one extra input is added
         let (keys, get_keys_time) = {
             let dur = Instant::now();
-            let mut keys = mpt_update_keys(proofs);
-            keys.sort();
-            keys.dedup();
-            (keys, dur.elapsed())
+            (canonical_representation_keys(proofs), dur.elapsed())
         };
-        let total_rep_size = n_rows / 32 - 1;
-        assert!(
-            total_rep_size >= keys.len(),
-            "no enough space for canonical representation of all keys (need {})",
-            keys.len()
-        );
         log::debug!("get keys took {:?}", get_keys_time);
 
         if use_par {
@@ -250,7 +286,8 @@ impl MptCircuitConfig {
 
                 Ok(())
             },
-        )
+        )?;
+        Ok(())
     }
 
     pub fn lookup_exprs<F: FromUniformBytes<64> + Ord>(
@@ -267,19 +304,116 @@ impl MptCircuitConfig {
 
     /// The number of minimum number of rows required for the mpt circuit.
     pub fn n_rows_required(proofs: &[Proof]) -> usize {
-        let (u32s, u64s, u128s, frs) = byte_representations(proofs);
+        gadget_n_rows_required(proofs)
+            .into_iter()
+            .map(|(_, n_rows)| n_rows)
+            .max()
+            .unwrap()
+    }
 
-        // +1 for the final padding row to satisfy the "final mpt update is padding" constraint.
-        1 + *[
-            MptUpdateConfig::n_rows_required(proofs),
-            CanonicalRepresentationConfig::n_rows_required(&mpt_update_keys(proofs)),
-            KeyBitConfig::n_rows_required(&key_bit_lookups(proofs)),
-            // TODO: move rlc lookup for frs into CanonicalRepresentationConfig.
-            ByteRepresentationConfig::n_rows_required(&u32s, &u64s, &u128s, &frs),
-            ByteBitGadget::n_rows_required(),
-        ]
-        .iter()
-        .max()
-        .unwrap()
+    /// Checks that the proofs fit into a circuit with n_rows rows, so that a batch that is too
+    /// large can be split before it is assigned. If they don't, the error is for the gadget which
+    /// requires the most rows, so that `missing_rows` is the number of rows to add.
+    pub fn check_n_rows(proofs: &[Proof], n_rows: usize) -> Result<(), AssignError> {
+        let (gadget, required) = gadget_n_rows_required(proofs)
+            .into_iter()
+            .max_by_key(|(_, required)| *required)
+            .unwrap();
+        if required > n_rows {
+            return Err(AssignError::NotEnoughRows {
+                gadget,
+                required,
+                available: n_rows,
+            });
+        }
+        Ok(())
     }
 }
+
+/// Upper bound on the rows a batch of proofs needs, which is updated as proofs are added one at a
+/// time, so that a stream of proofs can be cut into batches without calling `n_rows_required` on
+/// every prefix. Keys and byte representations shared by proofs are counted once per proof, so the
+/// bound can be above `n_rows_required` for the batch, but never below it.
+#[derive(Clone, Debug)]
+pub struct RowBudget {
+    available: usize,
+    overhead: [(&'static str, usize); 5],
+    required: [(&'static str, usize); 5],
+}
+
+impl RowBudget {
+    /// Budget for a circuit with n_rows rows.
+    pub fn new(n_rows: usize) -> Self {
+        let overhead = gadget_n_rows_required(&[]);
+        Self {
+            available: n_rows,
+            overhead,
+            required: overhead,
+        }
+    }
+
+    /// Bound on the rows needed by the proofs added so far.
+    pub fn n_rows(&self) -> usize {
+        self.required
+            .iter()
+            .map(|(_, n_rows)| *n_rows)
+            .max()
+            .unwrap()
+    }
+
+    /// Adds the proof to the batch if it fits, and leaves the budget unchanged otherwise.
+    pub fn add(&mut self, proof: &Proof) -> Result<(), AssignError> {
+        let mut required = self.required;
+        for ((gadget, n_rows), ((_, added), (_, overhead))) in required.iter_mut().zip(
+            gadget_n_rows_required(std::slice::from_ref(proof))
+                .into_iter()
+                .zip(self.overhead),
+        ) {
+            *n_rows += added - overhead;
+            if *n_rows > self.available {
+                return Err(AssignError::NotEnoughRows {
+                    gadget: *gadget,
+                    required: *n_rows,
+                    available: self.available,
+                });
+            }
+        }
+        self.required = required;
+        Ok(())
+    }
+
+    /// Empties the batch.
+    pub fn reset(&mut self) {
+        self.required = self.overhead;
+    }
+}
+
+// Rows needed by each sub-gadget. The +1's are for the final padding row to satisfy the "final mpt
+// update is padding" constraint.
+fn gadget_n_rows_required(proofs: &[Proof]) -> [(&'static str, usize); 5] {
+    let (u32s, u64s, u128s, frs) = byte_representations(proofs);
+    let n_keys = canonical_representation_keys(proofs).len();
+
+    [
+        ("mpt_update", MptUpdateConfig::n_rows_required(proofs) + 1),
+        // Each key takes 32 rows in canonical_representation, and assign adds one extra key.
+        ("canonical_representation", 32 * (n_keys + 1)),
+        (
+            "key_bit",
+            KeyBitConfig::n_rows_required(&key_bit_lookups(proofs)) + 1,
+        ),
+        (
+            "byte_representation",
+            // TODO: move rlc lookup for frs into CanonicalRepresentationConfig.
+            ByteRepresentationConfig::n_rows_required(&u32s, &u64s, &u128s, &frs) + 1,
+        ),
+        ("byte_bit", ByteBitGadget::n_rows_required() + 1),
+    ]
+}
+
+fn canonical_representation_keys(proofs: &[Proof]) -> Vec<Fr> {
+    let mut keys = mpt_update_keys(proofs);
+    keys.sort();
+    keys.dedup();
+    keys
+}
@@ -1,30 +1,60 @@
-use crate::types::{Claim, ClaimKind};
+use crate::{
+    serde::{HexBytes, SMTTrace},
+    types::{Claim, ClaimKind, Proof, TraceError},
+    util::{rlc, u256_to_big_endian},
+};
+use ethers_core::types::U256;
+use halo2_proofs::halo2curves::bn256::Fr;
+use num_traits::Zero;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
-/// The defination is greped from state-circuit
+/// The defination is greped from state-circuit. The discriminants are the values of the proof type
+/// column and its encoding in witnesses, so they must not change.
 #[derive(
     Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, EnumIter, Hash, Serialize, Deserialize,
 )]
 pub enum MPTProofType {
     /// nonce
-    NonceChanged,
+    NonceChanged = 0,
     /// balance
-    BalanceChanged,
+    BalanceChanged = 1,
     /// keccak codehash updated
-    CodeHashExists,
+    CodeHashExists = 2,
     /// poseidon codehash updated
-    PoseidonCodeHashExists,
+    PoseidonCodeHashExists = 3,
     /// code size updated
-    CodeSizeExists,
+    CodeSizeExists = 4,
     /// account is empty
-    AccountDoesNotExist,
+    AccountDoesNotExist = 5,
     /// storage
-    StorageChanged,
+    StorageChanged = 6,
     /// non exist proof for storage
-    StorageDoesNotExist,
+    StorageDoesNotExist = 7,
     /// account destructed
-    AccountDestructed,
+    AccountDestructed = 8,
+    /// account created with all its fields set at once
+    AccountCreated = 9,
+    /// nonce of existing account read without being changed
+    NonceRead = 10,
+    /// balance of existing account read without being changed
+    BalanceRead = 11,
+    /// non-empty storage slot read without being changed
+    StorageRead = 12,
+    /// nonce and at most one other account field updated together, exposed as a NonceChanged row
+    /// followed by a row for the update of the other field. Updates of more fields need separate
+    /// proofs.
+    NonceAndFieldChanged = 13,
+    /// several storage slots of one account updated together, exposed as a single row with the old
+    /// and new roots of the whole batch
+    StorageBatchChanged = 14,
+    /// storage root of existing account reset to the empty root
+    StorageWiped = 15,
+    /// account exists with all fields empty, as opposed to AccountDoesNotExist
+    AccountIsEmpty = 16,
+    /// leaf of a generic key/value trie updated, with the key in the storage key column and the
+    /// value hashes as values
+    KeyValueChanged = 17,
 }
 
 impl From<Claim> for MPTProofType {
@@ -44,6 +74,186 @@ impl From<ClaimKind> for MPTProofType {
             ClaimKind::Storage { .. } => MPTProofType::StorageChanged,
             ClaimKind::IsEmpty(None) => MPTProofType::AccountDoesNotExist,
             ClaimKind::IsEmpty(Some(_)) => MPTProofType::StorageDoesNotExist,
+            ClaimKind::AccountDestructed => MPTProofType::AccountDestructed,
+            ClaimKind::AccountCreated { .. } => MPTProofType::AccountCreated,
+            ClaimKind::NonceRead(_) => MPTProofType::NonceRead,
+            ClaimKind::BalanceRead(_) => MPTProofType::BalanceRead,
+            ClaimKind::StorageRead { .. } => MPTProofType::StorageRead,
+            ClaimKind::NonceAndField { .. } => MPTProofType::NonceAndFieldChanged,
+            ClaimKind::StorageBatch => MPTProofType::StorageBatchChanged,
+            ClaimKind::StorageWiped => MPTProofType::StorageWiped,
+            ClaimKind::AccountIsEmpty => MPTProofType::AccountIsEmpty,
+            ClaimKind::KeyValue { .. } => MPTProofType::KeyValueChanged,
         }
     }
 }
+
+impl MPTProofType {
+    /// Infers the proof type of a trace that comes without one, such as the traces in the
+    /// mptwitness of an l2geth block trace. Unchanged existing accounts are proven as NonceRead.
+    /// Returns None if the trace changes several account fields that no proof type covers.
+    pub fn infer(trace: &SMTTrace) -> Option<Self> {
+        match &trace.state_update {
+            Some([Some(old), Some(new)]) => {
+                return Some(
+                    if old.value == HexBytes::default() && new.value == old.value {
+                        Self::StorageDoesNotExist
+                    } else {
+                        Self::StorageChanged
+                    },
+                );
+            }
+            Some([None, None]) | None => {}
+            Some(_) => return None,
+        }
+        if trace.state_key.is_none()
+            && trace.common_state_root.is_none()
+            && trace.state_path[1].is_some()
+        {
+            return Some(Self::StorageWiped);
+        }
+
+        Some(match &trace.account_update {
+            [None, None] => Self::AccountDoesNotExist,
+            [Some(_), None] => Self::AccountDestructed,
+            [None, Some(new)] => {
+                let set_fields = [
+                    new.nonce != 0,
+                    !new.balance.is_zero(),
+                    !new.code_hash.is_zero(),
+                    !new.poseidon_code_hash.is_zero(),
+                    new.code_size != 0,
+                ];
+                match set_fields {
+                    [true, false, false, false, false] => Self::NonceChanged,
+                    [false, true, false, false, false] => Self::BalanceChanged,
+                    _ => Self::AccountCreated,
+                }
+            }
+            [Some(old), Some(new)] => {
+                let changed_fields = [
+                    old.nonce != new.nonce,
+                    old.balance != new.balance,
+                    old.code_hash != new.code_hash,
+                    old.poseidon_code_hash != new.poseidon_code_hash,
+                    old.code_size != new.code_size,
+                ];
+                match changed_fields {
+                    [false, false, false, false, false] => Self::NonceRead,
+                    [true, false, false, false, false] => Self::NonceChanged,
+                    [false, true, false, false, false] => Self::BalanceChanged,
+                    [true, true, false, false, false]
+                    | [true, false, true, false, false]
+                    | [true, false, false, true, false]
+                    | [true, false, false, false, true] => Self::NonceAndFieldChanged,
+                    [false, false, true, false, false] => Self::CodeHashExists,
+                    [false, false, false, true, false] => Self::PoseidonCodeHashExists,
+                    [false, false, false, false, true] => Self::CodeSizeExists,
+                    _ => return None,
+                }
+            }
+        })
+    }
+}
+
+/// Row of the mpt table, with the values that `MptUpdateLookup::lookup` exposes for a start row.
+/// Roots are the rlc of their big endian bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MptTableRow {
+    pub address: Fr,
+    pub storage_key_rlc: Fr,
+    pub proof_type: MPTProofType,
+    pub new_root: Fr,
+    pub old_root: Fr,
+    pub new_value: Fr,
+    pub old_value: Fr,
+}
+
+impl MptTableRow {
+    /// Values in the order of `MptUpdateLookup::lookup`.
+    pub fn values(&self) -> [Fr; 7] {
+        [
+            self.address,
+            self.storage_key_rlc,
+            Fr::from(self.proof_type as u64),
+            self.new_root,
+            self.old_root,
+            self.new_value,
+            self.old_value,
+        ]
+    }
+}
+
+impl Claim {
+    /// Rows of the mpt table for the claim. NonceAndField claims also expose a root that is only
+    /// known from their proof, so use `Proof::mpt_table_rows` for them.
+    pub fn mpt_table_rows(&self, randomness: Fr) -> Result<Vec<MptTableRow>, TraceError> {
+        match self.kind {
+            ClaimKind::NonceAndField { .. } => Err(TraceError::Unsupported {
+                proof_type: MPTProofType::from(*self),
+                reason: "rows depend on the proof",
+            }),
+            _ => Ok(vec![self.mpt_table_row(randomness)]),
+        }
+    }
+
+    fn mpt_table_row(&self, randomness: Fr) -> MptTableRow {
+        let storage_key_rlc = match self.kind {
+            // Key/value proofs expose their key instead of the rlc of a storage key.
+            ClaimKind::KeyValue { key, .. } => key,
+            _ => word_rlc(self.storage_key(), randomness),
+        };
+        MptTableRow {
+            address: rlc(&self.address.0, Fr::from(256)),
+            storage_key_rlc,
+            proof_type: MPTProofType::from(*self),
+            new_root: fr_rlc(self.new_root, randomness),
+            old_root: fr_rlc(self.old_root, randomness),
+            new_value: self.new_value_assignment(randomness),
+            old_value: self.old_value_assignment(randomness),
+        }
+    }
+}
+
+impl Proof {
+    /// Rows of the mpt table for the proof, in the order its updates are applied.
+    pub fn mpt_table_rows(&self, randomness: Fr) -> Vec<MptTableRow> {
+        let row = self.claim.mpt_table_row(randomness);
+        match (self.claim.kind, self.intermediate_root()) {
+            (ClaimKind::NonceAndField { field, .. }, Some(intermediate_root)) => {
+                // The nonce is updated first. In the circuit, the start row of the update of the
+                // other field comes first, but the lookup does not depend on the order.
+                let intermediate_root = fr_rlc(intermediate_root, randomness);
+                let field_claim = Claim {
+                    kind: field.claim_kind(),
+                    ..self.claim
+                };
+                vec![
+                    MptTableRow {
+                        proof_type: MPTProofType::NonceChanged,
+                        new_root: intermediate_root,
+                        ..row
+                    },
+                    MptTableRow {
+                        proof_type: MPTProofType::from(field.field()),
+                        old_root: intermediate_root,
+                        new_value: field_claim.new_value_assignment(randomness),
+                        old_value: field_claim.old_value_assignment(randomness),
+                        ..row
+                    },
+                ]
+            }
+            _ => vec![row],
+        }
+    }
+}
+
+fn word_rlc(word: U256, randomness: Fr) -> Fr {
+    rlc(&u256_to_big_endian(&word), randomness)
+}
+
+fn fr_rlc(x: Fr, randomness: Fr) -> Fr {
+    let mut bytes = x.to_bytes();
+    bytes.reverse();
+    rlc(&bytes, randomness)
+}
@@ -313,6 +313,9 @@ pub struct AccountData {
     /// codeSize
     #[serde(default)]
     pub code_size: u64,
+    /// storageRoot, taken from the storage path or common state root if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_root: Option<Hash>,
 }
 
 /// struct in SMTTrace
@@ -348,3 +351,14 @@ pub struct SMTTrace {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub state_update: Option<[Option<StateData>; 2]>,
 }
+
+/// represent an updating on a generic key/value SMT, whose leaves are (key, value hash) pairs
+/// rather than accounts
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all(deserialize = "camelCase", serialize = "camelCase"))]
+pub struct KeyValueTrace {
+    /// key of the leaf, which is also its path in the trie
+    pub key: Hash,
+    /// SMTPath for the key before and after the update
+    pub path: [SMTPath; 2],
+}
@@ -0,0 +1,181 @@
+//! World state which turns account and storage operations into the traces witnessing them
+//!
+use crate::{
+    serde::{AccountData, SMTTrace},
+    types::TraceError,
+    util::{fr_to_biguint, u256_to_biguint},
+    zktrie::AccountTrie,
+    MPTProofType,
+};
+use ethers_core::types::{Address, U256};
+use halo2_proofs::halo2curves::bn256::Fr;
+
+/// Update of a single account field or storage slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Nonce {
+        address: Address,
+        nonce: u64,
+    },
+    Balance {
+        address: Address,
+        balance: U256,
+    },
+    CodeHash {
+        address: Address,
+        code_hash: U256,
+    },
+    PoseidonCodeHash {
+        address: Address,
+        poseidon_code_hash: Fr,
+    },
+    CodeSize {
+        address: Address,
+        code_size: u64,
+    },
+    Storage {
+        address: Address,
+        key: U256,
+        value: U256,
+    },
+    Destroy {
+        address: Address,
+    },
+}
+
+impl Operation {
+    pub fn address(&self) -> Address {
+        match *self {
+            Self::Nonce { address, .. }
+            | Self::Balance { address, .. }
+            | Self::CodeHash { address, .. }
+            | Self::PoseidonCodeHash { address, .. }
+            | Self::CodeSize { address, .. }
+            | Self::Storage { address, .. }
+            | Self::Destroy { address } => address,
+        }
+    }
+
+    /// Proof type of the trace for the operation. Writing 0 to an empty storage slot is proven
+    /// with StorageDoesNotExist instead, and writing 0 to a field of an account which does not
+    /// exist with AccountDoesNotExist.
+    pub fn proof_type(&self) -> MPTProofType {
+        match self {
+            Self::Nonce { .. } => MPTProofType::NonceChanged,
+            Self::Balance { .. } => MPTProofType::BalanceChanged,
+            Self::CodeHash { .. } => MPTProofType::CodeHashExists,
+            Self::PoseidonCodeHash { .. } => MPTProofType::PoseidonCodeHashExists,
+            Self::CodeSize { .. } => MPTProofType::CodeSizeExists,
+            Self::Storage { .. } => MPTProofType::StorageChanged,
+            Self::Destroy { .. } => MPTProofType::AccountDestructed,
+        }
+    }
+
+    fn set_field(&self, account: &mut AccountData) {
+        match *self {
+            Self::Nonce { nonce, .. } => account.nonce = nonce,
+            Self::Balance { balance, .. } => account.balance = u256_to_biguint(balance),
+            Self::CodeHash { code_hash, .. } => account.code_hash = u256_to_biguint(code_hash),
+            Self::PoseidonCodeHash {
+                poseidon_code_hash, ..
+            } => account.poseidon_code_hash = fr_to_biguint(poseidon_code_hash),
+            Self::CodeSize { code_size, .. } => account.code_size = code_size,
+            Self::Storage { .. } | Self::Destroy { .. } => unreachable!("not an account field"),
+        }
+    }
+}
+
+/// Accounts and their storage, held in a native zktrie. Operations are applied one at a time, and
+/// the traces they return are chained, with the old root of each trace the new root of the
+/// previous one, which is the order `TestCircuit::new` and the mpt table expect.
+#[derive(Clone, Debug, Default)]
+pub struct StateDb {
+    trie: AccountTrie,
+}
+
+impl StateDb {
+    pub fn root(&self) -> Fr {
+        self.trie.root()
+    }
+
+    pub fn account(&self, address: Address) -> Option<&AccountData> {
+        self.trie.account(address)
+    }
+
+    pub fn storage(&self, address: Address, key: U256) -> U256 {
+        self.trie.storage(address, key)
+    }
+
+    /// Applies the operation and returns its trace. The state is unchanged if the operation cannot
+    /// be proven, e.g. when it destroys an account which does not exist.
+    pub fn apply(&mut self, operation: Operation) -> Result<(MPTProofType, SMTTrace), TraceError> {
+        let proof_type = operation.proof_type();
+        let inconsistent = |reason| TraceError::InconsistentProofType { proof_type, reason };
+        let address = operation.address();
+        let old_account = self.trie.account(address).cloned();
+
+        let trace = match operation {
+            Operation::Storage { key, value, .. } => {
+                if value.is_zero() && self.trie.storage(address, key).is_zero() {
+                    return Ok((
+                        MPTProofType::StorageDoesNotExist,
+                        self.trie.read_storage(address, key),
+                    ));
+                }
+                self.trie.set_storage(address, key, value)
+            }
+            Operation::Destroy { .. } => {
+                if old_account.is_none() {
+                    return Err(inconsistent("cannot destruct empty account"));
+                }
+                self.trie.delete_account(address)
+            }
+            _ => {
+                let mut new_account = old_account.clone().unwrap_or_default();
+                operation.set_field(&mut new_account);
+                if new_account.balance.bits() > 248 {
+                    return Err(TraceError::BalanceOutOfRange);
+                }
+                if old_account.is_none() {
+                    // An account which does not exist can only be read, or created by setting its
+                    // nonce or balance.
+                    if new_account == AccountData::default() {
+                        return Ok((
+                            MPTProofType::AccountDoesNotExist,
+                            self.trie.read_account(address),
+                        ));
+                    }
+                    if !matches!(
+                        operation,
+                        Operation::Nonce { .. } | Operation::Balance { .. }
+                    ) {
+                        return Err(TraceError::Unsupported {
+                            proof_type,
+                            reason: "nonce or balance must be first field set on empty account",
+                        });
+                    }
+                }
+                self.trie.set_account(address, new_account)
+            }
+        };
+        Ok((proof_type, trace))
+    }
+
+    /// Applies the operations in order. If one of them cannot be proven, the state is restored to
+    /// what it was before the first one, so that it never holds changes whose traces were not
+    /// returned.
+    pub fn apply_all(
+        &mut self,
+        operations: impl IntoIterator<Item = Operation>,
+    ) -> Result<Vec<(MPTProofType, SMTTrace)>, TraceError> {
+        let snapshot = self.trie.clone();
+        operations
+            .into_iter()
+            .map(|operation| self.apply(operation))
+            .collect::<Result<_, _>>()
+            .map_err(|e| {
+                self.trie = snapshot;
+                e
+            })
+    }
+}
@@ -0,0 +1,234 @@
+//! Streaming readers for large trace files, which yield proofs one at a time instead of holding the
+//! whole file in memory
+//!
+use crate::{
+    mpt::{AssignError, RowBudget},
+    serde::SMTTrace,
+    types::{Proof, TraceError},
+    MPTProofType,
+};
+use serde::Deserialize;
+use std::io::BufRead;
+
+/// Errors reading a stream of traces.
+#[derive(Debug, thiserror::Error)]
+pub enum StreamError {
+    #[error(transparent)]
+    /// error reading the underlying stream
+    Io(#[from] std::io::Error),
+    #[error("entry {index}: {source}")]
+    /// entry is not a (MPTProofType, SMTTrace) pair
+    Json {
+        /// index of the entry in the stream
+        index: usize,
+        /// underlying error
+        source: serde_json::Error,
+    },
+    #[error("entry {index}: expected {expected}")]
+    /// JSON array is malformed between entries
+    Syntax {
+        /// index of the entry in the stream
+        index: usize,
+        /// what was expected instead
+        expected: &'static str,
+    },
+    #[error("entry {index}: {source}")]
+    /// trace cannot be converted into a proof
+    Trace {
+        /// index of the entry in the stream
+        index: usize,
+        /// underlying error
+        source: TraceError,
+    },
+    #[error("entry {index}: {source}")]
+    /// proof does not fit into the circuit on its own
+    Assign {
+        /// index of the entry in the stream
+        index: usize,
+        /// underlying error
+        source: AssignError,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// one JSON array of all the entries, as in benches/traces.json
+    Array { started: bool },
+    /// one entry per line
+    Lines,
+}
+
+/// Iterator over the (MPTProofType, SMTTrace) entries of a JSON array or NDJSON stream, which
+/// parses one entry at a time. It ends after the first error.
+pub struct TraceReader<R> {
+    reader: R,
+    format: Format,
+    index: usize,
+    done: bool,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    /// Reader of a stream holding a single JSON array of entries.
+    pub fn json_array(reader: R) -> Self {
+        Self::new(reader, Format::Array { started: false })
+    }
+
+    /// Reader of a stream holding one entry per line.
+    pub fn ndjson(reader: R) -> Self {
+        Self::new(reader, Format::Lines)
+    }
+
+    fn new(reader: R, format: Format) -> Self {
+        Self {
+            reader,
+            format,
+            index: 0,
+            done: false,
+        }
+    }
+
+    /// Converts the entries into proofs as they are read.
+    pub fn proofs(self) -> impl Iterator<Item = Result<Proof, StreamError>> {
+        self.enumerate().map(|(index, entry)| {
+            let (proof_type, trace) = entry?;
+            Proof::try_from((&proof_type, &trace))
+                .map_err(|source| StreamError::Trace { index, source })
+        })
+    }
+
+    // Skips whitespace and returns the next byte without consuming it.
+    fn peek(&mut self) -> Result<Option<u8>, StreamError> {
+        loop {
+            let next = self.reader.fill_buf()?.first().copied();
+            match next {
+                Some(byte) if byte.is_ascii_whitespace() => self.reader.consume(1),
+                _ => return Ok(next),
+            }
+        }
+    }
+
+    fn expect(&mut self, byte: u8, expected: &'static str) -> Result<(), StreamError> {
+        if self.peek()? != Some(byte) {
+            return Err(self.syntax_error(expected));
+        }
+        self.reader.consume(1);
+        Ok(())
+    }
+
+    fn syntax_error(&self, expected: &'static str) -> StreamError {
+        StreamError::Syntax {
+            index: self.index,
+            expected,
+        }
+    }
+
+    fn read_entry(&mut self) -> Result<Option<(MPTProofType, SMTTrace)>, StreamError> {
+        match self.format {
+            Format::Array { started } => {
+                if !started {
+                    self.expect(b'[', "start of array")?;
+                    self.format = Format::Array { started: true };
+                }
+                match self.peek()? {
+                    Some(b']') => {
+                        self.reader.consume(1);
+                        if self.peek()?.is_some() {
+                            return Err(self.syntax_error("end of stream after array"));
+                        }
+                        return Ok(None);
+                    }
+                    Some(b',') if started => self.reader.consume(1),
+                    Some(_) if !started => {}
+                    _ => return Err(self.syntax_error("',' or ']'")),
+                }
+            }
+            Format::Lines => {
+                if self.peek()?.is_none() {
+                    return Ok(None);
+                }
+            }
+        }
+        // Entries are JSON arrays, so the deserializer stops at their closing bracket without
+        // reading ahead into the next entry.
+        let index = self.index;
+        let mut deserializer = serde_json::Deserializer::from_reader(&mut self.reader);
+        let entry = <(MPTProofType, SMTTrace)>::deserialize(&mut deserializer)
+            .map_err(|source| StreamError::Json { index, source })?;
+        self.index += 1;
+        Ok(Some(entry))
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<(MPTProofType, SMTTrace), StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let entry = self.read_entry().transpose();
+        self.done = !matches!(entry, Some(Ok(_)));
+        entry
+    }
+}
+
+/// Iterator cutting a stream of proofs into batches which fit into a circuit with n_rows rows,
+/// using a `RowBudget` so that each proof is only counted once.
+pub struct Batches<I> {
+    proofs: I,
+    budget: RowBudget,
+    index: usize,
+    pending: Option<Result<Proof, StreamError>>,
+}
+
+impl<I: Iterator<Item = Result<Proof, StreamError>>> Batches<I> {
+    pub fn new(proofs: I, n_rows: usize) -> Self {
+        Self {
+            proofs,
+            budget: RowBudget::new(n_rows),
+            index: 0,
+            pending: None,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<Proof, StreamError>>> Iterator for Batches<I> {
+    type Item = Result<Vec<Proof>, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut batch = vec![];
+        self.budget.reset();
+        while let Some(proof) = self.pending.take().or_else(|| self.proofs.next()) {
+            // Errors are returned after the batch read before them.
+            let proof = match proof {
+                Ok(proof) => proof,
+                Err(e) if batch.is_empty() => return Some(Err(e)),
+                Err(e) => {
+                    self.pending = Some(Err(e));
+                    break;
+                }
+            };
+            match self.budget.add(&proof) {
+                Ok(()) => {
+                    batch.push(proof);
+                    self.index += 1;
+                }
+                Err(source) if batch.is_empty() => {
+                    return Some(Err(StreamError::Assign {
+                        index: self.index,
+                        source,
+                    }))
+                }
+                Err(_) => {
+                    self.pending = Some(Ok(proof));
+                    break;
+                }
+            }
+        }
+        if batch.is_empty() {
+            None
+        } else {
+            Some(Ok(batch))
+        }
+    }
+}
@@ -1,13 +1,41 @@
-use crate::{circuit::TestCircuit, serde::SMTTrace, types::Proof, MPTProofType, MptCircuitConfig};
+use crate::{
+    circuit::TestCircuit,
+    codec::{self, CodecError},
+    constraint_builder::{Query, SecondPhaseAdviceColumn, SelectorColumn},
+    gadgets::poseidon::PoseidonTable,
+    genesis::Genesis,
+    hash_traces,
+    legacy::{self, LegacyStorage},
+    serde::{AccountData, HashType, HexBytes, KeyValueTrace, Row, SMTPath, SMTTrace},
+    state_db::{Operation, StateDb},
+    stream::{Batches, StreamError, TraceReader},
+    types::{path_shape, Claim, ClaimKind, HashDomain, Proof, TraceError, VerificationError},
+    util::{
+        empty_keccak_code_hash, empty_poseidon_code_hash, fr, keccak_code_hash, poseidon_code_hash,
+        Bit,
+    },
+    zktrie::{
+        node::{self, decode_proof, encode_proof, EthProof, NodeError},
+        AccountTrie,
+    },
+    MPTProofType, MptCircuitConfig,
+};
 use ethers_core::types::{Address, U256};
 use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner, Value},
     dev::MockProver,
     halo2curves::bn256::{Bn256, Fr},
-    plonk::{keygen_vk, Circuit, ConstraintSystem},
+    plonk::{
+        keygen_vk, Challenge, Circuit, ConstraintSystem, Error, FirstPhase, SecondPhase,
+        VirtualCells,
+    },
     poly::kzg::commitment::ParamsKZG,
 };
+use itertools::Itertools;
 use mpt_zktrie::state::{builder::HASH_SCHEME_DONE, witness::WitnessGenerator, ZktrieState};
+use num_bigint::BigUint;
 use rand_chacha::rand_core::SeedableRng;
+use strum::IntoEnumIterator;
 
 const N_ROWS: usize = 8 * 256 + 1;
 const STORAGE_ADDRESS: Address = Address::repeat_byte(1);
@@ -287,6 +315,366 @@ fn existing_account_balance_update() {
     mock_prove(vec![(MPTProofType::BalanceChanged, trace)]);
 }
 
+#[test]
+fn malformed_traces() {
+    let trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/existing_account_balance_update.json")).unwrap();
+    let try_proof =
+        |proof_type: MPTProofType, trace: &SMTTrace| Proof::try_from((&proof_type, trace));
+
+    assert!(try_proof(MPTProofType::BalanceChanged, &trace).is_ok());
+    assert!(matches!(
+        try_proof(MPTProofType::NonceChanged, &trace),
+        Err(TraceError::InconsistentProofType { .. })
+    ));
+
+    let mut non_canonical_root = trace.clone();
+    non_canonical_root.account_path[0].root = HexBytes([0xff; 32]);
+    assert!(matches!(
+        try_proof(MPTProofType::BalanceChanged, &non_canonical_root),
+        Err(TraceError::NonCanonical(_))
+    ));
+
+    let mut with_storage_root = trace.clone();
+    let storage_root = with_storage_root.common_state_root;
+    with_storage_root.account_update[0]
+        .as_mut()
+        .unwrap()
+        .storage_root = storage_root;
+    assert!(try_proof(MPTProofType::BalanceChanged, &with_storage_root).is_ok());
+    with_storage_root.account_update[1]
+        .as_mut()
+        .unwrap()
+        .storage_root = Some(HexBytes([1; 32]));
+    assert!(matches!(
+        try_proof(MPTProofType::BalanceChanged, &with_storage_root),
+        Err(TraceError::RootMismatch { .. })
+    ));
+
+    let mut large_balance = trace.clone();
+    large_balance.account_update[1].as_mut().unwrap().balance = BigUint::from(1u8) << 248;
+    assert!(matches!(
+        try_proof(MPTProofType::BalanceChanged, &large_balance),
+        Err(TraceError::BalanceOutOfRange)
+    ));
+
+    let mut wrong_address = trace;
+    wrong_address.address = HexBytes([0xab; 20]);
+    assert!(matches!(
+        try_proof(MPTProofType::BalanceChanged, &wrong_address),
+        Err(TraceError::KeyHashMismatch { .. })
+    ));
+}
+
+#[test]
+fn malformed_trie_paths() {
+    let trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/existing_account_balance_update.json")).unwrap();
+    let try_proof = |trace: &SMTTrace| Proof::try_from((&MPTProofType::BalanceChanged, trace));
+
+    // Nodes in the common part of the paths have the same sibling.
+    let mut sibling_changed = trace.clone();
+    sibling_changed.account_path[1].path[1].sibling = HexBytes([1; 32]);
+    assert!(matches!(
+        try_proof(&sibling_changed),
+        Err(TraceError::SiblingMismatch {
+            name: "account path",
+            depth: 1,
+        })
+    ));
+
+    // Node types only change where a leaf is inserted or deleted, which needs paths of different
+    // lengths. Both node types fit the shape of their path, since the sibling is not empty.
+    let mut node_type_changed = trace.clone();
+    let node = &mut node_type_changed.account_path[1].path[0];
+    assert_eq!(node.node_type, u64::from(HashDomain::Branch3));
+    assert!(fr(trace.account_key).bit(0));
+    node.node_type = HashDomain::Branch1.into();
+    assert!(matches!(
+        try_proof(&node_type_changed),
+        Err(TraceError::NodeTypeMismatch {
+            name: "account path",
+            depth: 0,
+        })
+    ));
+
+    // Key/value traces have no path shape check before their trie rows are built.
+    assert!(Proof::try_from(key_value_trace(trace.clone())).is_ok());
+    let mut unknown_node_type = key_value_trace(trace.clone());
+    for path in &mut unknown_node_type.path {
+        path.path[2].node_type = 100;
+    }
+    assert!(matches!(
+        Proof::try_from(unknown_node_type),
+        Err(TraceError::InvalidNodeType {
+            name: "path",
+            depth: 2,
+            node_type: 100,
+        })
+    ));
+    let mut non_canonical_key = key_value_trace(trace);
+    non_canonical_key.key = HexBytes([0xff; 32]);
+    assert!(matches!(
+        Proof::try_from(non_canonical_key),
+        Err(TraceError::NonCanonical("key"))
+    ));
+}
+
+#[test]
+fn verify_proofs() {
+    let trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/existing_account_balance_update.json")).unwrap();
+    let proof = Proof::from((MPTProofType::BalanceChanged, trace));
+    assert!(proof.verify().is_ok());
+
+    let mut wrong_root = proof.clone();
+    wrong_root.claim.new_root += Fr::one();
+    assert!(matches!(
+        wrong_root.verify(),
+        Err(VerificationError::RootMismatch { .. })
+    ));
+
+    let mut wrong_balance = proof;
+    if let ClaimKind::Balance { new, .. } = &mut wrong_balance.claim.kind {
+        *new = new.map(|balance| balance + 1);
+    }
+    assert!(matches!(
+        wrong_balance.verify(),
+        Err(VerificationError::ClaimMismatch(_))
+    ));
+}
+
+#[test]
+fn mpt_table_rows() {
+    let trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/existing_account_balance_update.json")).unwrap();
+    let proof = Proof::from((MPTProofType::BalanceChanged, trace));
+    let values = match proof.claim.kind {
+        ClaimKind::Balance { old, new } => [old, new],
+        _ => unreachable!(),
+    };
+    let roots = [proof.claim.old_root, proof.claim.new_root];
+    let claim = Claim::new(
+        proof.claim.address,
+        MPTProofType::BalanceChanged,
+        U256::zero(),
+        values,
+        roots,
+    )
+    .unwrap();
+
+    let randomness = Fr::from(123456);
+    assert_eq!(
+        claim.mpt_table_rows(randomness).unwrap(),
+        proof.mpt_table_rows(randomness)
+    );
+
+    assert!(matches!(
+        Claim::new(
+            proof.claim.address,
+            MPTProofType::NonceRead,
+            U256::zero(),
+            [Some(U256::one()), Some(U256::from(2))],
+            roots,
+        ),
+        Err(TraceError::InconsistentProofType { .. })
+    ));
+}
+
+// Circuit with a table of the native mpt table rows of table_proofs, which looks up the rows that
+// MptCircuitConfig::lookup_exprs exposes for proofs in the table, and the other way around.
+#[derive(Clone, Default)]
+struct MptTableCircuit {
+    proofs: Vec<Proof>,
+    table_proofs: Vec<Proof>,
+}
+
+impl Circuit<Fr> for MptTableCircuit {
+    type Config = (
+        PoseidonTable,
+        MptCircuitConfig,
+        Challenge,
+        SelectorColumn,
+        [SecondPhaseAdviceColumn; 7],
+    );
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::default()
+    }
+
+    fn configure(cs: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let poseidon = PoseidonTable::configure(cs);
+        let challenge = cs.challenge_usable_after(FirstPhase);
+        let mpt_circuit_config = MptCircuitConfig::configure(cs, challenge, &poseidon);
+
+        let selector = SelectorColumn(cs.fixed_column());
+        let table = [0; 7].map(|_| SecondPhaseAdviceColumn(cs.advice_column_in(SecondPhase)));
+        let table_exprs = |meta: &mut VirtualCells<'_, Fr>| {
+            std::iter::once(Query::from(selector.current()))
+                .chain(table.map(SecondPhaseAdviceColumn::current))
+                .map(|query| query.run(meta))
+                .collect_vec()
+        };
+        cs.lookup_any("native mpt table row is in circuit", |meta| {
+            let circuit_exprs = mpt_circuit_config.lookup_exprs(meta);
+            table_exprs(meta)
+                .into_iter()
+                .zip_eq(circuit_exprs)
+                .collect()
+        });
+        cs.lookup_any("circuit mpt table row is native", |meta| {
+            let circuit_exprs = mpt_circuit_config.lookup_exprs(meta);
+            circuit_exprs
+                .into_iter()
+                .zip_eq(table_exprs(meta))
+                .collect()
+        });
+
+        (poseidon, mpt_circuit_config, challenge, selector, table)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let (poseidon, mpt_circuit_config, challenge, selector, table) = config;
+        mpt_circuit_config.assign(&mut layouter, &self.proofs, N_ROWS)?;
+        layouter.assign_region(
+            || "load poseidon table",
+            |mut region| {
+                poseidon.load(&mut region, &hash_traces(&self.proofs));
+                Ok(())
+            },
+        )?;
+
+        let randomness = layouter.get_challenge(challenge);
+        let n_rows = self
+            .table_proofs
+            .iter()
+            .map(|proof| proof.mpt_table_rows(Fr::one()).len())
+            .sum();
+        let rows = randomness
+            .map(|randomness| {
+                self.table_proofs
+                    .iter()
+                    .flat_map(|proof| proof.mpt_table_rows(randomness))
+                    .map(|row| row.values())
+                    .collect_vec()
+            })
+            .transpose_vec(n_rows);
+        // Rows which are not the start of a proof expose all zeros, and the padding proofs expose
+        // that the address 0 does not exist in an empty trie.
+        let mut padding_row = [Fr::zero(); 7];
+        padding_row[2] = Fr::from(MPTProofType::AccountDoesNotExist as u64);
+        layouter.assign_region(
+            || "native mpt table",
+            |mut region| {
+                for (offset, row) in [Value::known([Fr::zero(); 7]), Value::known(padding_row)]
+                    .into_iter()
+                    .chain(rows.iter().copied())
+                    .enumerate()
+                {
+                    selector.enable(&mut region, offset);
+                    for (column, value) in table.iter().zip_eq(row.transpose_array()) {
+                        column.assign(&mut region, offset, value);
+                    }
+                }
+                Ok(())
+            },
+        )
+    }
+}
+
+#[test]
+fn mpt_table_rows_match_circuit() {
+    let mut state_db = initial_state_db();
+    let address = Address::repeat_byte(3);
+    let mut proofs: Vec<_> = state_db
+        .apply_all([
+            Operation::Balance {
+                address,
+                balance: U256::from(7),
+            },
+            Operation::CodeSize {
+                address,
+                code_size: 45,
+            },
+            Operation::Storage {
+                address: STORAGE_ADDRESS,
+                key: U256::from(45),
+                value: U256::from(3),
+            },
+            // nonexistence proofs
+            Operation::Storage {
+                address: STORAGE_ADDRESS,
+                key: U256::from(307),
+                value: U256::zero(),
+            },
+            Operation::Balance {
+                address: Address::zero(),
+                balance: U256::zero(),
+            },
+        ])
+        .unwrap()
+        .into_iter()
+        .map(Proof::from)
+        .collect();
+
+    // Claims of proofs other than NonceAndFieldChanged have the same rows as their proofs.
+    let randomness = Fr::from(123456);
+    for proof in &proofs {
+        assert_eq!(
+            proof.claim.mpt_table_rows(randomness).unwrap(),
+            proof.mpt_table_rows(randomness)
+        );
+    }
+
+    let [nonce_trace, balance_trace] = nonce_and_balance_traces(&mut state_db, address);
+    proofs.push(Proof::from((
+        MPTProofType::NonceAndFieldChanged,
+        combined_trace(&nonce_trace, &balance_trace),
+    )));
+    proofs.push(
+        Proof::storage_batch(storage_batch_traces(
+            &mut state_db,
+            &[(41, 20), (42, 0), (307, 7)],
+        ))
+        .unwrap(),
+    );
+    let trace = operation_traces(
+        &mut state_db,
+        [Operation::Balance {
+            address: Address::repeat_byte(20),
+            balance: U256::one(),
+        }],
+    )
+    .remove(0);
+    proofs.push(Proof::from(key_value_trace(trace)));
+
+    let circuit = MptTableCircuit {
+        proofs: proofs.clone(),
+        table_proofs: proofs.clone(),
+    };
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+
+    // The rows of each proof must be in the native table, and each native row in the circuit.
+    let missing_native_rows = MptTableCircuit {
+        proofs: proofs.clone(),
+        table_proofs: proofs[1..].to_vec(),
+    };
+    let prover = MockProver::<Fr>::run(14, &missing_native_rows, vec![]).unwrap();
+    assert!(prover.verify().is_err());
+    let missing_circuit_rows = MptTableCircuit {
+        proofs: proofs[1..].to_vec(),
+        table_proofs: proofs,
+    };
+    let prover = MockProver::<Fr>::run(14, &missing_circuit_rows, vec![]).unwrap();
+    assert!(prover.verify().is_err());
+}
+
 #[test]
 fn empty_account_type_1_balance_update() {
     let mut generator = initial_generator();
@@ -516,6 +904,175 @@ fn existing_account_poseidon_codehash_update() {
     mock_prove(vec![(MPTProofType::PoseidonCodeHashExists, trace)]);
 }
 
+#[test]
+fn existing_account_type_2_destructed() {
+    // Reversing the insertion of a type 2 empty account gives a deletion which leaves an empty
+    // node in place of the account leaf.
+    let trace: SMTTrace = serde_json::from_str(include_str!(
+        "traces/empty_account_type_2_balance_update.json"
+    ))
+    .unwrap();
+    let trace = reverse(trace);
+    assert!(
+        trace.account_update[1].is_none() && trace.account_path[1].leaf.is_none(),
+        "new account is not type 2"
+    );
+
+    let proof = Proof::from((MPTProofType::AccountDestructed, trace.clone()));
+    proof.check();
+
+    mock_prove(vec![(MPTProofType::AccountDestructed, trace)]);
+}
+
+#[test]
+fn existing_account_type_1_destructed() {
+    // Reversing the insertion of a type 1 empty account gives a deletion where the sibling leaf
+    // is moved up, so the new path is shorter than the old one.
+    for json in [
+        include_str!("traces/empty_account_type_1_balance_update.json"),
+        include_str!("traces/empty_account_type_1_nonce_update.json"),
+    ] {
+        let trace: SMTTrace = serde_json::from_str(json).unwrap();
+        let trace = reverse(trace);
+        assert!(
+            trace.account_path[0].path.len() > trace.account_path[1].path.len(),
+            "deletion does not collapse account trie"
+        );
+        assert!(
+            trace.account_update[1].is_none() && trace.account_path[1].leaf.is_some(),
+            "new account is not type 1"
+        );
+
+        let proof = Proof::from((MPTProofType::AccountDestructed, trace.clone()));
+        proof.check();
+
+        mock_prove(vec![(MPTProofType::AccountDestructed, trace)]);
+    }
+}
+
+#[test]
+fn singleton_mpt_account_destructed_collapse() {
+    assert!(*HASH_SCHEME_DONE);
+    let mut generator = WitnessGenerator::from(&ZktrieState::default());
+    generator.handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::BalanceChanged,
+        Address::repeat_byte(1),
+        U256::from(23),
+        U256::zero(),
+        None,
+    );
+    let trace = generator.handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::BalanceChanged,
+        Address::repeat_byte(2),
+        U256::from(15),
+        U256::zero(),
+        None,
+    );
+    let json = serde_json::to_string_pretty(&trace).unwrap();
+    let trace: SMTTrace = serde_json::from_str(&json).unwrap();
+
+    // Deleting one of two accounts leaves a trie whose root is the remaining account leaf.
+    mock_prove(vec![(MPTProofType::AccountDestructed, reverse(trace))]);
+}
+
+#[test]
+fn singleton_mpt_account_destructed() {
+    assert!(*HASH_SCHEME_DONE);
+    let mut generator = WitnessGenerator::from(&ZktrieState::default());
+    let trace = generator.handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::BalanceChanged,
+        Address::repeat_byte(2),
+        U256::from(1231412),
+        U256::zero(),
+        None,
+    );
+    let json = serde_json::to_string_pretty(&trace).unwrap();
+    let trace: SMTTrace = serde_json::from_str(&json).unwrap();
+
+    mock_prove(vec![(MPTProofType::AccountDestructed, reverse(trace))]);
+}
+
+// Traces of the operations applied in order to the state db.
+fn operation_traces(
+    state_db: &mut StateDb,
+    operations: impl IntoIterator<Item = Operation>,
+) -> Vec<SMTTrace> {
+    state_db
+        .apply_all(operations)
+        .unwrap()
+        .into_iter()
+        .map(|(_, trace)| trace)
+        .collect()
+}
+
+// Convert a trace from the witness generator into an SMTTrace.
+fn witness_generator_trace(trace: impl ::serde::Serialize) -> SMTTrace {
+    serde_json::from_str(&serde_json::to_string(&trace).unwrap()).unwrap()
+}
+
+// Produce a trace creating an account with several non-zero fields by setting the fields one at a
+// time and combining the old path of the first update with the new path of the last one.
+fn account_created_trace(state_db: &mut StateDb, address: Address) -> SMTTrace {
+    let traces = operation_traces(
+        state_db,
+        [
+            Operation::Nonce { address, nonce: 3 },
+            Operation::Balance {
+                address,
+                balance: U256::from(2342114),
+            },
+            Operation::CodeHash {
+                address,
+                code_hash: U256([1111, u64::MAX, 444, 555]),
+            },
+            Operation::PoseidonCodeHash {
+                address,
+                poseidon_code_hash: Fr::from_raw([u64::MAX, u64::MAX, u64::MAX, 2342]),
+            },
+            Operation::CodeSize {
+                address,
+                code_size: 100,
+            },
+        ],
+    );
+    combined_trace(traces.first().unwrap(), traces.last().unwrap())
+}
+
+#[test]
+fn empty_account_type_1_created() {
+    let trace = account_created_trace(&mut initial_state_db(), Address::zero());
+    assert!(
+        trace.account_update[0].is_none() && trace.account_path[0].leaf.is_some(),
+        "old account is not type 1"
+    );
+
+    let proof = Proof::from((MPTProofType::AccountCreated, trace.clone()));
+    proof.check();
+
+    mock_prove(vec![(MPTProofType::AccountCreated, trace)]);
+}
+
+#[test]
+fn empty_account_type_2_created() {
+    let trace = account_created_trace(&mut initial_state_db(), Address::repeat_byte(20));
+    assert!(
+        trace.account_update[0].is_none() && trace.account_path[0].leaf.is_none(),
+        "old account is not type 2"
+    );
+
+    let proof = Proof::from((MPTProofType::AccountCreated, trace.clone()));
+    proof.check();
+
+    mock_prove(vec![(MPTProofType::AccountCreated, trace)]);
+}
+
+#[test]
+fn empty_mpt_account_created() {
+    let trace = account_created_trace(&mut StateDb::default(), Address::repeat_byte(2));
+
+    mock_prove(vec![(MPTProofType::AccountCreated, trace)]);
+}
+
 #[test]
 fn existing_storage_update() {
     let mut generator = initial_storage_generator();
@@ -764,130 +1321,579 @@ fn empty_storage_type_1_update_c() {
 }
 
 #[test]
-fn multiple_updates() {
-    env_logger::init();
-    let witness = vec![
-        (
-            MPTProofType::StorageChanged,
-            serde_json::from_str(include_str!("traces/empty_storage_type_1_update_c.json"))
-                .unwrap(),
-        ),
-        (
-            MPTProofType::CodeHashExists,
-            serde_json::from_str(include_str!(
-                "traces/existing_account_keccak_codehash_update.json"
-            ))
-            .unwrap(),
-        ),
-        (
-            MPTProofType::BalanceChanged,
-            serde_json::from_str(include_str!(
-                "traces/empty_account_type_2_balance_update.json"
-            ))
-            .unwrap(),
-        ),
-        (
-            MPTProofType::AccountDoesNotExist,
-            serde_json::from_str(include_str!("traces/empty_account_type_1.json")).unwrap(),
-        ),
-    ];
-    mock_prove(witness);
-}
-
-#[test]
-fn empty_storage_trie() {
+fn empty_account_type_1_storage_update() {
     let mut generator = initial_generator();
     let trace = generator.handle_new_state(
         mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
-        STORAGE_ADDRESS,
-        U256::from(324123123u64),
+        Address::zero(),
+        U256::from(20),
         U256::zero(),
-        Some(U256::from(3)),
+        Some(U256::from(40)),
     );
-
     let json = serde_json::to_string_pretty(&trace).unwrap();
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
+    assert!(
+        trace.account_update[0].is_none() && trace.account_path[0].leaf.is_some(),
+        "old account is not type 1"
+    );
 
-    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
-    insertion_proof.check();
-    mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
+    let proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    proof.check();
 
-    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
-    deletion_proof.check();
-    mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
+    mock_prove(vec![(MPTProofType::StorageChanged, trace)]);
 }
 
 #[test]
-fn singleton_storage_trie() {
+fn empty_account_type_2_storage_update() {
     let mut generator = initial_generator();
-    generator.handle_new_state(
-        mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
-        Address::repeat_byte(2),
-        U256::from(7),
-        U256::zero(),
-        Some(U256::from(2)),
-    );
     let trace = generator.handle_new_state(
         mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
-        Address::repeat_byte(2),
-        U256::from(4),
+        Address::repeat_byte(20),
+        U256::from(20),
         U256::zero(),
-        Some(U256::from(3)),
+        Some(U256::from(40)),
     );
-
     let json = serde_json::to_string_pretty(&trace).unwrap();
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
+    assert!(
+        trace.account_update[0].is_none() && trace.account_path[0].leaf.is_none(),
+        "old account is not type 2"
+    );
 
-    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
-    insertion_proof.check();
-    mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
+    let proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    proof.check();
 
-    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
-    deletion_proof.check();
-    mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
+    mock_prove(vec![(MPTProofType::StorageChanged, trace)]);
 }
 
 #[test]
-fn depth_1_type_1_storage() {
-    // This tests the case where the hash domain for calculating the storage root changes
-    // because of an insertion or deletion.
+fn empty_mpt_storage_update() {
+    assert!(*HASH_SCHEME_DONE);
+    let mut generator = WitnessGenerator::from(&ZktrieState::default());
+    let trace = generator.handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
+        Address::repeat_byte(2),
+        U256::from(20),
+        U256::zero(),
+        Some(U256::from(40)),
+    );
+    let json = serde_json::to_string_pretty(&trace).unwrap();
+    let trace: SMTTrace = serde_json::from_str(&json).unwrap();
 
-    let trace: SMTTrace =
-        serde_json::from_str(include_str!("traces/depth_1_type_1_storage.json")).unwrap();
-    mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
-    mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
+    mock_prove(vec![(MPTProofType::StorageChanged, trace)]);
 }
 
 #[test]
-fn depth_1_type_1_empty_storage() {
+fn existing_account_nonce_read() {
     let mut generator = initial_generator();
-    for key in [2, 10] {
-        generator.handle_new_state(
-            mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
-            Address::repeat_byte(2),
-            U256::from(7),
-            U256::zero(),
-            Some(U256::from(key)),
-        );
-    }
     let trace = generator.handle_new_state(
-        mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
-        Address::repeat_byte(2),
+        mpt_zktrie::mpt_circuits::MPTProofType::NonceChanged,
+        Address::repeat_byte(3),
         U256::zero(),
         U256::zero(),
-        Some(U256::from(3)),
+        None,
     );
-
     let json = serde_json::to_string_pretty(&trace).unwrap();
     let trace: SMTTrace = serde_json::from_str(&json).unwrap();
 
-    let proof = Proof::from((MPTProofType::StorageDoesNotExist, trace.clone()));
+    let proof = Proof::from((MPTProofType::NonceRead, trace.clone()));
     proof.check();
-    mock_prove(vec![(MPTProofType::StorageDoesNotExist, trace)]);
+
+    mock_prove(vec![(MPTProofType::NonceRead, trace)]);
 }
 
 #[test]
-fn empty_storage_type_1() {
+fn existing_account_balance_read() {
+    let mut generator = initial_generator();
+    let trace = generator.handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::BalanceChanged,
+        Address::repeat_byte(3),
+        U256::one(),
+        U256::one(),
+        None,
+    );
+    let json = serde_json::to_string_pretty(&trace).unwrap();
+    let trace: SMTTrace = serde_json::from_str(&json).unwrap();
+
+    let proof = Proof::from((MPTProofType::BalanceRead, trace.clone()));
+    proof.check();
+
+    mock_prove(vec![(MPTProofType::BalanceRead, trace)]);
+}
+
+#[test]
+fn existing_storage_read() {
+    let mut generator = initial_storage_generator();
+    let trace = generator.handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
+        STORAGE_ADDRESS,
+        U256::one(),
+        U256::one(),
+        Some(U256::from(45)),
+    );
+    let json = serde_json::to_string_pretty(&trace).unwrap();
+    let trace: SMTTrace = serde_json::from_str(&json).unwrap();
+
+    let proof = Proof::from((MPTProofType::StorageRead, trace.clone()));
+    proof.check();
+
+    mock_prove(vec![(MPTProofType::StorageRead, trace)]);
+}
+
+fn nonce_and_balance_traces(state_db: &mut StateDb, address: Address) -> [SMTTrace; 2] {
+    operation_traces(
+        state_db,
+        [
+            Operation::Nonce { address, nonce: 5 },
+            Operation::Balance {
+                address,
+                balance: U256::from(100),
+            },
+        ],
+    )
+    .try_into()
+    .unwrap()
+}
+
+fn combined_trace(first: &SMTTrace, last: &SMTTrace) -> SMTTrace {
+    let mut trace = first.clone();
+    trace.account_path[1] = last.account_path[1].clone();
+    trace.account_update[1] = last.account_update[1].clone();
+    trace
+}
+
+#[test]
+fn existing_account_nonce_and_balance_update() {
+    let [nonce_trace, balance_trace] =
+        nonce_and_balance_traces(&mut initial_state_db(), Address::repeat_byte(3));
+    let trace = combined_trace(&nonce_trace, &balance_trace);
+
+    let proof = Proof::from((MPTProofType::NonceAndFieldChanged, trace.clone()));
+    proof.check();
+    assert!(
+        proof.n_rows()
+            < Proof::from((MPTProofType::NonceChanged, nonce_trace)).n_rows()
+                + Proof::from((MPTProofType::BalanceChanged, balance_trace)).n_rows()
+    );
+
+    mock_prove(vec![(MPTProofType::NonceAndFieldChanged, trace)]);
+}
+
+#[test]
+fn single_account_nonce_and_balance_update() {
+    let address = Address::repeat_byte(3);
+    let mut state_db = StateDb::default();
+    state_db
+        .apply(Operation::Balance {
+            address,
+            balance: U256::one(),
+        })
+        .unwrap();
+    let [nonce_trace, balance_trace] = nonce_and_balance_traces(&mut state_db, address);
+    let trace = combined_trace(&nonce_trace, &balance_trace);
+    assert!(trace.account_path[0].path.is_empty());
+
+    mock_prove(vec![(MPTProofType::NonceAndFieldChanged, trace)]);
+}
+
+#[test]
+fn existing_account_nonce_and_field_updates() {
+    let address = Address::repeat_byte(3);
+    let nonce = Operation::Nonce { address, nonce: 5 };
+    for field in [
+        Operation::Balance {
+            address,
+            balance: U256::from(100),
+        },
+        Operation::CodeHash {
+            address,
+            code_hash: U256::from(2000),
+        },
+        Operation::PoseidonCodeHash {
+            address,
+            poseidon_code_hash: Fr::from(3000),
+        },
+        Operation::CodeSize {
+            address,
+            code_size: 4000,
+        },
+    ] {
+        let witness = initial_state_db().apply_all([nonce, field]).unwrap();
+        let trace = combined_trace(&witness[0].1, &witness[1].1);
+        assert_eq!(
+            MPTProofType::infer(&trace),
+            Some(MPTProofType::NonceAndFieldChanged)
+        );
+
+        let proof = Proof::from((MPTProofType::NonceAndFieldChanged, trace.clone()));
+        proof.check();
+        let rows = proof.mpt_table_rows(Fr::from(31));
+        assert_eq!(
+            rows.iter().map(|row| row.proof_type).collect_vec(),
+            [MPTProofType::NonceChanged, field.proof_type()]
+        );
+
+        mock_prove(vec![(MPTProofType::NonceAndFieldChanged, trace)]);
+    }
+}
+
+#[test]
+fn nonce_and_two_fields_update() {
+    let address = Address::repeat_byte(3);
+    let witness = initial_state_db()
+        .apply_all([
+            Operation::Nonce { address, nonce: 5 },
+            Operation::Balance {
+                address,
+                balance: U256::from(100),
+            },
+            Operation::CodeSize {
+                address,
+                code_size: 4000,
+            },
+        ])
+        .unwrap();
+    let trace = combined_trace(&witness[0].1, &witness[2].1);
+
+    assert_eq!(MPTProofType::infer(&trace), None);
+    assert!(matches!(
+        Proof::try_from((&MPTProofType::NonceAndFieldChanged, &trace)),
+        Err(TraceError::InconsistentProofType { .. })
+    ));
+
+    // The third field is proven separately instead.
+    mock_prove(vec![
+        (
+            MPTProofType::NonceAndFieldChanged,
+            combined_trace(&witness[0].1, &witness[1].1),
+        ),
+        witness[2].clone(),
+    ]);
+}
+
+// Traces writing the (key, value) pairs to the storage of STORAGE_ADDRESS.
+fn storage_batch_traces(state_db: &mut StateDb, updates: &[(u64, u64)]) -> Vec<SMTTrace> {
+    operation_traces(
+        state_db,
+        updates.iter().map(|&(key, value)| Operation::Storage {
+            address: STORAGE_ADDRESS,
+            key: U256::from(key),
+            value: U256::from(value),
+        }),
+    )
+}
+
+#[test]
+fn existing_account_storage_batch_update() {
+    let mut state_db = initial_state_db();
+    // Update, delete, and insert a storage slot.
+    let traces = storage_batch_traces(&mut state_db, &[(41, 20), (42, 0), (307, 7)]);
+
+    let proof = Proof::storage_batch(traces.clone()).unwrap();
+    proof.check();
+    // The batch is exposed as a single update from its old root to its new root.
+    let randomness = Fr::from(123456);
+    let rows = proof.mpt_table_rows(randomness);
+    assert_eq!(rows, proof.claim.mpt_table_rows(randomness).unwrap());
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].proof_type, MPTProofType::StorageBatchChanged);
+    assert!(
+        proof.n_rows()
+            < traces
+                .iter()
+                .map(|trace| Proof::from((MPTProofType::StorageChanged, trace.clone())).n_rows())
+                .sum()
+    );
+
+    let next_trace = storage_batch_traces(&mut state_db, &[(43, 2)]).remove(0);
+    let circuit = TestCircuit::from_proofs(
+        N_ROWS,
+        vec![
+            proof,
+            Proof::from((MPTProofType::StorageChanged, next_trace)),
+        ],
+    );
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+}
+
+#[test]
+fn invalid_storage_batch() {
+    let mut state_db = initial_state_db();
+    let traces = storage_batch_traces(&mut state_db, &[(41, 20), (42, 0)]);
+
+    assert!(matches!(
+        Proof::storage_batch(vec![]),
+        Err(TraceError::InconsistentProofType { .. })
+    ));
+    assert!(matches!(
+        Proof::storage_batch(vec![traces[1].clone(), traces[0].clone()]),
+        Err(TraceError::RootMismatch { .. })
+    ));
+    let mut other_account = traces.clone();
+    other_account[1].address = HexBytes([9; 20]);
+    assert!(matches!(
+        Proof::storage_batch(other_account),
+        Err(TraceError::InconsistentProofType { .. })
+    ));
+}
+
+#[test]
+fn single_update_storage_batch() {
+    let mut state_db = initial_state_db();
+    let traces = storage_batch_traces(&mut state_db, &[(45, 3)]);
+
+    let proof = Proof::storage_batch(traces).unwrap();
+    proof.check();
+
+    let circuit = TestCircuit::from_proofs(N_ROWS, vec![proof]);
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+}
+
+#[test]
+fn existing_account_storage_wiped() {
+    let mut state_db = initial_state_db();
+    let deletions: Vec<_> = (40..60).map(|key| (key, 0)).collect();
+    let traces = storage_batch_traces(&mut state_db, &deletions);
+
+    let (first, last) = (traces.first().unwrap(), traces.last().unwrap());
+    let mut trace = combined_trace(first, last);
+    trace.state_path = [first.state_path[0].clone(), last.state_path[1].clone()];
+    trace.state_key = None;
+    trace.state_update = None;
+
+    let proof = Proof::from((MPTProofType::StorageWiped, trace.clone()));
+    proof.check();
+
+    mock_prove(vec![(MPTProofType::StorageWiped, trace)]);
+}
+
+#[test]
+fn existing_account_is_empty() {
+    let address = Address::repeat_byte(6);
+    let mut trace = operation_traces(
+        &mut initial_state_db(),
+        [
+            Operation::CodeHash {
+                address,
+                code_hash: empty_keccak_code_hash(),
+            },
+            Operation::PoseidonCodeHash {
+                address,
+                poseidon_code_hash: empty_poseidon_code_hash(),
+            },
+            Operation::Balance {
+                address,
+                balance: U256::zero(),
+            },
+        ],
+    )
+    .pop()
+    .unwrap();
+    // Read the account after it has been emptied.
+    trace.account_path[0] = trace.account_path[1].clone();
+    trace.account_update[0] = trace.account_update[1].clone();
+
+    let proof = Proof::from((MPTProofType::AccountIsEmpty, trace.clone()));
+    proof.check();
+
+    mock_prove(vec![(MPTProofType::AccountIsEmpty, trace)]);
+}
+
+#[test]
+fn zero_code_hash_account_is_empty() {
+    // An account created by setting its balance has code hashes of 0 instead of the hashes of
+    // empty code.
+    let mut trie = initial_account_trie();
+    let address = Address::repeat_byte(20);
+    trie.set_account(
+        address,
+        AccountData {
+            balance: BigUint::from(5u64),
+            ..Default::default()
+        },
+    );
+    trie.set_account(address, AccountData::default());
+    let trace = trie.read_account(address);
+
+    let proof = Proof::from((MPTProofType::AccountIsEmpty, trace.clone()));
+    proof.check();
+
+    mock_prove(vec![(MPTProofType::AccountIsEmpty, trace)]);
+}
+
+// Use the account trie of a trace as a key/value trie, with the account hashes as values.
+fn key_value_trace(trace: SMTTrace) -> KeyValueTrace {
+    KeyValueTrace {
+        key: trace.account_key,
+        path: trace.account_path,
+    }
+}
+
+#[test]
+fn key_value_updates() {
+    let mut traces = operation_traces(
+        &mut initial_state_db(),
+        [
+            // update
+            Operation::Balance {
+                address: Address::repeat_byte(3),
+                balance: U256::from(5),
+            },
+            // insertion
+            Operation::Balance {
+                address: Address::repeat_byte(20),
+                balance: U256::one(),
+            },
+            // nonexistence
+            Operation::Balance {
+                address: Address::zero(),
+                balance: U256::zero(),
+            },
+        ],
+    );
+    // deletion
+    traces.push(reverse(traces[1].clone()));
+
+    let proofs: Vec<_> = traces
+        .into_iter()
+        .map(|trace| Proof::from(key_value_trace(trace)))
+        .collect();
+    for proof in &proofs {
+        proof.check();
+    }
+
+    let circuit = TestCircuit::from_proofs(N_ROWS, proofs);
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+}
+
+#[test]
+fn multiple_updates() {
+    env_logger::init();
+    let witness = vec![
+        (
+            MPTProofType::StorageChanged,
+            serde_json::from_str(include_str!("traces/empty_storage_type_1_update_c.json"))
+                .unwrap(),
+        ),
+        (
+            MPTProofType::CodeHashExists,
+            serde_json::from_str(include_str!(
+                "traces/existing_account_keccak_codehash_update.json"
+            ))
+            .unwrap(),
+        ),
+        (
+            MPTProofType::BalanceChanged,
+            serde_json::from_str(include_str!(
+                "traces/empty_account_type_2_balance_update.json"
+            ))
+            .unwrap(),
+        ),
+        (
+            MPTProofType::AccountDoesNotExist,
+            serde_json::from_str(include_str!("traces/empty_account_type_1.json")).unwrap(),
+        ),
+    ];
+    mock_prove(witness);
+}
+
+#[test]
+fn empty_storage_trie() {
+    let mut generator = initial_generator();
+    let trace = generator.handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
+        STORAGE_ADDRESS,
+        U256::from(324123123u64),
+        U256::zero(),
+        Some(U256::from(3)),
+    );
+
+    let json = serde_json::to_string_pretty(&trace).unwrap();
+    let trace: SMTTrace = serde_json::from_str(&json).unwrap();
+
+    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    insertion_proof.check();
+    mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
+
+    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
+    deletion_proof.check();
+    mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
+}
+
+#[test]
+fn singleton_storage_trie() {
+    let mut generator = initial_generator();
+    generator.handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
+        Address::repeat_byte(2),
+        U256::from(7),
+        U256::zero(),
+        Some(U256::from(2)),
+    );
+    let trace = generator.handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
+        Address::repeat_byte(2),
+        U256::from(4),
+        U256::zero(),
+        Some(U256::from(3)),
+    );
+
+    let json = serde_json::to_string_pretty(&trace).unwrap();
+    let trace: SMTTrace = serde_json::from_str(&json).unwrap();
+
+    let insertion_proof = Proof::from((MPTProofType::StorageChanged, trace.clone()));
+    insertion_proof.check();
+    mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
+
+    let deletion_proof = Proof::from((MPTProofType::StorageChanged, reverse(trace.clone())));
+    deletion_proof.check();
+    mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
+}
+
+#[test]
+fn depth_1_type_1_storage() {
+    // This tests the case where the hash domain for calculating the storage root changes
+    // because of an insertion or deletion.
+
+    let trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/depth_1_type_1_storage.json")).unwrap();
+    mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
+    mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace))]);
+}
+
+#[test]
+fn depth_1_type_1_empty_storage() {
+    let mut generator = initial_generator();
+    for key in [2, 10] {
+        generator.handle_new_state(
+            mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
+            Address::repeat_byte(2),
+            U256::from(7),
+            U256::zero(),
+            Some(U256::from(key)),
+        );
+    }
+    let trace = generator.handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
+        Address::repeat_byte(2),
+        U256::zero(),
+        U256::zero(),
+        Some(U256::from(3)),
+    );
+
+    let json = serde_json::to_string_pretty(&trace).unwrap();
+    let trace: SMTTrace = serde_json::from_str(&json).unwrap();
+
+    let proof = Proof::from((MPTProofType::StorageDoesNotExist, trace.clone()));
+    proof.check();
+    mock_prove(vec![(MPTProofType::StorageDoesNotExist, trace)]);
+}
+
+#[test]
+fn empty_storage_type_1() {
     let mut generator = initial_storage_generator();
 
     let trace = generator.handle_new_state(
@@ -1059,6 +2065,86 @@ fn test_n_rows_required() {
     assert_eq!(prover.verify(), Ok(()));
 }
 
+#[test]
+fn not_enough_rows() {
+    let witness: Vec<(MPTProofType, SMTTrace)> =
+        serde_json::from_str(include_str!("../benches/traces.json")).unwrap();
+    let proofs: Vec<_> = witness.into_iter().map(Proof::from).collect();
+
+    let n_rows_required = MptCircuitConfig::n_rows_required(&proofs);
+    assert!(MptCircuitConfig::check_n_rows(&proofs, n_rows_required).is_ok());
+    let error = MptCircuitConfig::check_n_rows(&proofs, n_rows_required - 10).unwrap_err();
+    assert_eq!(error.missing_rows(), 10);
+    // Every gadget overflows, and the error is for the one requiring the most rows.
+    let error = MptCircuitConfig::check_n_rows(&proofs, 1).unwrap_err();
+    assert_eq!(error.missing_rows(), n_rows_required - 1);
+}
+
+#[test]
+fn reads_need_fewer_rows_than_writes() {
+    let mut trie = initial_account_trie();
+    let addresses: Vec<_> = (100..160).map(Address::repeat_byte).collect();
+    for address in &addresses {
+        trie.set_account(
+            *address,
+            AccountData {
+                balance: BigUint::from(1u64),
+                ..Default::default()
+            },
+        );
+    }
+    let keys: Vec<_> = (100..160u64).map(U256::from).collect();
+    for key in &keys {
+        trie.set_storage(STORAGE_ADDRESS, *key, U256::one());
+    }
+
+    // Reads leave the root unchanged, and don't look up their new values.
+    let nonce_reads: Vec<_> = addresses
+        .iter()
+        .map(|address| Proof::from((MPTProofType::NonceRead, trie.read_account(*address))))
+        .collect();
+    let storage_reads: Vec<_> = keys
+        .iter()
+        .map(|key| {
+            Proof::from((
+                MPTProofType::StorageRead,
+                trie.read_storage(STORAGE_ADDRESS, *key),
+            ))
+        })
+        .collect();
+
+    let mut writes = trie;
+    let nonce_writes: Vec<_> = addresses
+        .iter()
+        .map(|address| {
+            let account = AccountData {
+                nonce: 1,
+                ..writes.account(*address).unwrap().clone()
+            };
+            Proof::from((
+                MPTProofType::NonceChanged,
+                writes.set_account(*address, account),
+            ))
+        })
+        .collect();
+    let storage_writes: Vec<_> = keys
+        .iter()
+        .map(|key| {
+            Proof::from((
+                MPTProofType::StorageChanged,
+                writes.set_storage(STORAGE_ADDRESS, *key, U256::from(2)),
+            ))
+        })
+        .collect();
+
+    for (reads, writes) in [(nonce_reads, nonce_writes), (storage_reads, storage_writes)] {
+        reads.iter().for_each(Proof::check);
+        assert!(
+            MptCircuitConfig::n_rows_required(&reads) < MptCircuitConfig::n_rows_required(&writes)
+        );
+    }
+}
+
 #[test]
 fn verify_benchmark_trace() {
     let witness: Vec<(MPTProofType, SMTTrace)> =
@@ -1071,3 +2157,1011 @@ fn verify_benchmark_trace() {
     let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
     assert_eq!(prover.verify(), Ok(()));
 }
+
+// Native account trie with the same accounts and storage as initial_storage_generator.
+fn initial_account_trie() -> AccountTrie {
+    let mut trie = AccountTrie::default();
+    for i in 1..10 {
+        trie.set_account(
+            Address::repeat_byte(i),
+            AccountData {
+                balance: BigUint::from(1u64),
+                ..Default::default()
+            },
+        );
+    }
+    for i in 40..60 {
+        trie.set_storage(STORAGE_ADDRESS, U256::from(i), U256::one());
+    }
+    trie
+}
+
+#[test]
+fn native_zktrie_matches_witness_generator() {
+    let mut generator = initial_storage_generator();
+    let mut trie = initial_account_trie();
+    for (address, new_value, old_value, key) in [
+        (Address::repeat_byte(3), 7, 1, None),
+        (Address::repeat_byte(20), 7, 0, None),
+        (STORAGE_ADDRESS, 3, 1, Some(45)),
+        (STORAGE_ADDRESS, 0, 1, Some(46)),
+        (STORAGE_ADDRESS, 9, 0, Some(307)),
+    ] {
+        let proof_type = if key.is_some() {
+            mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged
+        } else {
+            mpt_zktrie::mpt_circuits::MPTProofType::BalanceChanged
+        };
+        let expected = generator.handle_new_state(
+            proof_type,
+            address,
+            U256::from(new_value),
+            U256::from(old_value),
+            key.map(U256::from),
+        );
+        let expected = witness_generator_trace(expected);
+
+        let trace = match key {
+            Some(key) => trie.set_storage(address, U256::from(key), U256::from(new_value)),
+            None => {
+                let account = trie.account(address).cloned().unwrap_or_default();
+                trie.set_account(
+                    address,
+                    AccountData {
+                        balance: BigUint::from(new_value),
+                        ..account
+                    },
+                )
+            }
+        };
+        assert_eq!(trace.account_path, expected.account_path);
+        assert_eq!(trace.account_update, expected.account_update);
+        assert_eq!(trace.state_path, expected.state_path);
+    }
+}
+
+#[test]
+fn native_zktrie_traces() {
+    let mut trie = initial_account_trie();
+    let balance = |balance: u64| AccountData {
+        balance: BigUint::from(balance),
+        ..Default::default()
+    };
+    let root = trie.root();
+
+    let witness = vec![
+        (
+            MPTProofType::BalanceChanged,
+            trie.set_account(Address::repeat_byte(3), balance(5)),
+        ),
+        (
+            MPTProofType::BalanceChanged,
+            trie.set_account(Address::repeat_byte(30), balance(5)),
+        ),
+        (
+            MPTProofType::BalanceRead,
+            trie.read_account(Address::repeat_byte(30)),
+        ),
+        (
+            MPTProofType::AccountDoesNotExist,
+            trie.read_account(Address::repeat_byte(31)),
+        ),
+        (
+            MPTProofType::AccountDestructed,
+            trie.delete_account(Address::repeat_byte(30)),
+        ),
+        (
+            MPTProofType::BalanceChanged,
+            trie.set_account(Address::repeat_byte(3), balance(1)),
+        ),
+    ];
+    // Deleting an account collapses the trie back to its shape before the insertion.
+    assert_eq!(trie.root(), root);
+
+    let witness: Vec<_> = witness
+        .into_iter()
+        .chain([
+            (
+                MPTProofType::StorageChanged,
+                trie.set_storage(STORAGE_ADDRESS, U256::from(45), U256::from(3)),
+            ),
+            (
+                MPTProofType::StorageChanged,
+                trie.set_storage(STORAGE_ADDRESS, U256::from(307), U256::from(3)),
+            ),
+            (
+                MPTProofType::StorageChanged,
+                trie.set_storage(STORAGE_ADDRESS, U256::from(46), U256::zero()),
+            ),
+            (
+                MPTProofType::StorageRead,
+                trie.read_storage(STORAGE_ADDRESS, U256::from(45)),
+            ),
+            (
+                MPTProofType::StorageDoesNotExist,
+                trie.read_storage(STORAGE_ADDRESS, U256::from(46)),
+            ),
+            (
+                MPTProofType::StorageChanged,
+                trie.set_storage(Address::repeat_byte(32), U256::one(), U256::one()),
+            ),
+            (
+                MPTProofType::StorageWiped,
+                trie.wipe_storage(STORAGE_ADDRESS),
+            ),
+        ])
+        .collect();
+    assert_eq!(trie.storage_root(STORAGE_ADDRESS), Fr::zero());
+
+    for (proof_type, trace) in &witness {
+        Proof::from((*proof_type, trace.clone())).check();
+    }
+    mock_prove(witness);
+}
+
+// State with the same accounts and storage as initial_storage_generator.
+fn initial_state_db() -> StateDb {
+    let mut state_db = StateDb::default();
+    let balances = (1..10).map(|i| Operation::Balance {
+        address: Address::repeat_byte(i),
+        balance: U256::one(),
+    });
+    let storage = (40..60).map(|i| Operation::Storage {
+        address: STORAGE_ADDRESS,
+        key: U256::from(i),
+        value: U256::one(),
+    });
+    state_db.apply_all(balances.chain(storage)).unwrap();
+    state_db
+}
+
+#[test]
+fn state_db_traces() {
+    let mut state_db = initial_state_db();
+    assert_eq!(state_db.root(), initial_account_trie().root());
+
+    let address = Address::repeat_byte(20);
+    let witness = state_db
+        .apply_all([
+            Operation::Nonce { address, nonce: 1 },
+            Operation::Balance {
+                address,
+                balance: U256::from(100),
+            },
+            Operation::CodeSize {
+                address,
+                code_size: 34,
+            },
+            Operation::Storage {
+                address,
+                key: U256::from(5),
+                value: U256::from(6),
+            },
+            Operation::Storage {
+                address: STORAGE_ADDRESS,
+                key: U256::from(45),
+                value: U256::zero(),
+            },
+            Operation::Storage {
+                address: STORAGE_ADDRESS,
+                key: U256::from(45),
+                value: U256::zero(),
+            },
+            Operation::Balance {
+                address: Address::repeat_byte(21),
+                balance: U256::zero(),
+            },
+            Operation::Destroy {
+                address: Address::repeat_byte(3),
+            },
+        ])
+        .unwrap();
+    assert_eq!(
+        witness
+            .iter()
+            .map(|(proof_type, _)| *proof_type)
+            .collect::<Vec<_>>(),
+        vec![
+            MPTProofType::NonceChanged,
+            MPTProofType::BalanceChanged,
+            MPTProofType::CodeSizeExists,
+            MPTProofType::StorageChanged,
+            MPTProofType::StorageChanged,
+            MPTProofType::StorageDoesNotExist,
+            MPTProofType::AccountDoesNotExist,
+            MPTProofType::AccountDestructed,
+        ]
+    );
+    assert_eq!(witness[6].1.account_update, [None, None]);
+    for ((_, trace), (_, next_trace)) in witness.iter().tuple_windows() {
+        assert_eq!(trace.account_path[1].root, next_trace.account_path[0].root);
+    }
+    assert_eq!(
+        witness.last().unwrap().1.account_path[1].root,
+        HexBytes(state_db.root().to_bytes())
+    );
+
+    // Operations which cannot be proven leave the state unchanged.
+    let root = state_db.root();
+    for operation in [
+        Operation::Destroy {
+            address: Address::repeat_byte(3),
+        },
+        Operation::CodeSize {
+            address: Address::repeat_byte(22),
+            code_size: 1,
+        },
+    ] {
+        assert!(state_db.apply(operation).is_err());
+    }
+    assert_eq!(state_db.root(), root);
+    // So do batches of operations in which one cannot be proven.
+    assert!(state_db
+        .apply_all([
+            Operation::Nonce {
+                address: Address::repeat_byte(4),
+                nonce: 7,
+            },
+            Operation::Destroy {
+                address: Address::repeat_byte(3),
+            },
+        ])
+        .is_err());
+    assert_eq!(state_db.root(), root);
+    assert_eq!(state_db.account(Address::repeat_byte(4)).unwrap().nonce, 0);
+
+    for (proof_type, trace) in &witness {
+        Proof::from((*proof_type, trace.clone())).check();
+    }
+    mock_prove(witness);
+}
+
+// Runtime code of an EIP-1167 proxy for 0x4dbba612a46ffdf8f8052ba8853aef50e01293ab, which is
+// the code of the account with code size 45 in benches/traces.json.
+const PROXY_CODE: &str =
+    "363d3d373d3d3d363d734dbba612a46ffdf8f8052ba8853aef50e01293ab5af43d82803e903d91602b57fd5bf3";
+
+// Code hashes of PROXY_CODE, as computed by l2geth.
+fn proxy_code_hashes() -> (U256, U256) {
+    (
+        U256::from_str_radix(
+            "4201fc1e3d05900ba0c453f3c034c4c283bf7cd70bb4513a16fc1f17c335159b",
+            16,
+        )
+        .unwrap(),
+        U256::from_str_radix(
+            "06f2be324bcdb6e54386f023f65536d120c02201fad75b38139e802cada081d6",
+            16,
+        )
+        .unwrap(),
+    )
+}
+
+#[test]
+fn code_hashes_match_l2geth() {
+    let code = hex::decode(PROXY_CODE).unwrap();
+    assert_eq!(code.len(), 45);
+
+    let (keccak_hash, poseidon_hash) = proxy_code_hashes();
+    assert_eq!(keccak_code_hash(&code), keccak_hash);
+    assert_eq!(
+        U256::from_little_endian(&poseidon_code_hash(&code).to_bytes()),
+        poseidon_hash
+    );
+}
+
+#[test]
+fn genesis_state_root() {
+    assert!(*HASH_SCHEME_DONE);
+    let mut generator = WitnessGenerator::from(&ZktrieState::default());
+    let mut empty_poseidon_hash = empty_poseidon_code_hash().to_bytes();
+    empty_poseidon_hash.reverse();
+    let (proxy_keccak_hash, proxy_poseidon_hash) = proxy_code_hashes();
+    let mut trace = None;
+    for i in 1..4 {
+        // The third account has PROXY_CODE, the others have no code.
+        let (keccak_hash, poseidon_hash, code_size) = if i == 3 {
+            (proxy_keccak_hash, proxy_poseidon_hash, 45)
+        } else {
+            (
+                empty_keccak_code_hash(),
+                U256::from_big_endian(&empty_poseidon_hash),
+                0,
+            )
+        };
+        for (proof_type, new) in [
+            (
+                mpt_zktrie::mpt_circuits::MPTProofType::BalanceChanged,
+                U256::from(10 * i),
+            ),
+            (
+                mpt_zktrie::mpt_circuits::MPTProofType::CodeHashExists,
+                keccak_hash,
+            ),
+            (
+                mpt_zktrie::mpt_circuits::MPTProofType::PoseidonCodeHashExists,
+                poseidon_hash,
+            ),
+            (
+                mpt_zktrie::mpt_circuits::MPTProofType::CodeSizeExists,
+                U256::from(code_size),
+            ),
+        ] {
+            if new.is_zero() {
+                continue;
+            }
+            trace = Some(generator.handle_new_state(
+                proof_type,
+                Address::repeat_byte(i),
+                new,
+                U256::zero(),
+                None,
+            ));
+        }
+    }
+    for key in 1..5u64 {
+        trace = Some(generator.handle_new_state(
+            mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
+            Address::repeat_byte(1),
+            U256::from(100 + key),
+            U256::zero(),
+            Some(U256::from(key)),
+        ));
+    }
+    let trace = witness_generator_trace(trace.unwrap());
+
+    // Balances and storage can be given in hex or decimal.
+    let genesis = Genesis::from_json(
+        r#"{
+            "config": {},
+            "alloc": {
+                "0x0101010101010101010101010101010101010101": {
+                    "balance": "10",
+                    "storage": {
+                        "0x0000000000000000000000000000000000000000000000000000000000000001": "0x65",
+                        "0x0000000000000000000000000000000000000000000000000000000000000002": "0x66",
+                        "0x0000000000000000000000000000000000000000000000000000000000000003": "0x67",
+                        "0x0000000000000000000000000000000000000000000000000000000000000004": "0x68",
+                        "0x0000000000000000000000000000000000000000000000000000000000000005": "0x0"
+                    }
+                },
+                "0x0202020202020202020202020202020202020202": { "balance": "0x14" },
+                "0x0303030303030303030303030303030303030303": {
+                    "balance": "0x1e",
+                    "nonce": "0x0",
+                    "code": "0x363d3d373d3d3d363d734dbba612a46ffdf8f8052ba8853aef50e01293ab5af43d82803e903d91602b57fd5bf3"
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+    let account_trie = genesis.account_trie();
+    assert_eq!(
+        HexBytes(account_trie.root().to_bytes()),
+        trace.account_path[1].root
+    );
+    assert_eq!(
+        HexBytes(
+            account_trie
+                .storage_root(Address::repeat_byte(1))
+                .to_bytes()
+        ),
+        trace.state_path[1].as_ref().unwrap().root
+    );
+
+    // Building the tries in bulk gives the same root as inserting the accounts one at a time.
+    let mut state_db = StateDb::default();
+    for (address, account) in &genesis.alloc {
+        let data = account.account_data();
+        state_db
+            .apply_all([
+                Operation::Balance {
+                    address: *address,
+                    balance: account.balance,
+                },
+                Operation::CodeHash {
+                    address: *address,
+                    code_hash: keccak_code_hash(&account.code),
+                },
+                Operation::PoseidonCodeHash {
+                    address: *address,
+                    poseidon_code_hash: poseidon_code_hash(&account.code),
+                },
+                Operation::CodeSize {
+                    address: *address,
+                    code_size: account.code.len() as u64,
+                },
+            ])
+            .unwrap();
+        assert_eq!(state_db.account(*address), Some(&data));
+        for (key, value) in &account.storage {
+            state_db
+                .apply(Operation::Storage {
+                    address: *address,
+                    key: *key,
+                    value: *value,
+                })
+                .unwrap();
+        }
+    }
+    assert_eq!(state_db.root(), genesis.state_root());
+}
+
+#[test]
+fn eth_proof_codec() {
+    // The proofs are produced from the native trie, with the same node encoding as l2geth.
+    let mut trie = initial_account_trie();
+    let new_address = Address::repeat_byte(20);
+    let key = U256::from(45);
+    let old_proofs = [
+        EthProof::new(&trie, STORAGE_ADDRESS, &[key]),
+        EthProof::new(&trie, new_address, &[]),
+    ];
+    let expected_traces = [
+        (
+            MPTProofType::StorageChanged,
+            trie.set_storage(STORAGE_ADDRESS, key, U256::from(3)),
+        ),
+        (
+            MPTProofType::BalanceChanged,
+            trie.set_account(
+                new_address,
+                AccountData {
+                    balance: BigUint::from(5u64),
+                    ..Default::default()
+                },
+            ),
+        ),
+    ];
+    let new_proofs = [
+        EthProof::new(&trie, STORAGE_ADDRESS, &[key]),
+        EthProof::new(&trie, new_address, &[]),
+    ];
+
+    for ((old, new), (proof_type, expected)) in old_proofs
+        .iter()
+        .zip_eq(&new_proofs)
+        .zip_eq(expected_traces)
+    {
+        for proof in [old, new] {
+            let json = serde_json::to_string(proof).unwrap();
+            assert_eq!(&serde_json::from_str::<EthProof>(&json).unwrap(), proof);
+
+            let nodes = decode_proof(&proof.account_proof).unwrap();
+            let encoded: Vec<_> = encode_proof(&nodes).into_iter().map(Into::into).collect();
+            assert_eq!(encoded, proof.account_proof);
+        }
+
+        let trace = node::trace([old, new]).unwrap();
+        assert_eq!(trace.account_path, expected.account_path);
+        assert_eq!(trace.state_path, expected.state_path);
+        assert_eq!(trace.state_key, expected.state_key);
+        assert_eq!(trace.state_update, expected.state_update);
+        assert_eq!(trace.common_state_root, expected.common_state_root);
+
+        Proof::from((proof_type, trace)).check();
+    }
+
+    let proof = &new_proofs[0].account_proof;
+    let mut truncated = proof.clone();
+    truncated[0] = truncated[0][..40].to_vec().into();
+    assert!(matches!(
+        decode_proof(&truncated),
+        Err(NodeError::InvalidLength(40))
+    ));
+    assert!(matches!(
+        decode_proof(&proof[..proof.len() - 1]),
+        Err(NodeError::MissingMagicBytes)
+    ));
+    let mut tampered = decode_proof(proof).unwrap();
+    tampered.swap(0, 1);
+    assert!(matches!(
+        node::smt_path(Fr::zero(), &tampered),
+        Err(NodeError::HashMismatch(1))
+    ));
+
+    let mut two_slots = EthProof::new(&trie, STORAGE_ADDRESS, &[key, U256::from(46)]);
+    assert!(matches!(
+        node::trace([&two_slots, &two_slots]),
+        Err(NodeError::TooManyStorageProofs(2))
+    ));
+    two_slots.storage_proof.pop();
+    assert!(node::trace([&two_slots, &two_slots]).is_ok());
+}
+
+#[test]
+fn eth_proof_fixture() {
+    // Proofs before and after the update of the l2geth trace in existing_storage_update.json, in the
+    // serialization of eth_getProof. Decoding them must give the paths and roots l2geth computed.
+    let proofs: [EthProof; 2] =
+        serde_json::from_str(include_str!("eth_proofs/existing_storage_update.json")).unwrap();
+    let expected: SMTTrace =
+        serde_json::from_str(include_str!("traces/existing_storage_update.json")).unwrap();
+    let state_key = fr(expected.state_key.unwrap());
+    for (i, proof) in proofs.iter().enumerate() {
+        let account_nodes = decode_proof(&proof.account_proof).unwrap();
+        let account_path = node::smt_path(fr(expected.account_key), &account_nodes).unwrap();
+        assert_eq!(account_path, expected.account_path[i]);
+        let encoded: Vec<_> = encode_proof(&account_nodes)
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        assert_eq!(encoded, proof.account_proof);
+
+        let storage_proof = &proof.storage_proof[0].proof;
+        let storage_nodes = decode_proof(storage_proof).unwrap();
+        let storage_path = node::smt_path(state_key, &storage_nodes).unwrap();
+        assert_eq!(Some(storage_path), expected.state_path[i]);
+        let encoded: Vec<_> = encode_proof(&storage_nodes)
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        assert_eq!(&encoded, storage_proof);
+    }
+
+    let trace = node::trace([&proofs[0], &proofs[1]]).unwrap();
+    assert_eq!(trace.account_path, expected.account_path);
+    assert_eq!(trace.state_path, expected.state_path);
+    assert_eq!(trace.state_key, expected.state_key);
+    assert_eq!(trace.state_update, expected.state_update);
+    Proof::from((MPTProofType::StorageChanged, trace)).check();
+}
+
+#[test]
+fn infer_proof_types() {
+    let witness: Vec<(MPTProofType, SMTTrace)> =
+        serde_json::from_str(include_str!("../benches/traces.json")).unwrap();
+    for (recorded, trace) in &witness {
+        let inferred = MPTProofType::infer(trace).unwrap();
+        // Reads of an account field are recorded with the proof type of the field, and inferred as
+        // NonceRead, since the trace doesn't say which field was read.
+        let is_field_read = inferred == MPTProofType::NonceRead
+            && matches!(
+                recorded,
+                MPTProofType::NonceChanged
+                    | MPTProofType::BalanceChanged
+                    | MPTProofType::CodeHashExists
+                    | MPTProofType::PoseidonCodeHashExists
+                    | MPTProofType::CodeSizeExists
+            );
+        assert!(
+            inferred == *recorded || is_field_read,
+            "{recorded:?} inferred as {inferred:?}"
+        );
+        Proof::try_from((&inferred, trace)).unwrap();
+    }
+
+    let mut state_db = initial_state_db();
+    let address = Address::repeat_byte(2);
+    for operation in [
+        Operation::Nonce { address, nonce: 3 },
+        Operation::CodeSize {
+            address,
+            code_size: 100,
+        },
+        Operation::Storage {
+            address,
+            key: U256::one(),
+            value: U256::zero(),
+        },
+        Operation::Destroy { address },
+    ] {
+        let (proof_type, trace) = state_db.apply(operation).unwrap();
+        assert_eq!(MPTProofType::infer(&trace), Some(proof_type));
+    }
+}
+
+// Rows of an op in the legacy format, with one row for each level of the longer of the two paths.
+fn legacy_rows(key: HexBytes<32>, paths: &[SMTPath; 2]) -> Vec<Row> {
+    let key_fr = fr(key);
+    let depth = paths.iter().map(|path| path.path.len()).max().unwrap();
+    let side = |path: &SMTPath, depth: usize| {
+        let hash = match depth {
+            0 => path.root,
+            _ => path
+                .path
+                .get(depth - 1)
+                .map_or(HexBytes([0; 32]), |node| node.value),
+        };
+        match path.path.get(depth) {
+            Some(node) => (HashType::Middle, hash, node.value),
+            None if depth == path.path.len() => match path.leaf {
+                Some(leaf) => (HashType::Leaf, hash, leaf.value),
+                None => (HashType::Empty, hash, HexBytes([0; 32])),
+            },
+            None => (HashType::Empty, HexBytes([0; 32]), HexBytes([0; 32])),
+        }
+    };
+    (0..=depth)
+        .map(|depth| {
+            let [(old_hash_type, old_hash, old_value), (new_hash_type, new_hash, new_value)] =
+                [0, 1].map(|i| side(&paths[i], depth));
+            let sib = paths
+                .iter()
+                .find_map(|path| path.path.get(depth))
+                .map_or(HexBytes([0; 32]), |node| node.sibling);
+            let leaf_key = paths
+                .iter()
+                .filter(|path| path.path.len() == depth)
+                .find_map(|path| path.leaf)
+                .map_or(key, |leaf| leaf.sibling);
+            Row {
+                is_first: depth == 0,
+                sib,
+                depth,
+                path: BigUint::from(u8::from(key_fr.bit(depth))),
+                path_acc: BigUint::from_bytes_le(&leaf_key.0),
+                old_hash_type,
+                old_hash,
+                old_value,
+                new_hash_type,
+                new_hash,
+                new_value,
+                key,
+                new_root: paths[1].root,
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn legacy_rows_to_traces() {
+    let mut trie = initial_account_trie();
+    let balance = |balance: u64| AccountData {
+        balance: BigUint::from(balance),
+        ..Default::default()
+    };
+    let witness = [
+        (
+            MPTProofType::BalanceChanged,
+            trie.set_account(Address::repeat_byte(3), balance(5)),
+        ),
+        (
+            MPTProofType::BalanceChanged,
+            trie.set_account(Address::repeat_byte(30), balance(5)),
+        ),
+        (
+            MPTProofType::AccountDoesNotExist,
+            trie.read_account(Address::repeat_byte(31)),
+        ),
+        (
+            MPTProofType::AccountDestructed,
+            trie.delete_account(Address::repeat_byte(30)),
+        ),
+        (
+            MPTProofType::StorageChanged,
+            trie.set_storage(STORAGE_ADDRESS, U256::from(45), U256::from(3)),
+        ),
+        (
+            MPTProofType::StorageChanged,
+            trie.set_storage(STORAGE_ADDRESS, U256::from(307), U256::from(3)),
+        ),
+        (
+            MPTProofType::StorageChanged,
+            trie.set_storage(STORAGE_ADDRESS, U256::from(46), U256::zero()),
+        ),
+    ];
+
+    let mut converted = vec![];
+    for (proof_type, trace) in witness {
+        let account_rows = legacy_rows(trace.account_key, &trace.account_path);
+        let key_value_trace = KeyValueTrace::try_from(&account_rows[..]).unwrap();
+        assert_eq!(key_value_trace.path, trace.account_path);
+
+        let storage_rows;
+        let storage = match (
+            trace.common_state_root,
+            &trace.state_update,
+            &trace.state_path,
+        ) {
+            (Some(root), _, _) => LegacyStorage::Root(fr(root)),
+            (None, Some([Some(old), Some(new)]), [Some(old_path), Some(new_path)]) => {
+                storage_rows = legacy_rows(
+                    trace.state_key.unwrap(),
+                    &[old_path.clone(), new_path.clone()],
+                );
+                LegacyStorage::Update {
+                    rows: &storage_rows,
+                    key: U256::from_big_endian(&old.key.0),
+                    values: [old, new].map(|data| U256::from_big_endian(&data.value.0)),
+                }
+            }
+            _ => unreachable!("account and storage updates have both storage values"),
+        };
+        let legacy_trace = legacy::smt_trace(
+            trace.address,
+            &account_rows,
+            trace.account_update.clone(),
+            storage,
+        )
+        .unwrap();
+        assert_eq!(legacy_trace.account_path, trace.account_path);
+        assert_eq!(legacy_trace.state_path, trace.state_path);
+        assert_eq!(legacy_trace.state_key, trace.state_key);
+
+        Proof::from((proof_type, legacy_trace.clone())).check();
+        converted.push((proof_type, legacy_trace));
+    }
+    mock_prove(converted);
+
+    let trace = trie.read_account(Address::repeat_byte(3));
+    let mut rows = legacy_rows(trace.account_key, &trace.account_path);
+    rows[0].sib = HexBytes([2; 32]);
+    assert!(matches!(
+        legacy::paths(&rows),
+        Err(legacy::RowError::BranchHashMismatch { row: 0, .. })
+    ));
+    assert!(matches!(legacy::paths(&[]), Err(legacy::RowError::EmptyOp)));
+}
+
+#[test]
+fn legacy_row_dump() {
+    // Ops for the l2geth traces of an account insertion and a storage insertion which each extend a
+    // leaf by two levels, and the account op of the storage insertion, in the row format.
+    let rows = Row::from_lines(include_str!("legacy_rows/leaf_extensions.jsonl")).unwrap();
+    let mut ops = Row::fold_flattern_rows(rows);
+    assert_eq!(ops.len(), 3);
+    assert!(ops
+        .iter()
+        .flatten()
+        .any(|row| row.old_hash_type == HashType::LeafExt));
+    assert!(ops
+        .iter()
+        .flatten()
+        .any(|row| row.old_hash_type == HashType::LeafExtFinal));
+
+    let account_insertion: SMTTrace = serde_json::from_str(include_str!(
+        "traces/empty_account_type_1_balance_update.json"
+    ))
+    .unwrap();
+    let legacy_trace = legacy::smt_trace(
+        account_insertion.address,
+        &ops[0],
+        account_insertion.account_update.clone(),
+        LegacyStorage::Root(Fr::zero()),
+    )
+    .unwrap();
+    assert_eq!(
+        serde_json::to_value(&legacy_trace).unwrap(),
+        serde_json::to_value(&account_insertion).unwrap()
+    );
+    Proof::from((MPTProofType::BalanceChanged, legacy_trace.clone())).check();
+
+    let storage_insertion: SMTTrace =
+        serde_json::from_str(include_str!("traces/depth_1_type_1_storage.json")).unwrap();
+    let state_update = storage_insertion.state_update.clone().unwrap();
+    let [old_value, new_value] =
+        state_update.map(|data| U256::from_big_endian(&data.unwrap().value.0));
+    let storage_trace = legacy::smt_trace(
+        storage_insertion.address,
+        &ops[1],
+        storage_insertion.account_update.clone(),
+        LegacyStorage::Update {
+            rows: &ops[2],
+            key: U256::from(5),
+            values: [old_value, new_value],
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        serde_json::to_value(&storage_trace).unwrap(),
+        serde_json::to_value(&storage_insertion).unwrap()
+    );
+    Proof::from((MPTProofType::StorageChanged, storage_trace.clone())).check();
+
+    mock_prove(vec![
+        (MPTProofType::BalanceChanged, legacy_trace),
+        (MPTProofType::StorageChanged, storage_trace),
+    ]);
+
+    ops[0][2].sib = HexBytes([0xff; 32]);
+    assert!(matches!(
+        legacy::paths(&ops[0]),
+        Err(legacy::RowError::NonCanonical {
+            field: "sibling",
+            row: 2,
+        })
+    ));
+}
+
+#[test]
+fn codec_round_trip() {
+    let traces = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/src/traces")).unwrap();
+    for entry in traces {
+        let path = entry.unwrap().path();
+        let json = std::fs::read_to_string(&path).unwrap();
+        let Ok(witness) = serde_json::from_str::<Vec<(MPTProofType, SMTTrace)>>(&json) else {
+            // Files with a single trace have no proof type.
+            let trace: SMTTrace = serde_json::from_str(&json).unwrap();
+            let bytes = codec::encode_trace(&trace).unwrap();
+            assert!(bytes.len() < json.len(), "{path:?}");
+            assert_eq!(
+                serde_json::to_value(codec::decode_trace(&bytes).unwrap()).unwrap(),
+                serde_json::to_value(&trace).unwrap(),
+                "{path:?}"
+            );
+            assert!(matches!(
+                codec::decode(&bytes),
+                Err(CodecError::MissingMagicBytes)
+            ));
+            continue;
+        };
+
+        let bytes = codec::encode(&witness).unwrap();
+        assert!(bytes.len() < json.len(), "{path:?}");
+        let decoded = codec::decode(&bytes).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&witness).unwrap(),
+            "{path:?}"
+        );
+        assert_eq!(codec::decode_proofs(&bytes).unwrap().len(), witness.len());
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[codec::MAGIC_BYTES.len()] += 1;
+        assert!(matches!(
+            codec::decode(&wrong_version),
+            Err(CodecError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            codec::decode(&bytes[..bytes.len() - 1]),
+            Err(CodecError::UnexpectedEnd)
+        ));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            codec::decode(&trailing),
+            Err(CodecError::TrailingBytes(1))
+        ));
+        assert!(matches!(
+            codec::decode(&bytes[1..]),
+            Err(CodecError::MissingMagicBytes)
+        ));
+    }
+
+    // Proof types are encoded as their discriminants, which decode back to the same variant.
+    let trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/empty_account_type_1.json")).unwrap();
+    let witness: Vec<_> = MPTProofType::iter()
+        .map(|proof_type| (proof_type, trace.clone()))
+        .collect();
+    let bytes = codec::encode(&witness).unwrap();
+    let decoded = codec::decode(&bytes).unwrap();
+    assert!(decoded
+        .iter()
+        .map(|(proof_type, _)| *proof_type)
+        .eq(MPTProofType::iter()));
+    // magic bytes, version, count and length of the first trace
+    let proof_type_offset = codec::MAGIC_BYTES.len() + 1 + 4 + 4;
+    assert_eq!(bytes[proof_type_offset], MPTProofType::NonceChanged as u8);
+    let mut unknown = bytes;
+    unknown[proof_type_offset] = MPTProofType::KeyValueChanged as u8 + 1;
+    assert!(matches!(
+        codec::decode(&unknown),
+        Err(CodecError::UnknownProofType(18))
+    ));
+}
+
+#[test]
+fn stream_traces() {
+    let json = include_str!("../benches/traces.json");
+    let witness: Vec<(MPTProofType, SMTTrace)> = serde_json::from_str(json).unwrap();
+
+    let streamed: Vec<_> = TraceReader::json_array(json.as_bytes())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&streamed).unwrap(),
+        serde_json::to_value(&witness).unwrap()
+    );
+
+    let ndjson = witness
+        .iter()
+        .map(|entry| serde_json::to_string(entry).unwrap() + "\n")
+        .join("");
+    let proofs: Vec<_> = TraceReader::ndjson(ndjson.as_bytes())
+        .proofs()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(proofs.len(), witness.len());
+
+    let n_rows = proofs
+        .iter()
+        .map(|proof| MptCircuitConfig::n_rows_required(std::slice::from_ref(proof)))
+        .max()
+        .unwrap();
+    let batches: Vec<_> = Batches::new(TraceReader::ndjson(ndjson.as_bytes()).proofs(), n_rows)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(batches.iter().map(Vec::len).sum::<usize>(), proofs.len());
+    for batch in &batches {
+        assert!(MptCircuitConfig::check_n_rows(batch, n_rows).is_ok());
+    }
+    assert!(matches!(
+        Batches::new(TraceReader::ndjson(ndjson.as_bytes()).proofs(), 100).next(),
+        Some(Err(StreamError::Assign { index: 0, .. }))
+    ));
+
+    let truncated = &json[..json.len() / 2];
+    let result: Result<Vec<_>, _> = TraceReader::json_array(truncated.as_bytes()).collect();
+    assert!(matches!(
+        result,
+        Err(StreamError::Json { .. } | StreamError::Syntax { .. })
+    ));
+    assert!(matches!(
+        TraceReader::json_array("[] []".as_bytes()).next(),
+        Some(Err(StreamError::Syntax { index: 0, .. }))
+    ));
+    assert!(TraceReader::json_array(" [ ] ".as_bytes()).next().is_none());
+}
+
+#[test]
+fn path_shape_checks() {
+    let trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/empty_storage_type_1_update_a.json")).unwrap();
+    let try_proof = |trace: &SMTTrace| Proof::try_from((&MPTProofType::StorageChanged, trace));
+    assert!(path_shape::check_paths(&trace).is_ok());
+
+    let mut legacy = trace.clone();
+    for path in legacy
+        .account_path
+        .iter_mut()
+        .chain(legacy.state_path.iter_mut().flatten())
+    {
+        for node in path.path.iter_mut().chain(path.leaf.as_mut()) {
+            node.node_type = 0;
+        }
+    }
+    assert!(matches!(
+        try_proof(&legacy),
+        Err(TraceError::InvalidNodeType {
+            name: "account path",
+            depth: 0,
+            node_type: 0,
+        })
+    ));
+    path_shape::infer_node_types(&mut legacy).unwrap();
+    assert_eq!(
+        serde_json::to_value(&legacy).unwrap(),
+        serde_json::to_value(&trace).unwrap()
+    );
+    try_proof(&legacy).unwrap().check();
+
+    // The last node of a path has a leaf or empty child on the side of the key.
+    let mut wrong_node_type = trace.clone();
+    let path = &mut wrong_node_type.state_path[0].as_mut().unwrap().path;
+    let depth = path.len() - 1;
+    path[depth].node_type = HashDomain::Branch3.into();
+    assert!(matches!(
+        try_proof(&wrong_node_type),
+        Err(TraceError::InvalidNodeType {
+            name: "storage path",
+            depth: d,
+            node_type: 9,
+        }) if d == depth
+    ));
+
+    // Both are public, so they must reject untrusted traces instead of panicking on them.
+    let mut non_canonical = legacy.clone();
+    non_canonical.state_path[1].as_mut().unwrap().path[0].sibling = HexBytes([0xff; 32]);
+    non_canonical.state_path[1].as_mut().unwrap().path[0].node_type = 0;
+    assert!(matches!(
+        path_shape::infer_node_types(&mut non_canonical),
+        Err(TraceError::NonCanonical("storage path"))
+    ));
+    non_canonical.state_key = Some(HexBytes([0xff; 32]));
+    assert!(matches!(
+        path_shape::check_paths(&non_canonical),
+        Err(TraceError::NonCanonical("state key"))
+    ));
+
+    let mut wrong_path_part = trace;
+    wrong_path_part.account_path[1].path_part ^= BigUint::from(2u8);
+    assert!(matches!(
+        try_proof(&wrong_path_part),
+        Err(TraceError::PathPartMismatch {
+            name: "account path",
+            depth: 1,
+        })
+    ));
+}
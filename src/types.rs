@@ -1,9 +1,9 @@
 use crate::{
     gadgets::mpt_update::PathType,
-    serde::{AccountData, HexBytes, SMTNode, SMTPath, SMTTrace},
+    serde::{AccountData, HexBytes, KeyValueTrace, SMTNode, SMTPath, SMTTrace},
     util::{
-        account_key, check_domain_consistency, domain_hash, fr_from_biguint, rlc,
-        u256_from_biguint, u256_from_hex, u256_to_big_endian,
+        account_key, domain_hash, empty_keccak_code_hash, empty_poseidon_code_hash, rlc,
+        split_word, try_fr, u256_from_biguint, u256_from_hex, u256_to_big_endian,
     },
     MPTProofType,
 };
@@ -15,18 +15,22 @@ use halo2_proofs::halo2curves::bn256::Fr;
 use itertools::{EitherOrBoth, Itertools};
 use num_bigint::BigUint;
 use num_traits::identities::Zero;
+use strum_macros::EnumIter;
 
+pub mod error;
+pub mod path_shape;
 pub mod storage;
 pub mod trie;
+pub use error::{TraceError, VerificationError};
 use storage::StorageProof;
-use trie::TrieRows;
+use trie::{common_domain, next_domain, node_domain, TrieRows};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum HashDomain {
     Leaf,
     Branch0, // branch node with both children = leaf or empty
-    Branch1, // branch node with left child = branch node and right child = leaf or empty
-    Branch2, // branch node with left child = leaf or empty and right child = branch node
+    Branch1, // branch node with left child = leaf or empty and right child = branch node
+    Branch2, // branch node with left child = branch node and right child = leaf or empty
     Branch3, // branch node with both children = branch node
     Pair,
     AccountFields,
@@ -109,12 +113,221 @@ pub enum ClaimKind {
         new_value: Option<U256>,
     },
     IsEmpty(Option<U256>),
+    AccountDestructed,
+    AccountCreated {
+        nonce: u64,
+        balance: U256,
+        code_hash: U256,
+        poseidon_code_hash: Fr,
+        code_size: u64,
+    },
+    NonceRead(u64),
+    BalanceRead(U256),
+    StorageRead {
+        key: U256,
+        value: U256,
+    },
+    // The nonce and at most one other field of an existing account, which covers the sender of a
+    // transfer. Each further field would need its own hashes along the whole account path, so
+    // updates of more fields are proven separately. The nonce update happens first, so the account
+    // between the two updates has the new nonce and the old value of the other field.
+    NonceAndField {
+        old_nonce: u64,
+        new_nonce: u64,
+        field: FieldUpdate,
+    },
+    // Several storage updates of one existing account, proven under a single account path. The
+    // state roots between the updates are not proven, so the batch is a single update from the old
+    // root to the new one. The values and storage key of each update are assigned to its own start
+    // row, which is not exposed in the mpt table, so the claim itself has none.
+    StorageBatch,
+    // The storage root of an existing account is reset to the empty root.
+    StorageWiped,
+    // The account exists, but its nonce, balance and code size are 0 and it has no code (EIP-161).
+    AccountIsEmpty,
+    // Leaf of a trie that holds (key, value_hash) pairs instead of accounts. The value hashes are
+    // None if the key does not exist.
+    KeyValue {
+        key: Fr,
+        old_value_hash: Option<Fr>,
+        new_value_hash: Option<Fr>,
+    },
+}
+
+/// Account field other than the nonce, in the order of the proof types that update them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, EnumIter)]
+pub enum AccountField {
+    Balance,
+    CodeHash,
+    PoseidonCodeHash,
+    CodeSize,
+}
+
+impl From<AccountField> for MPTProofType {
+    fn from(field: AccountField) -> Self {
+        match field {
+            AccountField::Balance => MPTProofType::BalanceChanged,
+            AccountField::CodeHash => MPTProofType::CodeHashExists,
+            AccountField::PoseidonCodeHash => MPTProofType::PoseidonCodeHashExists,
+            AccountField::CodeSize => MPTProofType::CodeSizeExists,
+        }
+    }
+}
+
+/// Update of the field that a NonceAndField claim changes together with the nonce.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldUpdate {
+    Balance { old: U256, new: U256 },
+    CodeHash { old: U256, new: U256 },
+    PoseidonCodeHash { old: Fr, new: Fr },
+    CodeSize { old: u64, new: u64 },
+}
+
+impl FieldUpdate {
+    pub fn field(&self) -> AccountField {
+        match self {
+            Self::Balance { .. } => AccountField::Balance,
+            Self::CodeHash { .. } => AccountField::CodeHash,
+            Self::PoseidonCodeHash { .. } => AccountField::PoseidonCodeHash,
+            Self::CodeSize { .. } => AccountField::CodeSize,
+        }
+    }
+
+    /// Claim kind of the update on its own, whose values are those of its start row.
+    pub fn claim_kind(&self) -> ClaimKind {
+        match *self {
+            Self::Balance { old, new } => ClaimKind::Balance {
+                old: Some(old),
+                new: Some(new),
+            },
+            Self::CodeHash { old, new } => ClaimKind::CodeHash {
+                old: Some(old),
+                new: Some(new),
+            },
+            Self::PoseidonCodeHash { old, new } => ClaimKind::PoseidonCodeHash {
+                old: Some(old),
+                new: Some(new),
+            },
+            Self::CodeSize { old, new } => ClaimKind::CodeSize {
+                old: Some(old),
+                new: Some(new),
+            },
+        }
+    }
 }
 
 impl Claim {
+    /// Builds the claim of `proof_type` for the account at `address`, or for its storage slot at
+    /// `storage_key`, with values None if the account or storage slot doesn't exist. Proof types
+    /// that set several fields at once have no single old and new value, so their ClaimKind must
+    /// be built directly.
+    pub fn new(
+        address: Address,
+        proof_type: MPTProofType,
+        storage_key: U256,
+        [old, new]: [Option<U256>; 2],
+        [old_root, new_root]: [Fr; 2],
+    ) -> Result<Self, TraceError> {
+        let inconsistent = |reason| TraceError::InconsistentProofType { proof_type, reason };
+        let to_u64 = |value: Option<U256>| {
+            value
+                .map(|x| match x.bits() {
+                    0..=64 => Ok(x.as_u64()),
+                    _ => Err(inconsistent("value does not fit into 8 bytes")),
+                })
+                .transpose()
+        };
+        let to_fr = |value: Option<U256>| {
+            value
+                .map(|x| {
+                    let mut bytes = [0; 32];
+                    x.to_little_endian(&mut bytes);
+                    try_fr(HexBytes(bytes), "poseidon code hash")
+                })
+                .transpose()
+        };
+        let read_value = || match (old, new) {
+            (Some(old), Some(new)) if old == new => Ok(old),
+            _ => Err(inconsistent("read value must exist and not change")),
+        };
+
+        let kind = match proof_type {
+            MPTProofType::NonceChanged => ClaimKind::Nonce {
+                old: to_u64(old)?,
+                new: to_u64(new)?,
+            },
+            MPTProofType::BalanceChanged => ClaimKind::Balance { old, new },
+            MPTProofType::CodeHashExists => ClaimKind::CodeHash { old, new },
+            MPTProofType::PoseidonCodeHashExists => ClaimKind::PoseidonCodeHash {
+                old: to_fr(old)?,
+                new: to_fr(new)?,
+            },
+            MPTProofType::CodeSizeExists => ClaimKind::CodeSize {
+                old: to_u64(old)?,
+                new: to_u64(new)?,
+            },
+            MPTProofType::StorageChanged => ClaimKind::Storage {
+                key: storage_key,
+                old_value: old,
+                new_value: new,
+            },
+            MPTProofType::AccountDoesNotExist | MPTProofType::StorageDoesNotExist => {
+                if old.is_some() || new.is_some() {
+                    return Err(inconsistent("nonexistence proof has values"));
+                }
+                ClaimKind::IsEmpty(
+                    Some(storage_key).filter(|_| proof_type == MPTProofType::StorageDoesNotExist),
+                )
+            }
+            MPTProofType::NonceRead => ClaimKind::NonceRead(to_u64(Some(read_value()?))?.unwrap()),
+            MPTProofType::BalanceRead => ClaimKind::BalanceRead(read_value()?),
+            MPTProofType::StorageRead => ClaimKind::StorageRead {
+                key: storage_key,
+                value: read_value()?,
+            },
+            MPTProofType::AccountDestructed
+            | MPTProofType::AccountCreated
+            | MPTProofType::NonceAndFieldChanged
+            | MPTProofType::StorageBatchChanged
+            | MPTProofType::StorageWiped
+            | MPTProofType::AccountIsEmpty
+            | MPTProofType::KeyValueChanged => {
+                return Err(TraceError::Unsupported {
+                    proof_type,
+                    reason: "claim has no single old and new value",
+                })
+            }
+        };
+
+        Ok(Self {
+            old_root,
+            new_root,
+            address,
+            kind,
+        })
+    }
+
+    /// Key of the leaf in the trie with the old and new roots.
+    pub fn key(&self) -> Fr {
+        match self.kind {
+            ClaimKind::KeyValue { key, .. } => key,
+            _ => account_key(self.address),
+        }
+    }
+
+    /// Whether the claim is for a read, which leaves the trie unchanged.
+    pub fn is_read(&self) -> bool {
+        matches!(
+            self.kind,
+            ClaimKind::NonceRead(_) | ClaimKind::BalanceRead(_) | ClaimKind::StorageRead { .. }
+        )
+    }
+
     pub fn storage_key(&self) -> U256 {
         match self.kind {
-            ClaimKind::Storage { key, .. } | ClaimKind::IsEmpty(Some(key)) => key,
+            ClaimKind::Storage { key, .. }
+            | ClaimKind::StorageRead { key, .. }
+            | ClaimKind::IsEmpty(Some(key)) => key,
             _ => U256::zero(),
         }
     }
@@ -132,7 +345,19 @@ impl Claim {
                 &u256_to_big_endian(&old_value.unwrap_or_default()),
                 randomness,
             ),
-            ClaimKind::IsEmpty(_) => Fr::zero(),
+            ClaimKind::NonceRead(nonce) => Fr::from(nonce),
+            ClaimKind::BalanceRead(value) | ClaimKind::StorageRead { value, .. } => {
+                rlc(&u256_to_big_endian(&value), randomness)
+            }
+            // The values of the other field are assigned to the extra start row for its update.
+            ClaimKind::NonceAndField { old_nonce, .. } => Fr::from(old_nonce),
+            ClaimKind::KeyValue { old_value_hash, .. } => old_value_hash.unwrap_or_default(),
+            ClaimKind::IsEmpty(_)
+            | ClaimKind::AccountDestructed
+            | ClaimKind::AccountCreated { .. }
+            | ClaimKind::StorageBatch
+            | ClaimKind::StorageWiped
+            | ClaimKind::AccountIsEmpty => Fr::zero(),
         }
     }
 
@@ -149,7 +374,33 @@ impl Claim {
                 &u256_to_big_endian(&new_value.unwrap_or_default()),
                 randomness,
             ),
-            ClaimKind::IsEmpty(_) => Fr::zero(),
+            ClaimKind::NonceRead(nonce) => Fr::from(nonce),
+            ClaimKind::BalanceRead(value) | ClaimKind::StorageRead { value, .. } => {
+                rlc(&u256_to_big_endian(&value), randomness)
+            }
+            ClaimKind::NonceAndField { new_nonce, .. } => Fr::from(new_nonce),
+            ClaimKind::KeyValue { new_value_hash, .. } => new_value_hash.unwrap_or_default(),
+            ClaimKind::IsEmpty(_)
+            | ClaimKind::AccountDestructed
+            | ClaimKind::StorageBatch
+            | ClaimKind::StorageWiped
+            | ClaimKind::AccountIsEmpty => Fr::zero(),
+            // The new value is the hash of all the fields of the created account, which has an
+            // empty storage trie.
+            ClaimKind::AccountCreated {
+                nonce,
+                balance,
+                code_hash,
+                poseidon_code_hash,
+                code_size,
+            } => EthAccount {
+                nonce,
+                code_size,
+                balance,
+                keccak_codehash: code_hash,
+                storage_root: Fr::zero(),
+            }
+            .hash(poseidon_code_hash),
         }
     }
 }
@@ -181,47 +432,97 @@ pub struct Proof {
     pub new_account: Option<EthAccount>,
 
     pub account_trie_rows: TrieRows,
+
+    // Account between the nonce update and the update of the other field of a NonceAndField claim.
+    pub intermediate_account_hash_traces: Option<[[Fr; 3]; 6]>,
 }
 
 // TODO: rename to Account
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct EthAccount {
     pub nonce: u64,
     pub code_size: u64,
-    pub balance: Fr,
+    pub balance: U256,
     pub keccak_codehash: U256,
     pub storage_root: Fr,
 }
 
+impl EthAccount {
+    /// Hash of the account fields, which is the value of its leaf in the account trie.
+    pub fn hash(&self, poseidon_code_hash: Fr) -> Fr {
+        let (codehash_hi, codehash_lo) = split_word(self.keccak_codehash);
+        let h1 = domain_hash(codehash_hi, codehash_lo, HashDomain::Pair);
+        let h2 = domain_hash(self.storage_root, h1, HashDomain::AccountFields);
+        let nonce_and_codesize =
+            Fr::from(self.nonce) + Fr::from(self.code_size) * Fr::from(1 << 32).square();
+        let balance = fr_from_u256(self.balance);
+        let h3 = domain_hash(nonce_and_codesize, balance, HashDomain::AccountFields);
+        let h4 = domain_hash(h3, h2, HashDomain::AccountFields);
+        domain_hash(h4, poseidon_code_hash, HashDomain::AccountFields)
+    }
+}
+
+/// Hash of an account with the given storage root, which is the value of its leaf in the account
+/// trie.
+pub(crate) fn account_hash(account: &AccountData, storage_root: Fr) -> Fr {
+    let eth_account = EthAccount {
+        storage_root,
+        ..EthAccount::from(account.clone())
+    };
+    eth_account.hash(big_uint_to_fr(&account.poseidon_code_hash))
+}
+
 impl From<AccountData> for EthAccount {
     fn from(account_data: AccountData) -> Self {
         Self {
             nonce: account_data.nonce,
             code_size: account_data.code_size,
-            balance: fr_from_biguint(&account_data.balance),
+            balance: u256_from_biguint(&account_data.balance),
             keccak_codehash: u256_from_biguint(&account_data.code_hash),
-            storage_root: Fr::zero(), // TODO: fixmeeee!!!
+            storage_root: account_data.storage_root.map(fr).unwrap_or_default(),
         }
     }
 }
 
 impl Proof {
     pub fn n_rows(&self) -> usize {
-        if self.old_account.is_none() && self.new_account.is_none() {
+        if !self.leaf_exists() {
             return 1 + self.address_hash_traces.len();
         }
         1 + self.address_hash_traces.len()
             + match self.claim.kind {
-                ClaimKind::Nonce { .. } => 4,
+                ClaimKind::Nonce { .. } | ClaimKind::NonceRead(_) => 4,
                 ClaimKind::CodeSize { .. } => 4,
-                ClaimKind::Balance { .. } => 4,
+                ClaimKind::Balance { .. } | ClaimKind::BalanceRead(_) => 4,
                 ClaimKind::PoseidonCodeHash { .. } => 2,
                 ClaimKind::CodeHash { .. } => 4,
-                ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)) => 4,
+                ClaimKind::Storage { .. }
+                | ClaimKind::StorageRead { .. }
+                | ClaimKind::IsEmpty(Some(_)) => 4,
                 ClaimKind::IsEmpty(None) => 0,
+                ClaimKind::AccountDestructed => 1,
+                ClaimKind::AccountCreated { .. } => 4,
+                // An extra start row for the update of the other field.
+                ClaimKind::NonceAndField { .. } => 1 + 4,
+                ClaimKind::StorageBatch => 4,
+                ClaimKind::StorageWiped => 4,
+                ClaimKind::AccountIsEmpty => 4,
+                ClaimKind::KeyValue { .. } => 1,
             }
             + self.storage.n_rows()
     }
+
+    /// Whether there is a leaf for the key of the claim before or after the update.
+    pub fn leaf_exists(&self) -> bool {
+        match self.claim.kind {
+            ClaimKind::KeyValue {
+                old_value_hash,
+                new_value_hash,
+                ..
+            } => old_value_hash.is_some() || new_value_hash.is_some(),
+            _ => self.old_account.is_some() || self.new_account.is_some(),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -239,23 +540,55 @@ impl Path {
     }
 }
 
-impl From<(&MPTProofType, &SMTTrace)> for Claim {
-    fn from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Self {
-        let [old_root, new_root] = trace.account_path.clone().map(|path| fr(path.root));
+impl TryFrom<(&MPTProofType, &SMTTrace)> for Claim {
+    type Error = TraceError;
+
+    fn try_from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Result<Self, Self::Error> {
+        let old_root = try_fr(trace.account_path[0].root, "old account root")?;
+        let new_root = try_fr(trace.account_path[1].root, "new account root")?;
         let address = trace.address.0.into();
-        let kind = ClaimKind::from((proof_type, trace));
-        assert_eq!(MPTProofType::from(kind), *proof_type);
-        Self {
+        let kind = ClaimKind::try_from((proof_type, trace))?;
+        if MPTProofType::from(kind) != *proof_type {
+            return Err(TraceError::InconsistentProofType {
+                proof_type: *proof_type,
+                reason: "trace is for a different proof type",
+            });
+        }
+        Ok(Self {
             new_root,
             old_root,
             address,
             kind,
-        }
+        })
     }
 }
 
-impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
-    fn from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Self {
+impl TryFrom<(&MPTProofType, &SMTTrace)> for ClaimKind {
+    type Error = TraceError;
+
+    fn try_from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Result<Self, Self::Error> {
+        let proof_type = *proof_type;
+        let inconsistent = |reason| TraceError::InconsistentProofType { proof_type, reason };
+        let ensure = |condition: bool, reason| {
+            if condition {
+                Ok(())
+            } else {
+                Err(inconsistent(reason))
+            }
+        };
+        let unsupported = |reason| Err(TraceError::Unsupported { proof_type, reason });
+
+        match proof_type {
+            MPTProofType::StorageBatchChanged => {
+                return unsupported(
+                    "storage batches are built from several traces by Proof::storage_batch",
+                )
+            }
+            MPTProofType::KeyValueChanged => {
+                return unsupported("key/value proofs are built from a KeyValueTrace")
+            }
+            _ => {}
+        }
         let [account_old, account_new] = &trace.account_update;
         let state_update = &trace.state_update;
 
@@ -264,24 +597,35 @@ impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
                 [None, None] => (),
                 [Some(old), Some(new)] => {
                     // Accesses to the MPT happen in the order defined in the state (aka rw) circuit, which is not the
-                    // same as the order they occur in the EVM. In the state circuit, nonce and balance modifications
-                    // will precede storage modifications for a given address, which means that the MPT circuit only
-                    // needs to handle storage modifications for existing accounts, even though this is not true in the
-                    // EVM, where the storage of an account can be modified during its construction.
-                    if !(account_old == account_new
-                        || (account_old.is_none() && account_new == &Some(Default::default())))
-                    {
-                        assert_eq!(account_old, account_new, "{state_update:?}");
-                    }
+                    // same as the order they occur in the EVM. In particular, the storage of an account can be
+                    // modified before any of its other fields are set, e.g. during its construction. In that case
+                    // the account leaf is inserted by the storage modification, with all other fields empty and an
+                    // empty old storage root.
+                    ensure(
+                        account_old == account_new
+                            || (account_old.is_none() && account_new == &Some(Default::default())),
+                        "account changed in storage update",
+                    )?;
                     let old_value = u256_from_hex(old.value);
                     let new_value = u256_from_hex(new.value);
 
-                    assert_eq!(old.key, new.key);
+                    ensure(old.key == new.key, "storage key changed")?;
                     let key = u256_from_hex(old.key);
+                    if proof_type == MPTProofType::StorageRead {
+                        ensure(old_value == new_value, "storage value changed for read")?;
+                        ensure(
+                            !old_value.is_zero(),
+                            "use StorageDoesNotExist to read empty storage slot",
+                        )?;
+                        return Ok(ClaimKind::StorageRead {
+                            key,
+                            value: old_value,
+                        });
+                    }
                     if old_value.is_zero() && new_value.is_zero() {
-                        return ClaimKind::IsEmpty(Some(key));
+                        return Ok(ClaimKind::IsEmpty(Some(key)));
                     }
-                    return ClaimKind::Storage {
+                    return Ok(ClaimKind::Storage {
                         key,
                         old_value: if old_value.is_zero() {
                             None
@@ -293,14 +637,16 @@ impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
                         } else {
                             Some(new_value)
                         },
-                    };
+                    });
+                }
+                [None, Some(_)] | [Some(_), None] => {
+                    return Err(TraceError::MissingField("state_update"))
                 }
-                [None, Some(_)] | [Some(_), None] => unreachable!(),
             }
         }
 
-        match &trace.account_update {
-            [None, None] => match *proof_type {
+        Ok(match &trace.account_update {
+            [None, None] => match proof_type {
                 MPTProofType::NonceChanged => ClaimKind::Nonce {
                     old: Some(0),
                     new: Some(0),
@@ -319,128 +665,461 @@ impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
                     new: Some(0),
                 },
                 MPTProofType::StorageDoesNotExist => {
-                    ClaimKind::IsEmpty(Some(u256_from_hex(trace.state_key.unwrap())))
+                    let state_key = trace
+                        .state_key
+                        .ok_or(TraceError::MissingField("state_key"))?;
+                    ClaimKind::IsEmpty(Some(u256_from_hex(state_key)))
+                }
+                MPTProofType::PoseidonCodeHashExists | MPTProofType::StorageChanged => {
+                    return Err(inconsistent("account does not exist"))
+                }
+                MPTProofType::AccountDestructed => {
+                    return Err(inconsistent("cannot destruct empty account"))
+                }
+                MPTProofType::AccountCreated => {
+                    return Err(inconsistent("cannot create empty account"))
+                }
+                MPTProofType::NonceRead | MPTProofType::BalanceRead | MPTProofType::StorageRead => {
+                    return Err(inconsistent(
+                        "use AccountDoesNotExist to read from empty account",
+                    ))
+                }
+                MPTProofType::NonceAndFieldChanged => {
+                    return unsupported(
+                        "nonce and other field can only be updated together for existing accounts",
+                    )
+                }
+                MPTProofType::StorageBatchChanged | MPTProofType::KeyValueChanged => unreachable!(),
+                MPTProofType::StorageWiped => {
+                    return Err(inconsistent("cannot wipe storage of empty account"))
+                }
+                MPTProofType::AccountIsEmpty => {
+                    return Err(inconsistent(
+                        "use AccountDoesNotExist for accounts without a leaf",
+                    ))
                 }
-                MPTProofType::PoseidonCodeHashExists => unreachable!(),
-                MPTProofType::StorageChanged => unreachable!(),
-                MPTProofType::AccountDestructed => unimplemented!(),
             },
-            [None, Some(new)] => {
-                if !new.nonce.is_zero() {
-                    assert_eq!(*proof_type, MPTProofType::NonceChanged);
+            [None, Some(new)] => match proof_type {
+                MPTProofType::NonceAndFieldChanged => {
+                    return unsupported(
+                        "nonce and other field can only be updated together for existing accounts",
+                    )
+                }
+                MPTProofType::StorageWiped => {
+                    return Err(inconsistent("cannot wipe storage of empty account"))
+                }
+                MPTProofType::AccountIsEmpty => {
+                    return Err(inconsistent(
+                        "use AccountDoesNotExist for accounts without a leaf",
+                    ))
+                }
+                MPTProofType::AccountCreated => {
+                    ensure(
+                        trace.common_state_root == Some(HexBytes::default()),
+                        "created account must have empty storage",
+                    )?;
+                    ClaimKind::AccountCreated {
+                        nonce: new.nonce,
+                        balance: u256_from_biguint(&new.balance),
+                        code_hash: u256_from_biguint(&new.code_hash),
+                        poseidon_code_hash: big_uint_to_fr(&new.poseidon_code_hash),
+                        code_size: new.code_size,
+                    }
+                }
+                _ if !new.nonce.is_zero() => {
+                    ensure(
+                        proof_type == MPTProofType::NonceChanged,
+                        "nonce of new account changed",
+                    )?;
                     ClaimKind::Nonce {
                         old: None,
                         new: Some(new.nonce),
                     }
-                } else if !new.balance.is_zero() {
-                    assert_eq!(*proof_type, MPTProofType::BalanceChanged);
+                }
+                _ if !new.balance.is_zero() => {
+                    ensure(
+                        proof_type == MPTProofType::BalanceChanged,
+                        "balance of new account changed",
+                    )?;
                     ClaimKind::Balance {
                         old: None,
                         new: Some(u256_from_biguint(&new.balance)),
                     }
-                } else {
-                    unimplemented!("nonce or balance must be first field set on empty account");
                 }
-            }
-            [Some(old), Some(new)] => match *proof_type {
+                _ => {
+                    return unsupported(
+                        "nonce or balance must be first field set on empty account, use AccountCreated to set several fields",
+                    )
+                }
+            },
+            [Some(old), Some(new)] => match proof_type {
                 MPTProofType::NonceChanged => {
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.code_hash, new.code_hash);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    ensure(old.balance == new.balance, "balance changed")?;
+                    ensure(old.code_size == new.code_size, "code size changed")?;
+                    ensure(old.code_hash == new.code_hash, "code hash changed")?;
+                    ensure(
+                        old.poseidon_code_hash == new.poseidon_code_hash,
+                        "poseidon code hash changed",
+                    )?;
                     ClaimKind::Nonce {
                         old: Some(old.nonce),
                         new: Some(new.nonce),
                     }
                 }
                 MPTProofType::BalanceChanged => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.code_hash, new.code_hash);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    ensure(old.nonce == new.nonce, "nonce changed")?;
+                    ensure(old.code_size == new.code_size, "code size changed")?;
+                    ensure(old.code_hash == new.code_hash, "code hash changed")?;
+                    ensure(
+                        old.poseidon_code_hash == new.poseidon_code_hash,
+                        "poseidon code hash changed",
+                    )?;
                     ClaimKind::Balance {
                         old: Some(u256_from_biguint(&old.balance)),
                         new: Some(u256_from_biguint(&new.balance)),
                     }
                 }
                 MPTProofType::CodeHashExists => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    ensure(old.nonce == new.nonce, "nonce changed")?;
+                    ensure(old.balance == new.balance, "balance changed")?;
+                    ensure(old.code_size == new.code_size, "code size changed")?;
+                    ensure(
+                        old.poseidon_code_hash == new.poseidon_code_hash,
+                        "poseidon code hash changed",
+                    )?;
                     ClaimKind::CodeHash {
                         old: Some(u256_from_biguint(&old.code_hash)),
                         new: Some(u256_from_biguint(&new.code_hash)),
                     }
                 }
                 MPTProofType::CodeSizeExists => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_hash, new.code_hash);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    ensure(old.nonce == new.nonce, "nonce changed")?;
+                    ensure(old.balance == new.balance, "balance changed")?;
+                    ensure(old.code_hash == new.code_hash, "code hash changed")?;
+                    ensure(
+                        old.poseidon_code_hash == new.poseidon_code_hash,
+                        "poseidon code hash changed",
+                    )?;
                     ClaimKind::CodeSize {
                         old: Some(old.code_size),
                         new: Some(new.code_size),
                     }
                 }
                 MPTProofType::PoseidonCodeHashExists => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.code_hash, new.code_hash);
+                    ensure(old.nonce == new.nonce, "nonce changed")?;
+                    ensure(old.balance == new.balance, "balance changed")?;
+                    ensure(old.code_size == new.code_size, "code size changed")?;
+                    ensure(old.code_hash == new.code_hash, "code hash changed")?;
                     ClaimKind::PoseidonCodeHash {
                         old: Some(big_uint_to_fr(&old.poseidon_code_hash)),
                         new: Some(big_uint_to_fr(&new.poseidon_code_hash)),
                     }
                 }
+                MPTProofType::NonceRead => {
+                    ensure(old == new, "account changed for nonce read")?;
+                    ClaimKind::NonceRead(new.nonce)
+                }
+                MPTProofType::BalanceRead => {
+                    ensure(old == new, "account changed for balance read")?;
+                    ClaimKind::BalanceRead(u256_from_biguint(&new.balance))
+                }
+                MPTProofType::StorageWiped => {
+                    ensure(old == new, "account changed for storage wipe")?;
+                    ClaimKind::StorageWiped
+                }
+                MPTProofType::AccountIsEmpty => {
+                    ensure(old == new, "account changed for empty account read")?;
+                    ensure(old.nonce == 0, "account has non-zero nonce")?;
+                    ensure(old.balance.is_zero(), "account has non-zero balance")?;
+                    ensure(old.code_size == 0, "account has code")?;
+                    ensure(
+                        is_empty_code(
+                            u256_from_biguint(&old.code_hash),
+                            big_uint_to_fr(&old.poseidon_code_hash),
+                        ),
+                        "account has code",
+                    )?;
+                    ClaimKind::AccountIsEmpty
+                }
+                MPTProofType::NonceAndFieldChanged => {
+                    let balance = FieldUpdate::Balance {
+                        old: u256_from_biguint(&old.balance),
+                        new: u256_from_biguint(&new.balance),
+                    };
+                    let mut changed_fields = [
+                        (old.balance != new.balance).then_some(balance),
+                        (old.code_hash != new.code_hash).then_some(FieldUpdate::CodeHash {
+                            old: u256_from_biguint(&old.code_hash),
+                            new: u256_from_biguint(&new.code_hash),
+                        }),
+                        (old.poseidon_code_hash != new.poseidon_code_hash).then_some(
+                            FieldUpdate::PoseidonCodeHash {
+                                old: big_uint_to_fr(&old.poseidon_code_hash),
+                                new: big_uint_to_fr(&new.poseidon_code_hash),
+                            },
+                        ),
+                        (old.code_size != new.code_size).then_some(FieldUpdate::CodeSize {
+                            old: old.code_size,
+                            new: new.code_size,
+                        }),
+                    ]
+                    .into_iter()
+                    .flatten();
+                    // If only the nonce changed, the other field is the unchanged balance.
+                    let field = changed_fields.next().unwrap_or(balance);
+                    ensure(
+                        changed_fields.next().is_none(),
+                        "only one other field can change together with nonce",
+                    )?;
+                    ClaimKind::NonceAndField {
+                        old_nonce: old.nonce,
+                        new_nonce: new.nonce,
+                        field,
+                    }
+                }
                 MPTProofType::AccountDoesNotExist
+                | MPTProofType::StorageRead
                 | MPTProofType::StorageChanged
-                | MPTProofType::StorageDoesNotExist => unreachable!(),
-                MPTProofType::AccountDestructed => unimplemented!(),
+                | MPTProofType::StorageDoesNotExist
+                | MPTProofType::AccountDestructed
+                | MPTProofType::AccountCreated => {
+                    return Err(inconsistent("account exists and is not deleted"))
+                }
+                MPTProofType::StorageBatchChanged | MPTProofType::KeyValueChanged => unreachable!(),
             },
-            [Some(_old), None] => unimplemented!("SELFDESTRUCT"),
-        }
+            [Some(_old), None] => {
+                ensure(
+                    proof_type == MPTProofType::AccountDestructed,
+                    "account is deleted",
+                )?;
+                ClaimKind::AccountDestructed
+            }
+        })
+    }
+}
+
+impl TryFrom<(&MPTProofType, &SMTTrace)> for Proof {
+    type Error = TraceError;
+
+    fn try_from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Result<Self, Self::Error> {
+        check_field_elements(trace)?;
+        path_shape::check_paths(trace)?;
+        let claim = Claim::try_from((proof_type, trace))?;
+        let storage = StorageProof::try_from(trace)?;
+        Self::new(claim, storage, trace)
     }
 }
 
 impl From<(MPTProofType, SMTTrace)> for Proof {
-    fn from((proof, trace): (MPTProofType, SMTTrace)) -> Self {
-        let claim = Claim::from((&proof, &trace));
+    fn from((proof_type, trace): (MPTProofType, SMTTrace)) -> Self {
+        Self::try_from((&proof_type, &trace)).unwrap()
+    }
+}
+
+impl From<KeyValueTrace> for Proof {
+    fn from(trace: KeyValueTrace) -> Self {
+        Self::try_from(trace).unwrap()
+    }
+}
+
+impl TryFrom<KeyValueTrace> for Proof {
+    type Error = TraceError;
+
+    fn try_from(trace: KeyValueTrace) -> Result<Self, Self::Error> {
+        let key = try_fr(trace.key, "key")?;
+        for path in &trace.path {
+            check_path_field_elements(path, "path")?;
+        }
+        let [old_root, new_root] = trace.path.clone().map(|path| fr(path.root));
+        let [old_value_hash, new_value_hash] = trace.path.clone().map(|path| {
+            path.leaf
+                .filter(|leaf| fr(leaf.sibling) == key)
+                .map(|leaf| fr(leaf.value))
+        });
+        let claim = Claim {
+            old_root,
+            new_root,
+            address: Address::zero(),
+            kind: ClaimKind::KeyValue {
+                key,
+                old_value_hash,
+                new_value_hash,
+            },
+        };
+
+        let account_trie_rows = TrieRows::new(
+            "path",
+            key,
+            &trace.path[0].path,
+            &trace.path[1].path,
+            trace.path[0].leaf,
+            trace.path[1].leaf,
+        )?;
+
+        let leafs = trace.path.clone().map(get_leaf);
+        let leaf_hashes = trace.path.clone().map(leaf_hash);
+        let address_hash_traces = get_internal_hash_traces(
+            "path",
+            key,
+            leaf_hashes,
+            &trace.path[0].path,
+            &trace.path[1].path,
+        )?;
+        check_hash_traces_new(&address_hash_traces)?;
+
+        let [old, new] = trace.path.map(|path| Path {
+            key: path.leaf.map_or(key, |leaf| fr(leaf.sibling)),
+            leaf_data_hash: path.leaf.map(|leaf| fr(leaf.value)),
+        });
+
+        Ok(Self {
+            claim,
+            address_hash_traces,
+            old_account_hash_traces: empty_account_hash_traces(leafs[0]),
+            new_account_hash_traces: empty_account_hash_traces(leafs[1]),
+            leafs,
+            storage: StorageProof::Root(Fr::zero()),
+            old,
+            new,
+            old_account: None,
+            new_account: None,
+            account_trie_rows,
+            intermediate_account_hash_traces: None,
+        })
+    }
+}
+
+impl Proof {
+    /// Combines the traces of several storage updates to the same account into a single proof,
+    /// where the account path is only proven once, from the first storage root to the last one.
+    pub fn storage_batch(traces: Vec<SMTTrace>) -> Result<Self, TraceError> {
+        let proof_type = MPTProofType::StorageBatchChanged;
+        let inconsistent = |reason| TraceError::InconsistentProofType { proof_type, reason };
+        let (first, last) = match (traces.first(), traces.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(inconsistent("storage batch must have at least one update")),
+        };
+        for (trace, next_trace) in traces.iter().tuple_windows() {
+            if trace.account_path[1].root != next_trace.account_path[0].root {
+                return Err(TraceError::RootMismatch {
+                    name: "old root of storage update in batch",
+                    expected: try_fr(trace.account_path[1].root, "new root")?,
+                    found: try_fr(next_trace.account_path[0].root, "old root")?,
+                });
+            }
+        }
+        for trace in traces.iter() {
+            if trace.address != first.address {
+                return Err(inconsistent("storage batch must be for one account"));
+            }
+            let [old_account, new_account] = &trace.account_update;
+            if old_account.is_none() {
+                return Err(inconsistent("storage batch account must exist"));
+            }
+            if old_account != new_account {
+                return Err(inconsistent("storage batch cannot change account"));
+            }
+            if !matches!(
+                ClaimKind::try_from((&MPTProofType::StorageChanged, trace)),
+                Ok(ClaimKind::Storage { .. })
+            ) {
+                return Err(inconsistent(
+                    "storage batch can only contain storage changes",
+                ));
+            }
+        }
+
+        let mut trace = first.clone();
+        trace.account_path[1] = last.account_path[1].clone();
+        trace.account_update[1] = last.account_update[1].clone();
 
-        let storage = StorageProof::from(&trace);
+        let claim = Claim {
+            old_root: try_fr(first.account_path[0].root, "old root")?,
+            new_root: try_fr(last.account_path[1].root, "new root")?,
+            address: first.address.0.into(),
+            kind: ClaimKind::StorageBatch,
+        };
+        let storage = StorageProof::Batch(
+            traces
+                .iter()
+                .map(StorageProof::try_from)
+                .collect::<Result<_, _>>()?,
+        );
+        Self::new(claim, storage, &trace)
+    }
 
+    fn new(claim: Claim, storage: StorageProof, trace: &SMTTrace) -> Result<Self, TraceError> {
         let key = account_key(claim.address);
-        assert_eq!(key, fr(trace.account_key));
+        if key != fr(trace.account_key) {
+            return Err(TraceError::KeyHashMismatch {
+                name: "account key",
+                expected: key,
+                found: fr(trace.account_key),
+            });
+        }
 
         let account_trie_rows = TrieRows::new(
+            "account path",
             fr(trace.account_key),
             &trace.account_path[0].path,
             &trace.account_path[1].path,
             trace.account_path[0].leaf,
             trace.account_path[1].leaf,
-        );
+        )?;
 
         let leafs = trace.account_path.clone().map(get_leaf);
         let [open_hash_traces, close_hash_traces] =
             trace.account_path.clone().map(|path| path.path);
         let leaf_hashes = trace.account_path.clone().map(leaf_hash);
-        let address_hash_traces =
-            get_internal_hash_traces(key, leaf_hashes, &open_hash_traces, &close_hash_traces);
-        check_hash_traces_new(&address_hash_traces);
-
-        let [old_account, new_account] = trace.account_update;
+        let address_hash_traces = get_internal_hash_traces(
+            "account path",
+            key,
+            leaf_hashes,
+            &open_hash_traces,
+            &close_hash_traces,
+        )?;
+        check_hash_traces_new(&address_hash_traces)?;
+
+        let [old_account, new_account] = trace.account_update.clone();
+        let old_storage_root =
+            account_storage_root(old_account.as_ref(), storage.old_root(), "old storage root")?;
+        let new_storage_root =
+            account_storage_root(new_account.as_ref(), storage.new_root(), "new storage root")?;
         let old_account_hash_traces = match old_account.clone() {
             None => empty_account_hash_traces(leafs[0]),
-            Some(account) => account_hash_traces(claim.address, account, storage.old_root()),
+            Some(account) => account_hash_traces(claim.address, account, old_storage_root),
         };
         let new_account_hash_traces = match new_account.clone() {
             None => empty_account_hash_traces(leafs[1]),
-            Some(account) => account_hash_traces(claim.address, account, storage.new_root()),
+            Some(account) => account_hash_traces(claim.address, account, new_storage_root),
+        };
+        for (name, account_hash_traces, leaf_hash) in [
+            ("old account", old_account_hash_traces, leaf_hashes[0]),
+            ("new account", new_account_hash_traces, leaf_hashes[1]),
+        ] {
+            if account_hash_traces[5][2] != leaf_hash {
+                return Err(TraceError::LeafHashMismatch {
+                    name,
+                    expected: account_hash_traces[5][2],
+                    found: leaf_hash,
+                });
+            }
+        }
+
+        let intermediate_account_hash_traces = match claim.kind {
+            ClaimKind::NonceAndField { new_nonce, .. } => {
+                let mut account = old_account.clone().unwrap();
+                account.nonce = new_nonce;
+                Some(account_hash_traces(
+                    claim.address,
+                    account,
+                    old_storage_root,
+                ))
+            }
+            _ => None,
         };
-        assert_eq!(old_account_hash_traces[5][2], leaf_hashes[0]);
-        assert_eq!(new_account_hash_traces[5][2], leaf_hashes[1]);
 
-        let [old, new] = trace.account_path.map(|path| {
+        let [old, new] = trace.account_path.clone().map(|path| {
             // The account_key(address) if the account exists
             // else: path.leaf.sibling if it's a type 1 non-existence proof
             // otherwise account_key(address) if it's a type 2 non-existence proof
@@ -459,7 +1138,7 @@ impl From<(MPTProofType, SMTTrace)> for Proof {
         let old_account = match old_account {
             Some(account_data) => {
                 let mut account = EthAccount::from(account_data);
-                account.storage_root = storage.old_root();
+                account.storage_root = old_storage_root;
                 Some(account)
             }
             None => None,
@@ -467,13 +1146,13 @@ impl From<(MPTProofType, SMTTrace)> for Proof {
         let new_account = match new_account {
             Some(account_data) => {
                 let mut account = EthAccount::from(account_data);
-                account.storage_root = storage.new_root();
+                account.storage_root = new_storage_root;
                 Some(account)
             }
             None => None,
         };
 
-        Self {
+        Ok(Self {
             claim,
             address_hash_traces,
             old_account_hash_traces,
@@ -485,10 +1164,68 @@ impl From<(MPTProofType, SMTTrace)> for Proof {
             old_account,
             new_account,
             account_trie_rows,
+            intermediate_account_hash_traces,
+        })
+    }
+}
+
+// Everything in the trace that is converted with `fr` must be a canonical field element, since
+// `fr` panics otherwise.
+fn check_field_elements(trace: &SMTTrace) -> Result<(), TraceError> {
+    try_fr(trace.account_key, "account key")?;
+    for path in &trace.account_path {
+        check_path_field_elements(path, "account path")?;
+    }
+    for path in trace.state_path.iter().flatten() {
+        check_path_field_elements(path, "storage path")?;
+    }
+    if let Some(root) = trace.common_state_root {
+        try_fr(root, "common state root")?;
+    }
+    if let Some(key) = trace.state_key {
+        try_fr(key, "state key")?;
+    }
+    for account in trace.account_update.iter().flatten() {
+        // The circuit range checks balances to 31 bytes, so that they are always less than the
+        // modulus.
+        if account.balance.bits() > 248 {
+            return Err(TraceError::BalanceOutOfRange);
+        }
+        if let Some(root) = account.storage_root {
+            try_fr(root, "account storage root")?;
+        }
+    }
+    Ok(())
+}
+
+// The storage root of an account is the root of its storage proof. If the trace also gives it in
+// the account data, the two must agree.
+fn account_storage_root(
+    account: Option<&AccountData>,
+    storage_root: Fr,
+    name: &'static str,
+) -> Result<Fr, TraceError> {
+    match account.and_then(|account| account.storage_root).map(fr) {
+        Some(account_storage_root) if account_storage_root != storage_root => {
+            Err(TraceError::RootMismatch {
+                name,
+                expected: storage_root,
+                found: account_storage_root,
+            })
         }
+        _ => Ok(storage_root),
     }
 }
 
+fn check_path_field_elements(path: &SMTPath, name: &'static str) -> Result<(), TraceError> {
+    try_fr(path.root, name)?;
+    for node in path.path.iter().chain(path.leaf.iter()) {
+        try_fr(node.value, name)?;
+        try_fr(node.sibling, name)?;
+    }
+    Ok(())
+}
+
 // This should be an optional
 fn get_leaf(path: SMTPath) -> Option<LeafNode> {
     path.leaf.map(|leaf| LeafNode {
@@ -537,11 +1274,12 @@ fn account_hash_traces(address: Address, account: AccountData, storage_root: Fr)
 }
 
 fn get_internal_hash_traces(
+    name: &'static str,
     key: Fr,
     leaf_hashes: [Fr; 2],
     open_hash_traces: &[SMTNode],
     close_hash_traces: &[SMTNode],
-) -> Vec<(bool, HashDomain, Fr, Fr, Fr, bool, bool)> {
+) -> Result<Vec<(bool, HashDomain, Fr, Fr, Fr, bool, bool)>, TraceError> {
     let mut address_hash_traces = vec![];
     for (i, e) in open_hash_traces
         .iter()
@@ -550,44 +1288,24 @@ fn get_internal_hash_traces(
     {
         let direction = key.bit(i);
         address_hash_traces.push(match e {
-            EitherOrBoth::Both(open, close) => {
-                assert_eq!(open.sibling, close.sibling);
-                let open_domain = HashDomain::try_from(open.node_type).unwrap();
-                let close_domain = HashDomain::try_from(close.node_type).unwrap();
-
-                let domain = if open_domain != close_domain {
-                    // This can only happen when inserting or deleting a node.
-                    assert!(open_hash_traces.len() != close_hash_traces.len());
-                    assert!(
-                        i == std::cmp::min(open_hash_traces.len(), close_hash_traces.len()) - 1
-                    );
-
-                    if i == open_hash_traces.len() - 1 {
-                        // Inserting a leaf, so open is before insertion, close is after insertion.
-                        check_domain_consistency(open_domain, close_domain, direction);
-                        open_domain
-                    } else {
-                        // Deleting a leaf, so open is after insertion, close is before insertion.
-                        check_domain_consistency(close_domain, open_domain, direction);
-                        close_domain
-                    }
-                } else {
-                    open_domain
-                };
-
-                (
+            EitherOrBoth::Both(open, close) => (
+                direction,
+                common_domain(
+                    name,
+                    i,
+                    [open, close],
+                    [open_hash_traces.len(), close_hash_traces.len()],
                     direction,
-                    domain,
-                    fr(open.value),
-                    fr(close.value),
-                    fr(open.sibling),
-                    false,
-                    false,
-                )
-            }
+                )?,
+                fr(open.value),
+                fr(close.value),
+                fr(open.sibling),
+                false,
+                false,
+            ),
             EitherOrBoth::Left(open) => (
                 direction,
-                HashDomain::try_from(open.node_type).unwrap(),
+                node_domain(name, i, open)?,
                 fr(open.value),
                 leaf_hashes[1],
                 fr(open.sibling),
@@ -596,7 +1314,7 @@ fn get_internal_hash_traces(
             ),
             EitherOrBoth::Right(close) => (
                 direction,
-                HashDomain::try_from(close.node_type).unwrap(),
+                node_domain(name, i, close)?,
                 leaf_hashes[0],
                 fr(close.value),
                 fr(close.sibling),
@@ -606,7 +1324,14 @@ fn get_internal_hash_traces(
         });
     }
     address_hash_traces.reverse();
-    address_hash_traces
+    Ok(address_hash_traces)
+}
+
+// Accounts without code have the zero code hashes if they were created by setting their nonce or
+// balance, and the hashes of empty code if they were created by a contract creation.
+fn is_empty_code(keccak_code_hash: U256, poseidon_code_hash: Fr) -> bool {
+    (keccak_code_hash.is_zero() || keccak_code_hash == empty_keccak_code_hash())
+        && (poseidon_code_hash == Fr::zero() || poseidon_code_hash == empty_poseidon_code_hash())
 }
 
 fn empty_account_hash_traces(leaf: Option<LeafNode>) -> [[Fr; 3]; 6] {
@@ -633,6 +1358,18 @@ impl Proof {
                 let old_nonce_and_codesize = old_account_hash_traces[2][0];
                 vec![old_account_hash, old_h4, old_h3, old_nonce_and_codesize]
             }),
+            ClaimKind::NonceRead(_) | ClaimKind::NonceAndField { .. } => {
+                let old_account_hash = old_account_hash_traces[5][1];
+                let old_h4 = old_account_hash_traces[4][0];
+                let old_h3 = old_account_hash_traces[3][0];
+                let old_nonce_and_codesize = old_account_hash_traces[2][0];
+                Some(vec![
+                    old_account_hash,
+                    old_h4,
+                    old_h3,
+                    old_nonce_and_codesize,
+                ])
+            }
             ClaimKind::Balance { old, .. } => old.map(|_| {
                 let old_account_hash = old_account_hash_traces[5][1];
                 let old_h4 = old_account_hash_traces[4][0];
@@ -640,6 +1377,13 @@ impl Proof {
                 let old_balance = old_account_hash_traces[2][1];
                 vec![old_account_hash, old_h4, old_h3, old_balance]
             }),
+            ClaimKind::BalanceRead(_) => {
+                let old_account_hash = old_account_hash_traces[5][1];
+                let old_h4 = old_account_hash_traces[4][0];
+                let old_h3 = old_account_hash_traces[3][0];
+                let old_balance = old_account_hash_traces[2][1];
+                Some(vec![old_account_hash, old_h4, old_h3, old_balance])
+            }
             ClaimKind::PoseidonCodeHash { old, .. } => old.map(|_| {
                 let old_account_hash = old_account_hash_traces[5][1];
                 let old_poseidon_code_hash = old_account_hash_traces[4][1];
@@ -652,7 +1396,20 @@ impl Proof {
                 let old_h1 = old_account_hash_traces[0][2];
                 vec![old_account_hash, old_h4, old_h2, old_h1]
             }),
-            ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)) => self.old_account.map(|_| {
+            // The account is opened down to the keccak code hash, with the other fields as
+            // siblings.
+            ClaimKind::AccountIsEmpty => {
+                let old_account_hash = old_account_hash_traces[5][1];
+                let old_h4 = old_account_hash_traces[4][0];
+                let old_h2 = old_account_hash_traces[1][2];
+                let old_h1 = old_account_hash_traces[0][2];
+                Some(vec![old_account_hash, old_h4, old_h2, old_h1])
+            }
+            ClaimKind::Storage { .. }
+            | ClaimKind::StorageRead { .. }
+            | ClaimKind::IsEmpty(Some(_))
+            | ClaimKind::StorageBatch
+            | ClaimKind::StorageWiped => self.old_account.map(|_| {
                 let old_account_hash = old_account_hash_traces[5][1];
                 let old_h4 = old_account_hash_traces[4][0];
                 let old_h2 = old_account_hash_traces[1][2];
@@ -663,6 +1420,12 @@ impl Proof {
                 let old_account_hash = old_account_hash_traces[5][1];
                 vec![old_account_hash]
             }),
+            ClaimKind::AccountDestructed => {
+                let old_account_hash = old_account_hash_traces[5][1];
+                Some(vec![old_account_hash])
+            }
+            ClaimKind::AccountCreated { .. } => None,
+            ClaimKind::KeyValue { old_value_hash, .. } => old_value_hash.map(|hash| vec![hash]),
         }
     }
 
@@ -695,7 +1458,26 @@ impl Proof {
                 let new_h1 = new_account_hash_traces[0][2];
                 vec![new_account_hash, new_h4, new_h2, new_h1]
             }),
-            ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)) => {
+            // The new hashes are those of the account after the nonce update.
+            ClaimKind::NonceAndField { .. } => {
+                self.intermediate_account_hash_traces
+                    .map(|account_hash_traces| {
+                        let account_hash = account_hash_traces[5][1];
+                        let h4 = account_hash_traces[4][0];
+                        let h3 = account_hash_traces[3][0];
+                        let nonce_and_codesize = account_hash_traces[2][0];
+                        vec![account_hash, h4, h3, nonce_and_codesize]
+                    })
+            }
+            // Reads don't change the account, so the new hashes are the same as the old ones.
+            ClaimKind::NonceRead(_) | ClaimKind::BalanceRead(_) | ClaimKind::AccountIsEmpty => {
+                self.old_account_leaf_hashes()
+            }
+            ClaimKind::Storage { .. }
+            | ClaimKind::StorageRead { .. }
+            | ClaimKind::IsEmpty(Some(_))
+            | ClaimKind::StorageBatch
+            | ClaimKind::StorageWiped => {
                 let new_account_hash = new_account_hash_traces[5][1];
                 let new_h4 = new_account_hash_traces[4][0];
                 let new_h2 = new_account_hash_traces[1][2];
@@ -706,6 +1488,21 @@ impl Proof {
                 let new_account_hash = new_account_hash_traces[5][1];
                 vec![new_account_hash]
             }),
+            // The account leaf is removed, so the new hashes are those of the new path.
+            ClaimKind::AccountDestructed => None,
+            ClaimKind::KeyValue { new_value_hash, .. } => new_value_hash.map(|hash| vec![hash]),
+            ClaimKind::AccountCreated { .. } => {
+                let new_account_hash = new_account_hash_traces[5][1];
+                let new_h4 = new_account_hash_traces[4][0];
+                let new_h3 = new_account_hash_traces[3][0];
+                let new_nonce_and_codesize = new_account_hash_traces[2][0];
+                Some(vec![
+                    new_account_hash,
+                    new_h4,
+                    new_h3,
+                    new_nonce_and_codesize,
+                ])
+            }
         }
     }
 
@@ -736,6 +1533,18 @@ impl Proof {
 
                 vec![account_key, poseidon_codehash, h2, nonce_and_codesize]
             }
+            ClaimKind::NonceRead(_) | ClaimKind::NonceAndField { .. } => {
+                let balance = self.old_account_hash_traces[2][1];
+                let h2 = self.old_account_hash_traces[3][1];
+                let poseidon_codehash = self.old_account_hash_traces[4][1];
+                vec![account_key, poseidon_codehash, h2, balance]
+            }
+            ClaimKind::BalanceRead(_) => {
+                let nonce_and_codesize = self.old_account_hash_traces[2][0];
+                let h2 = self.old_account_hash_traces[3][1];
+                let poseidon_codehash = self.old_account_hash_traces[4][1];
+                vec![account_key, poseidon_codehash, h2, nonce_and_codesize]
+            }
             ClaimKind::PoseidonCodeHash { old, new } => {
                 let account_hash_traces = match (old, new) {
                     (Some(_), _) => self.old_account_hash_traces,
@@ -757,7 +1566,18 @@ impl Proof {
                 let storage_root = account_hash_traces[1][0];
                 vec![account_key, poseidon_codehash, h3, storage_root]
             }
-            ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)) => {
+            ClaimKind::Storage { .. } if self.old_account.is_none() => {
+                // The account is created by the storage update, so only the new account exists.
+                let poseidon_codehash = self.new_account_hash_traces[4][1];
+                let h3 = self.new_account_hash_traces[3][0];
+                let keccak_codehash_hash = self.new_account_hash_traces[1][1];
+                vec![account_key, poseidon_codehash, h3, keccak_codehash_hash]
+            }
+            ClaimKind::Storage { .. }
+            | ClaimKind::StorageRead { .. }
+            | ClaimKind::IsEmpty(Some(_))
+            | ClaimKind::StorageBatch
+            | ClaimKind::StorageWiped => {
                 assert_eq!(
                     self.old_account_hash_traces[4][1],
                     self.new_account_hash_traces[4][1]
@@ -776,26 +1596,404 @@ impl Proof {
                 let keccak_codehash_hash = self.old_account_hash_traces[1][1];
                 vec![account_key, poseidon_codehash, h3, keccak_codehash_hash]
             }
+            ClaimKind::AccountIsEmpty => {
+                let poseidon_codehash = self.old_account_hash_traces[4][1];
+                let h3 = self.old_account_hash_traces[3][0];
+                let storage_root = self.old_account_hash_traces[1][0];
+                vec![account_key, poseidon_codehash, h3, storage_root]
+            }
             ClaimKind::IsEmpty(None) => vec![],
+            ClaimKind::AccountDestructed => vec![account_key],
+            ClaimKind::KeyValue { key, .. } => vec![key],
+            ClaimKind::AccountCreated { .. } => {
+                let balance = self.new_account_hash_traces[2][1];
+                let h2 = self.new_account_hash_traces[3][1];
+                let poseidon_codehash = self.new_account_hash_traces[4][1];
+                vec![account_key, poseidon_codehash, h2, balance]
+            }
         }
     }
 
+    /// Hashes of the account leaf after the update of the other field of a NonceAndField claim,
+    /// in the same order as the old and new account leaf hashes.
+    pub fn nonce_and_field_leaf_hashes(&self) -> Option<Vec<Fr>> {
+        match self.claim.kind {
+            ClaimKind::NonceAndField { .. } => {
+                let account_hash = self.new_account_hash_traces[5][1];
+                let h4 = self.new_account_hash_traces[4][0];
+                let h3 = self.new_account_hash_traces[3][0];
+                let nonce_and_codesize = self.new_account_hash_traces[2][0];
+                Some(vec![account_hash, h4, h3, nonce_and_codesize])
+            }
+            _ => None,
+        }
+    }
+
+    /// Siblings of the account leaf after the update of the other field of a NonceAndField claim.
+    /// They differ from the account leaf siblings at the row of the other field, except for the
+    /// code size, which is in the same field element as the nonce.
+    pub fn nonce_and_field_leaf_siblings(&self) -> Option<Vec<Fr>> {
+        match self.claim.kind {
+            ClaimKind::NonceAndField { .. } => {
+                let balance = self.new_account_hash_traces[2][1];
+                let h2 = self.new_account_hash_traces[3][1];
+                let poseidon_codehash = self.new_account_hash_traces[4][1];
+                Some(vec![self.claim.key(), poseidon_codehash, h2, balance])
+            }
+            _ => None,
+        }
+    }
+
+    /// Account trie rows of a NonceAndField claim, with the new hashes replaced by the ones
+    /// between the nonce update and the update of the other field.
+    pub fn intermediate_account_trie_rows(&self) -> Option<TrieRows> {
+        self.intermediate_account_hash_traces
+            .map(|traces| self.account_trie_rows.with_new_leaf_hash(traces[5][2]))
+    }
+
+    /// State root between the nonce update and the update of the other field of a NonceAndField
+    /// claim.
+    pub fn intermediate_root(&self) -> Option<Fr> {
+        self.intermediate_account_hash_traces.map(|traces| {
+            self.account_trie_rows
+                .with_new_leaf_hash(traces[5][2])
+                .new_root(|| traces[5][2])
+        })
+    }
+
+    /// Recomputes the old and new roots from the trie rows and leaves of the proof, and checks them
+    /// and the account or storage values against the claim.
+    pub fn verify(&self) -> Result<(), VerificationError> {
+        self.storage.verify()?;
+
+        let key = self.claim.key();
+        self.account_trie_rows.verify(
+            [self.claim.old_root, self.claim.new_root],
+            [self.old.hash(), self.new.hash()],
+            key,
+            [self.old.key, self.new.key],
+        )?;
+
+        let [old_poseidon_code_hash, new_poseidon_code_hash] =
+            [self.old_account_hash_traces, self.new_account_hash_traces].map(|traces| traces[4][1]);
+        for (name, account, poseidon_code_hash, path) in [
+            (
+                "old account",
+                self.old_account,
+                old_poseidon_code_hash,
+                &self.old,
+            ),
+            (
+                "new account",
+                self.new_account,
+                new_poseidon_code_hash,
+                &self.new,
+            ),
+        ] {
+            let leaf_exists = path.key == key && path.leaf_data_hash.is_some();
+            match account {
+                Some(account) => {
+                    if !leaf_exists || path.leaf_data_hash != Some(account.hash(poseidon_code_hash))
+                    {
+                        return Err(VerificationError::LeafMismatch(name));
+                    }
+                }
+                None => {
+                    if leaf_exists && !matches!(self.claim.kind, ClaimKind::KeyValue { .. }) {
+                        return Err(VerificationError::LeafMismatch(name));
+                    }
+                }
+            }
+        }
+
+        self.verify_claim(old_poseidon_code_hash, new_poseidon_code_hash)
+    }
+
+    fn verify_claim(
+        &self,
+        old_poseidon_code_hash: Fr,
+        new_poseidon_code_hash: Fr,
+    ) -> Result<(), VerificationError> {
+        let ensure = |condition: bool, field| {
+            if condition {
+                Ok(())
+            } else {
+                Err(VerificationError::ClaimMismatch(field))
+            }
+        };
+
+        if let ClaimKind::KeyValue {
+            key,
+            old_value_hash,
+            new_value_hash,
+        } = self.claim.kind
+        {
+            let value_hash = |path: &Path| path.leaf_data_hash.filter(|_| path.key == key);
+            ensure(value_hash(&self.old) == old_value_hash, "old value hash")?;
+            return ensure(value_hash(&self.new) == new_value_hash, "new value hash");
+        }
+
+        let (old_account, new_account) = match (self.old_account, self.new_account) {
+            (None, None) => {
+                // Proofs for accounts that don't exist can only claim empty values.
+                ensure(
+                    matches!(
+                        MPTProofType::from(self.claim),
+                        MPTProofType::NonceChanged
+                            | MPTProofType::BalanceChanged
+                            | MPTProofType::CodeHashExists
+                            | MPTProofType::CodeSizeExists
+                            | MPTProofType::AccountDoesNotExist
+                            | MPTProofType::StorageDoesNotExist
+                    ),
+                    "account existence",
+                )?;
+                ensure(
+                    self.claim.old_value_assignment(Fr::one()) == Fr::zero(),
+                    "old value",
+                )?;
+                return ensure(
+                    self.claim.new_value_assignment(Fr::one()) == Fr::zero(),
+                    "new value",
+                );
+            }
+            accounts => accounts,
+        };
+
+        match old_account {
+            Some(account) => ensure(
+                account.storage_root == self.storage.old_root(),
+                "old storage root",
+            )?,
+            None => ensure(self.storage.old_root() == Fr::zero(), "old storage root")?,
+        }
+        if old_account.is_some() && new_account.is_some() {
+            ensure(
+                old_poseidon_code_hash == new_poseidon_code_hash
+                    || matches!(
+                        self.claim.kind,
+                        ClaimKind::PoseidonCodeHash { .. }
+                            | ClaimKind::NonceAndField {
+                                field: FieldUpdate::PoseidonCodeHash { .. },
+                                ..
+                            }
+                    ),
+                "poseidon code hash",
+            )?;
+        }
+
+        // The new account is the old one with the claimed changes applied. If the account didn't
+        // exist, only the claimed field of the new account is checked.
+        let account = old_account.or(new_account).unwrap_or_default();
+        let storage_root = self.storage.new_root();
+        let expected_new_account = match self.claim.kind {
+            ClaimKind::Nonce { old, new } => {
+                ensure(old_account.map(|a| a.nonce) == old, "old nonce")?;
+                Some(EthAccount {
+                    nonce: new.unwrap_or_default(),
+                    ..account
+                })
+            }
+            ClaimKind::Balance { old, new } => {
+                ensure(old_account.map(|a| a.balance) == old, "old balance")?;
+                Some(EthAccount {
+                    balance: new.unwrap_or_default(),
+                    ..account
+                })
+            }
+            ClaimKind::CodeHash { old, new } => {
+                ensure(
+                    old_account.map(|a| a.keccak_codehash) == old,
+                    "old code hash",
+                )?;
+                Some(EthAccount {
+                    keccak_codehash: new.unwrap_or_default(),
+                    ..account
+                })
+            }
+            ClaimKind::CodeSize { old, new } => {
+                ensure(old_account.map(|a| a.code_size) == old, "old code size")?;
+                Some(EthAccount {
+                    code_size: new.unwrap_or_default(),
+                    ..account
+                })
+            }
+            ClaimKind::PoseidonCodeHash { old, new } => {
+                ensure(
+                    old_account.map(|_| old_poseidon_code_hash) == old,
+                    "old poseidon code hash",
+                )?;
+                ensure(
+                    new_account.map(|_| new_poseidon_code_hash) == new,
+                    "new poseidon code hash",
+                )?;
+                new_account.map(|_| account)
+            }
+            ClaimKind::Storage { key, .. }
+            | ClaimKind::StorageRead { key, .. }
+            | ClaimKind::IsEmpty(Some(key)) => {
+                let (old_value, new_value) = match self.claim.kind {
+                    ClaimKind::Storage {
+                        old_value,
+                        new_value,
+                        ..
+                    } => (old_value.unwrap_or_default(), new_value.unwrap_or_default()),
+                    ClaimKind::StorageRead { value, .. } => (value, value),
+                    _ => (U256::zero(), U256::zero()),
+                };
+                match &self.storage {
+                    StorageProof::Update {
+                        storage_key,
+                        old_leaf,
+                        new_leaf,
+                        ..
+                    } => {
+                        ensure(*storage_key == key, "storage key")?;
+                        ensure(old_leaf.value() == old_value, "old storage value")?;
+                        ensure(new_leaf.value() == new_value, "new storage value")?;
+                    }
+                    _ => return Err(VerificationError::ClaimMismatch("storage update")),
+                }
+                Some(EthAccount {
+                    storage_root,
+                    ..account
+                })
+            }
+            ClaimKind::StorageBatch => {
+                ensure(
+                    matches!(self.storage, StorageProof::Batch(_)),
+                    "storage batch",
+                )?;
+                Some(EthAccount {
+                    storage_root,
+                    ..account
+                })
+            }
+            ClaimKind::StorageWiped => {
+                ensure(
+                    matches!(self.storage, StorageProof::Wiped(_)),
+                    "wiped storage",
+                )?;
+                Some(EthAccount {
+                    storage_root,
+                    ..account
+                })
+            }
+            ClaimKind::IsEmpty(None) => {
+                return Err(VerificationError::ClaimMismatch("account existence"))
+            }
+            ClaimKind::AccountDestructed => {
+                ensure(old_account.is_some(), "account existence")?;
+                None
+            }
+            ClaimKind::AccountCreated {
+                nonce,
+                balance,
+                code_hash,
+                poseidon_code_hash,
+                code_size,
+            } => {
+                ensure(old_account.is_none(), "account existence")?;
+                ensure(
+                    new_poseidon_code_hash == poseidon_code_hash,
+                    "poseidon code hash",
+                )?;
+                Some(EthAccount {
+                    nonce,
+                    code_size,
+                    balance,
+                    keccak_codehash: code_hash,
+                    storage_root,
+                })
+            }
+            ClaimKind::NonceRead(nonce) => {
+                ensure(old_account.map(|a| a.nonce) == Some(nonce), "nonce")?;
+                old_account
+            }
+            ClaimKind::BalanceRead(balance) => {
+                ensure(old_account.map(|a| a.balance) == Some(balance), "balance")?;
+                old_account
+            }
+            ClaimKind::NonceAndField {
+                old_nonce,
+                new_nonce,
+                field,
+            } => {
+                ensure(old_account.map(|a| a.nonce) == Some(old_nonce), "old nonce")?;
+                let account = EthAccount {
+                    nonce: new_nonce,
+                    ..account
+                };
+                Some(match field {
+                    FieldUpdate::Balance { old, new } => {
+                        ensure(old_account.map(|a| a.balance) == Some(old), "old balance")?;
+                        EthAccount {
+                            balance: new,
+                            ..account
+                        }
+                    }
+                    FieldUpdate::CodeHash { old, new } => {
+                        ensure(
+                            old_account.map(|a| a.keccak_codehash) == Some(old),
+                            "old code hash",
+                        )?;
+                        EthAccount {
+                            keccak_codehash: new,
+                            ..account
+                        }
+                    }
+                    FieldUpdate::PoseidonCodeHash { old, new } => {
+                        ensure(old_poseidon_code_hash == old, "old poseidon code hash")?;
+                        ensure(new_poseidon_code_hash == new, "new poseidon code hash")?;
+                        account
+                    }
+                    FieldUpdate::CodeSize { old, new } => {
+                        ensure(
+                            old_account.map(|a| a.code_size) == Some(old),
+                            "old code size",
+                        )?;
+                        EthAccount {
+                            code_size: new,
+                            ..account
+                        }
+                    }
+                })
+            }
+            ClaimKind::AccountIsEmpty => {
+                ensure(
+                    old_account
+                        == Some(EthAccount {
+                            keccak_codehash: account.keccak_codehash,
+                            storage_root: account.storage_root,
+                            ..Default::default()
+                        }),
+                    "empty account",
+                )?;
+                ensure(
+                    is_empty_code(account.keccak_codehash, old_poseidon_code_hash),
+                    "empty account",
+                )?;
+                old_account
+            }
+            ClaimKind::KeyValue { .. } => unreachable!(),
+        };
+        ensure(new_account == expected_new_account, "new account")
+    }
+
     // fn new_account_leaf_hashes(&self) -> Vec<Fr> {}
     // fn account_leaf_siblings(&self) -> Vec<Fr> {}
     #[cfg(test)]
     pub fn check(&self) {
+        self.verify().unwrap();
         self.storage.check();
 
         // poseidon hashes are correct
-        check_hash_traces_new(&self.address_hash_traces);
+        check_hash_traces_new(&self.address_hash_traces).unwrap();
 
         // directions match account key.
-        let account_key = account_key(self.claim.address);
+        let key = self.claim.key();
         for (i, (direction, _, _, _, _, _, _)) in self.address_hash_traces.iter().enumerate() {
-            assert_eq!(
-                *direction,
-                account_key.bit(self.address_hash_traces.len() - i - 1)
-            );
+            assert_eq!(*direction, key.bit(self.address_hash_traces.len() - i - 1));
         }
 
         // old and new roots are correct
@@ -879,17 +2077,29 @@ impl Proof {
     }
 }
 
-fn check_hash_traces_new(traces: &[(bool, HashDomain, Fr, Fr, Fr, bool, bool)]) {
+fn check_hash_traces_new(
+    traces: &[(bool, HashDomain, Fr, Fr, Fr, bool, bool)],
+) -> Result<(), TraceError> {
     let mut previous_path_type: Option<PathType> = None;
 
     let current_hash_traces = traces.iter();
     let mut next_hash_traces = traces.iter();
     next_hash_traces.next();
     for (
-        (direction, domain, open, close, sibling, is_padding_open, is_padding_close),
-        (_, _, next_open, next_close, _, _, _),
-    ) in current_hash_traces.zip(next_hash_traces)
+        i,
+        (
+            (direction, domain, open, close, sibling, is_padding_open, is_padding_close),
+            (_, _, next_open, next_close, _, _, _),
+        ),
+    ) in current_hash_traces.zip(next_hash_traces).enumerate()
     {
+        let check_hash = |hash: Fr, expected: Fr| {
+            if hash == expected {
+                Ok(())
+            } else {
+                Err(TraceError::PathHashMismatch(i))
+            }
+        };
         let path_type = match (is_padding_open, is_padding_close) {
             (false, false) => PathType::Common,
             (false, true) => PathType::ExtensionOld,
@@ -902,7 +2112,8 @@ fn check_hash_traces_new(traces: &[(bool, HashDomain, Fr, Fr, Fr, bool, bool)])
             PathType::Common => {
                 let [open_domain, close_domain] =
                     if previous_path_type == Some(PathType::ExtensionOld) {
-                        unimplemented!("account leaf deletion");
+                        // Deleting a leaf, so domain is the domain after deletion.
+                        [next_domain(*domain, *direction), *domain]
                     } else if previous_path_type == Some(PathType::ExtensionNew) {
                         match *domain {
                             HashDomain::Branch0 => [
@@ -925,11 +2136,11 @@ fn check_hash_traces_new(traces: &[(bool, HashDomain, Fr, Fr, Fr, bool, bool)])
                     };
 
                 if *direction {
-                    assert_eq!(domain_hash(*sibling, *open, open_domain), *next_open);
-                    assert_eq!(domain_hash(*sibling, *close, close_domain), *next_close);
+                    check_hash(domain_hash(*sibling, *open, open_domain), *next_open)?;
+                    check_hash(domain_hash(*sibling, *close, close_domain), *next_close)?;
                 } else {
-                    assert_eq!(domain_hash(*open, *sibling, open_domain), *next_open);
-                    assert_eq!(domain_hash(*close, *sibling, close_domain), *next_close);
+                    check_hash(domain_hash(*open, *sibling, open_domain), *next_open)?;
+                    check_hash(domain_hash(*close, *sibling, close_domain), *next_close)?;
                 }
             }
             PathType::ExtensionOld => {
@@ -938,9 +2149,9 @@ fn check_hash_traces_new(traces: &[(bool, HashDomain, Fr, Fr, Fr, bool, bool)])
                         || previous_path_type == Some(PathType::ExtensionOld)
                 );
                 if *direction {
-                    assert_eq!(domain_hash(*sibling, *open, *domain), *next_open);
+                    check_hash(domain_hash(*sibling, *open, *domain), *next_open)?;
                 } else {
-                    assert_eq!(domain_hash(*open, *sibling, *domain), *next_open);
+                    check_hash(domain_hash(*open, *sibling, *domain), *next_open)?;
                 }
             }
             PathType::ExtensionNew => {
@@ -949,21 +2160,27 @@ fn check_hash_traces_new(traces: &[(bool, HashDomain, Fr, Fr, Fr, bool, bool)])
                         || previous_path_type == Some(PathType::ExtensionNew)
                 );
                 if *direction {
-                    assert_eq!(domain_hash(*sibling, *close, *domain), *next_close);
+                    check_hash(domain_hash(*sibling, *close, *domain), *next_close)?;
                 } else {
-                    assert_eq!(domain_hash(*close, *sibling, *domain), *next_close);
+                    check_hash(domain_hash(*close, *sibling, *domain), *next_close)?;
                 }
             }
         }
 
         previous_path_type = Some(path_type);
     }
+    Ok(())
 }
 
 fn fr(x: HexBytes<32>) -> Fr {
     Fr::from_bytes(&x.0).unwrap()
 }
 
+// Integer value of the word, which must be less than the modulus.
+fn fr_from_u256(x: U256) -> Fr {
+    rlc(&u256_to_big_endian(&x), Fr::from(256))
+}
+
 fn big_uint_to_fr(i: &BigUint) -> Fr {
     i.to_u64_digits()
         .iter()
@@ -0,0 +1,132 @@
+use crate::MPTProofType;
+use halo2_proofs::halo2curves::bn256::Fr;
+
+#[derive(Debug, thiserror::Error)]
+/// Errors converting a trace into a proof.
+pub enum TraceError {
+    #[error("{0} is not a canonical field element")]
+    /// bytes are not the little endian encoding of a field element
+    NonCanonical(&'static str),
+    #[error("balance does not fit into 31 bytes")]
+    /// balance is too large for the account leaf, which holds it as a field element
+    BalanceOutOfRange,
+    #[error("{0} is missing")]
+    /// field required for the proof is missing from the trace
+    MissingField(&'static str),
+    #[error("{name} mismatch: expected {expected:?}, found {found:?}")]
+    /// root in the trace differs from the one computed from its path and leaf
+    RootMismatch {
+        /// which root
+        name: &'static str,
+        /// computed root
+        expected: Fr,
+        /// root in the trace
+        found: Fr,
+    },
+    #[error("{name} is not the hash of its preimage: expected {expected:?}, found {found:?}")]
+    /// mpt key in the trace is not the hash of the address or storage key
+    KeyHashMismatch {
+        /// which key
+        name: &'static str,
+        /// hash of the address or storage key
+        expected: Fr,
+        /// key in the trace
+        found: Fr,
+    },
+    #[error("{name} leaf hash mismatch: expected {expected:?}, found {found:?}")]
+    /// leaf hash in the path differs from the one computed from the account
+    LeafHashMismatch {
+        /// which leaf
+        name: &'static str,
+        /// hash computed from the account
+        expected: Fr,
+        /// hash in the path
+        found: Fr,
+    },
+    #[error("{name} node at depth {depth} has type {node_type}, which does not fit the path")]
+    /// node type is not a branch domain whose children are branches or terminal as in the path
+    InvalidNodeType {
+        /// which path
+        name: &'static str,
+        /// depth of the node
+        depth: usize,
+        /// node type in the trace
+        node_type: u64,
+    },
+    #[error("{name} path part differs from the key at depth {depth}")]
+    /// path part is not the bits of the key above the leaf
+    PathPartMismatch {
+        /// which path
+        name: &'static str,
+        /// first depth at which the path part and key bits differ
+        depth: usize,
+    },
+    #[error("old and new {name}s have different siblings at depth {depth}")]
+    /// node is in the common part of the old and new paths, but its sibling changed
+    SiblingMismatch {
+        /// which path
+        name: &'static str,
+        /// depth of the node
+        depth: usize,
+    },
+    #[error("old and new {name}s have inconsistent node types at depth {depth}")]
+    /// node types differ other than at the node where a leaf is inserted or deleted
+    NodeTypeMismatch {
+        /// which path
+        name: &'static str,
+        /// depth of the node
+        depth: usize,
+    },
+    #[error("hash of path node {0} does not match its parent")]
+    /// path node and its sibling don't hash to the next node of the path
+    PathHashMismatch(usize),
+    #[error("trace is inconsistent with {proof_type:?}: {reason}")]
+    /// account or storage update in the trace is not allowed for the proof type
+    InconsistentProofType {
+        /// requested proof type
+        proof_type: MPTProofType,
+        /// invariant that failed
+        reason: &'static str,
+    },
+    #[error("inconsistent storage update: {0}")]
+    /// storage update in the trace does not match its storage path
+    InconsistentStorageUpdate(&'static str),
+    #[error("{proof_type:?} is not supported: {reason}")]
+    /// proof type cannot be built from a single trace of this kind
+    Unsupported {
+        /// requested proof type
+        proof_type: MPTProofType,
+        /// why it is not supported
+        reason: &'static str,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+/// Errors found when verifying a proof outside of the circuit.
+pub enum VerificationError {
+    #[error("{name} mismatch: claimed {claimed:?}, computed {computed:?}")]
+    /// root recomputed from the trie path differs from the claimed one
+    RootMismatch {
+        /// which root
+        name: &'static str,
+        /// root in the claim or storage proof
+        claimed: Fr,
+        /// root recomputed from the path
+        computed: Fr,
+    },
+    #[error("hash of trie row {0} does not match the row above it")]
+    /// row and its sibling don't hash to the row above it
+    TrieHashMismatch(usize),
+    #[error("direction of trie row {0} does not match the key")]
+    /// direction of a row is not the corresponding bit of the key
+    KeyBitMismatch(usize),
+    #[error("invalid path type at trie row {0}")]
+    /// extension path is followed by a common path, or row has path type Start
+    InvalidPathType(usize),
+    #[error("{0} does not match the leaf in the trie")]
+    /// leaf at the end of the path is not the one for the account or storage slot
+    LeafMismatch(&'static str),
+    #[error("{0} does not match the claim")]
+    /// account or storage value differs from the claimed one
+    ClaimMismatch(&'static str),
+}
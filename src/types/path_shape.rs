@@ -0,0 +1,170 @@
+//! Checks that the node types and path parts of the paths in a trace agree with their shape
+//!
+use crate::{
+    serde::{SMTPath, SMTTrace},
+    types::{HashDomain, TraceError},
+    util::{domain_hash, try_fr, Bit},
+};
+use halo2_proofs::halo2curves::bn256::Fr;
+use num_bigint::BigUint;
+use num_traits::identities::{One, Zero};
+use std::iter::once;
+
+/// Checks that each branch in the paths of the trace has a node type whose children are branches
+/// or terminal as the path requires, and that each path part is the key bits above the leaf.
+pub fn check_paths(trace: &SMTTrace) -> Result<(), TraceError> {
+    for (path, key, name) in paths(trace)? {
+        check_path(path, key, name)?;
+    }
+    Ok(())
+}
+
+/// Fills in the node types missing (0) from traces of older l2geth versions, using the hashes of
+/// the paths to tell apart the node types that their shape allows.
+pub fn infer_node_types(trace: &mut SMTTrace) -> Result<(), TraceError> {
+    let account_key = try_fr(trace.account_key, "account key")?;
+    for path in &mut trace.account_path {
+        infer_path_node_types(path, account_key, "account path")?;
+    }
+    let state_key = trace
+        .state_key
+        .map(|key| try_fr(key, "state key"))
+        .transpose()?;
+    if let Some(state_key) = state_key {
+        for path in trace.state_path.iter_mut().flatten() {
+            infer_path_node_types(path, state_key, "storage path")?;
+        }
+    }
+    Ok(())
+}
+
+fn paths(trace: &SMTTrace) -> Result<Vec<(&SMTPath, Fr, &'static str)>, TraceError> {
+    let account_key = try_fr(trace.account_key, "account key")?;
+    let mut paths: Vec<_> = trace
+        .account_path
+        .iter()
+        .map(|path| (path, account_key, "account path"))
+        .collect();
+    // Storage paths without a key are rejected when the storage proof is built.
+    if let Some(state_key) = trace.state_key {
+        let state_key = try_fr(state_key, "state key")?;
+        paths.extend(
+            trace
+                .state_path
+                .iter()
+                .flatten()
+                .map(|path| (path, state_key, "storage path")),
+        );
+    }
+    Ok(paths)
+}
+
+fn check_path(path: &SMTPath, key: Fr, name: &'static str) -> Result<(), TraceError> {
+    for (depth, node) in path.path.iter().enumerate() {
+        let is_allowed = HashDomain::try_from(node.node_type).map_or(false, |domain| {
+            allowed_domains(path, key, depth).any(|allowed| allowed == domain)
+        });
+        if !is_allowed {
+            return Err(TraceError::InvalidNodeType {
+                name,
+                depth,
+                node_type: node.node_type,
+            });
+        }
+    }
+
+    let path_part = path_part(key, path.path.len());
+    if path_part != path.path_part {
+        let difference = path_part ^ &path.path_part;
+        return Err(TraceError::PathPartMismatch {
+            name,
+            depth: difference.trailing_zeros().unwrap() as usize,
+        });
+    }
+    Ok(())
+}
+
+fn infer_path_node_types(
+    path: &mut SMTPath,
+    key: Fr,
+    name: &'static str,
+) -> Result<(), TraceError> {
+    let parents: Vec<Fr> = once(path.root)
+        .chain(path.path.iter().map(|node| node.value))
+        .map(|hash| try_fr(hash, name))
+        .collect::<Result<_, _>>()?;
+    let allowed: Vec<Vec<HashDomain>> = (0..path.path.len())
+        .map(|depth| allowed_domains(path, key, depth).collect())
+        .collect();
+    for (depth, (node, (parent, allowed))) in path
+        .path
+        .iter_mut()
+        .zip(parents.into_iter().zip(allowed))
+        .enumerate()
+    {
+        if node.node_type != 0 {
+            continue;
+        }
+        let (value, sibling) = (try_fr(node.value, name)?, try_fr(node.sibling, name)?);
+        let (left, right) = if key.bit(depth) {
+            (sibling, value)
+        } else {
+            (value, sibling)
+        };
+        let domain = allowed
+            .into_iter()
+            .find(|domain| domain_hash(left, right, *domain) == parent)
+            .ok_or(TraceError::InvalidNodeType {
+                name,
+                depth,
+                node_type: 0,
+            })?;
+        node.node_type = domain.into();
+    }
+    if let Some(leaf) = path.leaf.as_mut() {
+        if leaf.node_type == 0 {
+            leaf.node_type = HashDomain::Leaf.into();
+        }
+    }
+    Ok(())
+}
+
+// Branch domains of the node at depth which are consistent with the path. The child on the side of
+// the key is a branch unless the node is the last one of the path, and an empty sibling is
+// terminal. Whether any other sibling is a branch or terminal is only known from its hash.
+fn allowed_domains(path: &SMTPath, key: Fr, depth: usize) -> impl Iterator<Item = HashDomain> {
+    let direction = key.bit(depth);
+    let child_is_branch = depth + 1 < path.path.len();
+    // Zero is the only field element whose encoding is all zero bytes, so this needs no parsing.
+    let sibling_is_empty = path.path[depth].sibling.0 == [0; 32];
+    [
+        HashDomain::Branch0,
+        HashDomain::Branch1,
+        HashDomain::Branch2,
+        HashDomain::Branch3,
+    ]
+    .into_iter()
+    .filter(move |domain| {
+        let (left_is_branch, right_is_branch) = match domain {
+            HashDomain::Branch0 => (false, false),
+            HashDomain::Branch1 => (false, true),
+            HashDomain::Branch2 => (true, false),
+            HashDomain::Branch3 => (true, true),
+            _ => unreachable!(),
+        };
+        let (child_side, sibling_side) = if direction {
+            (right_is_branch, left_is_branch)
+        } else {
+            (left_is_branch, right_is_branch)
+        };
+        child_side == child_is_branch && !(sibling_is_empty && sibling_side)
+    })
+}
+
+fn path_part(key: Fr, depth: usize) -> BigUint {
+    (0..depth)
+        .filter(|i| key.bit(*i))
+        .fold(BigUint::zero(), |path_part, i| {
+            path_part + (BigUint::one() << i)
+        })
+}
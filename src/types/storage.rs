@@ -1,12 +1,11 @@
-#[cfg(test)]
-use crate::types::{Bit, PathType};
 use crate::{
     serde::{SMTNode, SMTTrace, StateData},
-    types::{trie::TrieRows, HashDomain},
+    types::{trie::TrieRows, HashDomain, TraceError, VerificationError},
     util::{domain_hash, fr, storage_key_hash, u256_from_hex, u256_hi_lo},
 };
 use ethers_core::{k256::elliptic_curve::PrimeField, types::U256};
 use halo2_proofs::halo2curves::bn256::Fr;
+use itertools::Itertools;
 
 #[derive(Clone, Debug)]
 pub enum StorageProof {
@@ -18,6 +17,8 @@ pub enum StorageProof {
         old_leaf: StorageLeaf,
         new_leaf: StorageLeaf,
     },
+    Batch(Vec<StorageProof>), // Consecutive updates to the storage trie of one account.
+    Wiped(Fr),                // Storage root changes from the old root to the empty root.
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -30,41 +31,46 @@ pub enum StorageLeaf {
 impl StorageProof {
     pub fn n_rows(&self) -> usize {
         match self {
-            Self::Root(_) => 0,
+            Self::Root(_) | Self::Wiped(_) => 0,
             Self::Update {
                 trie_rows,
                 old_leaf,
                 new_leaf,
                 ..
             } => trie_rows.len() + std::cmp::max(old_leaf.n_rows(), new_leaf.n_rows()),
+            // Each update has its own start row and storage root row.
+            Self::Batch(updates) => updates.iter().map(|update| 2 + update.n_rows()).sum(),
         }
     }
 
     pub fn old_root(&self) -> Fr {
         match self {
-            Self::Root(root) => *root,
+            Self::Root(root) | Self::Wiped(root) => *root,
             Self::Update {
                 trie_rows,
                 old_leaf,
                 ..
             } => trie_rows.old_root(|| old_leaf.hash()),
+            Self::Batch(updates) => updates.first().unwrap().old_root(),
         }
     }
 
     pub fn new_root(&self) -> Fr {
         match self {
             Self::Root(root) => *root,
+            Self::Wiped(_) => Fr::zero(),
             Self::Update {
                 trie_rows,
                 new_leaf,
                 ..
             } => trie_rows.new_root(|| new_leaf.hash()),
+            Self::Batch(updates) => updates.last().unwrap().new_root(),
         }
     }
 
     pub fn poseidon_lookups(&self) -> Vec<(Fr, Fr, HashDomain, Fr)> {
         match self {
-            Self::Root(_) => vec![],
+            Self::Root(_) | Self::Wiped(_) => vec![],
             Self::Update {
                 storage_key,
                 key,
@@ -85,37 +91,40 @@ impl StorageProof {
                 lookups.extend(new_leaf.poseidon_lookups());
                 lookups
             }
+            Self::Batch(updates) => updates.iter().flat_map(Self::poseidon_lookups).collect(),
         }
     }
 
     pub fn key_lookups(&self) -> Vec<Fr> {
         match self {
-            Self::Root(_) => vec![],
+            Self::Root(_) | Self::Wiped(_) => vec![],
             Self::Update { .. } => {
                 vec![self.key(), self.other_key()]
             }
+            Self::Batch(updates) => updates.iter().flat_map(Self::key_lookups).collect(),
         }
     }
 
     pub fn key_bit_lookups(&self) -> Vec<(Fr, usize, bool)> {
         match self {
-            Self::Root(_) => vec![],
+            Self::Root(_) | Self::Wiped(_) => vec![],
             Self::Update { trie_rows, .. } => {
                 trie_rows.key_bit_lookups(self.key(), self.other_key())
             }
+            Self::Batch(updates) => updates.iter().flat_map(Self::key_bit_lookups).collect(),
         }
     }
 
     pub fn key(&self) -> Fr {
         match self {
-            Self::Root(_) => unreachable!(),
+            Self::Root(_) | Self::Batch(_) | Self::Wiped(_) => unreachable!(),
             Self::Update { key, .. } => *key,
         }
     }
 
     pub fn other_key(&self) -> Fr {
         match self {
-            Self::Root(_) => unreachable!(),
+            Self::Root(_) | Self::Batch(_) | Self::Wiped(_) => unreachable!(),
             Self::Update {
                 key,
                 old_leaf,
@@ -133,65 +142,75 @@ impl StorageProof {
         }
     }
 
-    #[cfg(test)]
-    pub fn check(&self) {
-        if let Self::Update {
-            trie_rows,
-            old_leaf,
-            new_leaf,
-            ..
-        } = self
-        {
-            // Check that trie rows are consistent and produce claimed roots.
-            trie_rows.check(self.old_root(), self.new_root());
-
-            // Check that directions match old and new keys.
-            for (i, row) in trie_rows.0.iter().enumerate() {
-                let old_key = old_leaf.key();
-                let new_key = new_leaf.key();
-                match row.path_type {
-                    PathType::Start => unreachable!(),
-                    PathType::Common => {
-                        assert_eq!(row.direction, old_key.bit(i));
-                        assert_eq!(row.direction, new_key.bit(i));
-                    }
-                    PathType::ExtensionOld => {
-                        assert_eq!(row.direction, old_key.bit(i));
-                    }
-                    PathType::ExtensionNew => {
-                        assert_eq!(row.direction, new_key.bit(i));
-                    }
+    /// Checks that the trie rows of each storage update hash up to its storage roots from the
+    /// leaves, and that consecutive updates in a batch share their storage roots.
+    pub fn verify(&self) -> Result<(), VerificationError> {
+        match self {
+            Self::Root(_) | Self::Wiped(_) => Ok(()),
+            Self::Update {
+                storage_key,
+                key,
+                trie_rows,
+                old_leaf,
+                new_leaf,
+            } => {
+                if storage_key_hash(*storage_key) != *key {
+                    return Err(VerificationError::LeafMismatch("storage key hash"));
                 }
+                trie_rows.verify(
+                    [self.old_root(), self.new_root()],
+                    [old_leaf.hash(), new_leaf.hash()],
+                    *key,
+                    [old_leaf.key(), new_leaf.key()],
+                )
             }
-
-            // Check that final trie_row values match leaf hashes
-            if let Some(row) = trie_rows.0.last() {
-                assert_eq!(old_leaf.hash(), row.old);
-                assert_eq!(new_leaf.hash(), row.new);
+            Self::Batch(updates) => {
+                for (update, next_update) in updates.iter().tuple_windows() {
+                    if update.new_root() != next_update.old_root() {
+                        return Err(VerificationError::RootMismatch {
+                            name: "storage root between batch updates",
+                            claimed: next_update.old_root(),
+                            computed: update.new_root(),
+                        });
+                    }
+                }
+                updates.iter().try_for_each(Self::verify)
             }
         }
     }
+
+    #[cfg(test)]
+    pub fn check(&self) {
+        self.verify().unwrap();
+    }
 }
 
 impl StorageLeaf {
-    fn new(mpt_key: Fr, node: &Option<SMTNode>, data: &StateData) -> Self {
+    fn new(mpt_key: Fr, node: &Option<SMTNode>, data: &StateData) -> Result<Self, TraceError> {
         let value = u256_from_hex(data.value);
         match (node, value.is_zero()) {
-            (None, true) => Self::Empty { mpt_key },
+            (None, true) => Ok(Self::Empty { mpt_key }),
             (Some(node), true) => {
-                assert_eq!(mpt_key, storage_key_hash(u256_from_hex(data.key)));
-                Self::Leaf {
+                let storage_key_hash = storage_key_hash(u256_from_hex(data.key));
+                if mpt_key != storage_key_hash {
+                    return Err(TraceError::KeyHashMismatch {
+                        name: "storage key",
+                        expected: storage_key_hash,
+                        found: mpt_key,
+                    });
+                }
+                Ok(Self::Leaf {
                     mpt_key: fr(node.sibling),
                     value_hash: fr(node.value),
-                }
+                })
             }
-            (Some(_), false) => Self::Entry {
+            (Some(_), false) => Ok(Self::Entry {
                 storage_key: u256_from_hex(data.key),
                 value,
-            },
-            (None, false) => {
-                unreachable!();
-            }
+            }),
+            (None, false) => Err(TraceError::InconsistentStorageUpdate(
+                "non-zero value without storage leaf",
+            )),
         }
     }
 
@@ -272,28 +291,51 @@ impl StorageLeaf {
     }
 }
 
-impl From<&SMTTrace> for StorageProof {
-    fn from(trace: &SMTTrace) -> Self {
+impl TryFrom<&SMTTrace> for StorageProof {
+    type Error = TraceError;
+
+    fn try_from(trace: &SMTTrace) -> Result<Self, Self::Error> {
         if let Some(root) = trace.common_state_root {
-            return Self::Root(fr(root));
+            return Ok(Self::Root(fr(root)));
         }
-        let key = fr(trace.state_key.unwrap());
-        let [old_path, new_path] = &trace.state_path;
-        let old_leaf = old_path.as_ref().unwrap().leaf;
-        let new_leaf = new_path.as_ref().unwrap().leaf;
+        let [old_path, new_path] = match &trace.state_path {
+            [Some(old_path), Some(new_path)] => [old_path, new_path],
+            _ => return Err(TraceError::MissingField("state_path")),
+        };
+        let key = match trace.state_key {
+            Some(state_key) => fr(state_key),
+            None => {
+                // Without a storage key, the trace is for the whole storage trie being wiped.
+                let new_root = fr(new_path.root);
+                if new_root != Fr::zero() {
+                    return Err(TraceError::RootMismatch {
+                        name: "wiped storage root",
+                        expected: Fr::zero(),
+                        found: new_root,
+                    });
+                }
+                return Ok(Self::Wiped(fr(old_path.root)));
+            }
+        };
         let trie_rows = TrieRows::new(
+            "storage path",
             key,
-            &old_path.as_ref().unwrap().path,
-            &new_path.as_ref().unwrap().path,
-            old_leaf,
-            new_leaf,
-        );
+            &old_path.path,
+            &new_path.path,
+            old_path.leaf,
+            new_path.leaf,
+        )?;
 
-        let [old_entry, new_entry] = trace.state_update.unwrap().map(Option::unwrap);
-        assert_eq!(old_entry.key, new_entry.key);
+        let [old_entry, new_entry] = match trace.state_update {
+            Some([Some(old_entry), Some(new_entry)]) => [old_entry, new_entry],
+            _ => return Err(TraceError::MissingField("state_update")),
+        };
+        if old_entry.key != new_entry.key {
+            return Err(TraceError::InconsistentStorageUpdate("storage key changed"));
+        }
         let storage_key = u256_from_hex(old_entry.key);
-        let old_leaf = StorageLeaf::new(key, &old_leaf, &old_entry);
-        let new_leaf = StorageLeaf::new(key, &new_leaf, &new_entry);
+        let old_leaf = StorageLeaf::new(key, &old_path.leaf, &old_entry)?;
+        let new_leaf = StorageLeaf::new(key, &new_path.leaf, &new_entry)?;
 
         let storage_proof = Self::Update {
             storage_key,
@@ -302,14 +344,18 @@ impl From<&SMTTrace> for StorageProof {
             old_leaf,
             new_leaf,
         };
-        assert_eq!(
-            storage_proof.old_root(),
-            fr(old_path.as_ref().unwrap().root)
-        );
-        assert_eq!(
-            storage_proof.new_root(),
-            fr(new_path.as_ref().unwrap().root)
-        );
-        storage_proof
+        for (name, expected, path) in [
+            ("old storage root", storage_proof.old_root(), old_path),
+            ("new storage root", storage_proof.new_root(), new_path),
+        ] {
+            if expected != fr(path.root) {
+                return Err(TraceError::RootMismatch {
+                    name,
+                    expected,
+                    found: fr(path.root),
+                });
+            }
+        }
+        Ok(storage_proof)
     }
 }
@@ -1,8 +1,8 @@
 use crate::{
     gadgets::mpt_update::PathType,
     serde::SMTNode,
-    types::HashDomain,
-    util::{check_domain_consistency, domain_hash, fr, Bit},
+    types::{HashDomain, TraceError, VerificationError},
+    util::{domain_hash, fr, Bit},
 };
 use halo2_proofs::halo2curves::bn256::Fr;
 use itertools::{EitherOrBoth, Itertools};
@@ -59,12 +59,13 @@ impl TrieRow {
 
 impl TrieRows {
     pub fn new(
+        name: &'static str,
         key: Fr,
         old_nodes: &[SMTNode],
         new_nodes: &[SMTNode],
         old_leaf: Option<SMTNode>,
         new_leaf: Option<SMTNode>,
-    ) -> Self {
+    ) -> Result<Self, TraceError> {
         let old_leaf_hash = old_nodes
             .last()
             .map(|node| fr(node.value))
@@ -73,66 +74,47 @@ impl TrieRows {
             .last()
             .map(|node| fr(node.value))
             .unwrap_or_else(|| new_leaf.map(leaf_hash).unwrap_or_default());
-        Self(
-            old_nodes
-                .iter()
-                .zip_longest(new_nodes.iter())
-                .enumerate()
-                .map(|(i, pair)| {
-                    let direction = key.bit(i);
-                    match pair {
-                        EitherOrBoth::Both(old, new) => {
-                            assert_eq!(old.sibling, new.sibling);
-
-                            let old_domain = HashDomain::try_from(old.node_type).unwrap();
-                            let new_domain = HashDomain::try_from(new.node_type).unwrap();
-                            let domain = if old_domain != new_domain {
-                                // This can only happen when inserting or deleting a node.
-                                assert!(old_nodes.len() != new_nodes.len());
-                                assert!(i == std::cmp::min(old_nodes.len(), new_nodes.len()) - 1);
-
-                                if i == old_nodes.len() - 1 {
-                                    // Inserting a leaf, so old is before insertion, new is after insertion.
-                                    check_domain_consistency(old_domain, new_domain, direction);
-                                    old_domain
-                                } else {
-                                    // Deleting a leaf, so new is after insertion, old is before insertion.
-                                    check_domain_consistency(new_domain, old_domain, direction);
-                                    new_domain
-                                }
-                            } else {
-                                old_domain
-                            };
-
-                            TrieRow {
-                                domain,
-                                direction,
-                                old: fr(old.value),
-                                new: fr(new.value),
-                                sibling: fr(old.sibling),
-                                path_type: PathType::Common,
-                            }
-                        }
-                        EitherOrBoth::Left(old) => TrieRow {
-                            domain: HashDomain::try_from(old.node_type).unwrap(),
-                            direction,
-                            old: fr(old.value),
-                            new: new_leaf_hash,
-                            sibling: fr(old.sibling),
-                            path_type: PathType::ExtensionOld,
-                        },
-                        EitherOrBoth::Right(new) => TrieRow {
-                            domain: HashDomain::try_from(new.node_type).unwrap(),
+        old_nodes
+            .iter()
+            .zip_longest(new_nodes.iter())
+            .enumerate()
+            .map(|(i, pair)| {
+                let direction = key.bit(i);
+                Ok(match pair {
+                    EitherOrBoth::Both(old, new) => TrieRow {
+                        domain: common_domain(
+                            name,
+                            i,
+                            [old, new],
+                            [old_nodes.len(), new_nodes.len()],
                             direction,
-                            old: old_leaf_hash,
-                            new: fr(new.value),
-                            sibling: fr(new.sibling),
-                            path_type: PathType::ExtensionNew,
-                        },
-                    }
+                        )?,
+                        direction,
+                        old: fr(old.value),
+                        new: fr(new.value),
+                        sibling: fr(old.sibling),
+                        path_type: PathType::Common,
+                    },
+                    EitherOrBoth::Left(old) => TrieRow {
+                        domain: node_domain(name, i, old)?,
+                        direction,
+                        old: fr(old.value),
+                        new: new_leaf_hash,
+                        sibling: fr(old.sibling),
+                        path_type: PathType::ExtensionOld,
+                    },
+                    EitherOrBoth::Right(new) => TrieRow {
+                        domain: node_domain(name, i, new)?,
+                        direction,
+                        old: old_leaf_hash,
+                        new: fr(new.value),
+                        sibling: fr(new.sibling),
+                        path_type: PathType::ExtensionNew,
+                    },
                 })
-                .collect(),
-        )
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
     }
 
     pub fn len(&self) -> usize {
@@ -207,6 +189,23 @@ impl TrieRows {
         lookups
     }
 
+    /// Returns the rows with the new hashes replaced by the ones on the path to `leaf_hash`. The
+    /// old hashes and siblings are unchanged, so all rows must be on the common path.
+    pub fn with_new_leaf_hash(&self, leaf_hash: Fr) -> Self {
+        let mut rows = self.0.clone();
+        let mut hash = leaf_hash;
+        for row in rows.iter_mut().rev() {
+            assert_eq!(row.path_type, PathType::Common);
+            row.new = hash;
+            hash = if row.direction {
+                domain_hash(row.sibling, hash, row.domain)
+            } else {
+                domain_hash(hash, row.sibling, row.domain)
+            };
+        }
+        Self(rows)
+    }
+
     pub fn old_root(&self, leaf_hash: impl FnOnce() -> Fr) -> Fr {
         let next_path_type = self.0.get(1).map(|row| row.path_type);
         self.0
@@ -221,8 +220,28 @@ impl TrieRows {
             .map_or_else(leaf_hash, |row| row.new_hash(next_path_type))
     }
 
-    #[cfg(test)]
-    pub fn check(&self, old_root: Fr, new_root: Fr) {
+    /// Checks that the rows hash up to the roots from the leaf hashes, along the path given by the
+    /// keys. The key is checked on the common path, and the old or new key on extension paths.
+    pub fn verify(
+        &self,
+        [old_root, new_root]: [Fr; 2],
+        [old_leaf_hash, new_leaf_hash]: [Fr; 2],
+        key: Fr,
+        [old_key, new_key]: [Fr; 2],
+    ) -> Result<(), VerificationError> {
+        for (name, claimed, computed) in [
+            ("old root", old_root, self.old_root(|| old_leaf_hash)),
+            ("new root", new_root, self.new_root(|| new_leaf_hash)),
+        ] {
+            if claimed != computed {
+                return Err(VerificationError::RootMismatch {
+                    name,
+                    claimed,
+                    computed,
+                });
+            }
+        }
+
         for (i, row) in self.0.iter().enumerate() {
             let [[old_left, old_right], [new_left, new_right]] = if row.direction {
                 [[row.sibling, row.old], [row.sibling, row.new]]
@@ -230,50 +249,63 @@ impl TrieRows {
                 [[row.old, row.sibling], [row.new, row.sibling]]
             };
 
-            let [expected_old_hash, expected_new_hash] = if i == 0 {
-                [old_root, new_root]
-            } else {
-                let previous_row = self.0.get(i - 1).unwrap();
-                [previous_row.old, previous_row.new]
+            let [expected_old_hash, expected_new_hash] = match i.checked_sub(1) {
+                None => [old_root, new_root],
+                Some(previous) => [self.0[previous].old, self.0[previous].new],
             };
+            let next_path_type = self.0.get(i + 1).map(|row| row.path_type);
 
-            match row.path_type {
-                PathType::Start => unreachable!(),
+            let (hashes_match, path_keys) = match row.path_type {
+                PathType::Start => return Err(VerificationError::InvalidPathType(i)),
                 PathType::Common => {
-                    let [old_domain, new_domain] = if let Some(next_row) = self.0.get(i + 1) {
-                        get_domains(next_row.path_type, row.domain, row.direction)
-                    } else {
-                        [row.domain, row.domain]
+                    let [old_domain, new_domain] = match next_path_type {
+                        Some(next_path_type) => {
+                            get_domains(next_path_type, row.domain, row.direction)
+                        }
+                        None => [row.domain, row.domain],
                     };
-                    assert_eq!(
-                        domain_hash(old_left, old_right, old_domain),
-                        expected_old_hash
-                    );
-                    assert_eq!(
-                        domain_hash(new_left, new_right, new_domain),
-                        expected_new_hash
-                    );
+                    (
+                        domain_hash(old_left, old_right, old_domain) == expected_old_hash
+                            && domain_hash(new_left, new_right, new_domain) == expected_new_hash,
+                        vec![key, old_key, new_key],
+                    )
                 }
                 PathType::ExtensionOld => {
-                    if let Some(row) = self.0.get(i + 1) {
-                        assert_eq!(row.path_type, PathType::ExtensionOld);
+                    if next_path_type.map_or(false, |t| t != PathType::ExtensionOld) {
+                        return Err(VerificationError::InvalidPathType(i + 1));
                     }
-                    assert_eq!(
-                        domain_hash(old_left, old_right, row.domain),
-                        expected_old_hash
-                    );
+                    (
+                        domain_hash(old_left, old_right, row.domain) == expected_old_hash,
+                        vec![old_key],
+                    )
                 }
                 PathType::ExtensionNew => {
-                    if let Some(row) = self.0.get(i + 1) {
-                        assert_eq!(row.path_type, PathType::ExtensionNew);
+                    if next_path_type.map_or(false, |t| t != PathType::ExtensionNew) {
+                        return Err(VerificationError::InvalidPathType(i + 1));
                     }
-                    assert_eq!(
-                        domain_hash(new_left, new_right, row.domain),
-                        expected_new_hash
-                    );
+                    (
+                        domain_hash(new_left, new_right, row.domain) == expected_new_hash,
+                        vec![new_key],
+                    )
                 }
+            };
+            if !hashes_match {
+                return Err(VerificationError::TrieHashMismatch(i));
+            }
+            if path_keys.iter().any(|key| key.bit(i) != row.direction) {
+                return Err(VerificationError::KeyBitMismatch(i));
+            }
+        }
+
+        if let Some(row) = self.0.last() {
+            if row.old != old_leaf_hash {
+                return Err(VerificationError::LeafMismatch("old leaf hash"));
+            }
+            if row.new != new_leaf_hash {
+                return Err(VerificationError::LeafMismatch("new leaf hash"));
             }
         }
+        Ok(())
     }
 }
 
@@ -314,3 +346,59 @@ fn get_domains(
 fn leaf_hash(leaf: SMTNode) -> Fr {
     domain_hash(fr(leaf.sibling), fr(leaf.value), HashDomain::Leaf)
 }
+
+pub(crate) fn node_domain(
+    name: &'static str,
+    depth: usize,
+    node: &SMTNode,
+) -> Result<HashDomain, TraceError> {
+    HashDomain::try_from(node.node_type).map_err(|_| TraceError::InvalidNodeType {
+        name,
+        depth,
+        node_type: node.node_type,
+    })
+}
+
+// Domain of a node in the common part of the old and new paths. The old and new node types can only
+// differ at the last common node of an insertion or deletion, where the child along the path becomes
+// a branch or stops being one.
+pub(crate) fn common_domain(
+    name: &'static str,
+    depth: usize,
+    [old, new]: [&SMTNode; 2],
+    [old_len, new_len]: [usize; 2],
+    direction: bool,
+) -> Result<HashDomain, TraceError> {
+    if old.sibling != new.sibling {
+        return Err(TraceError::SiblingMismatch { name, depth });
+    }
+    let old_domain = node_domain(name, depth, old)?;
+    let new_domain = node_domain(name, depth, new)?;
+    if old_domain == new_domain {
+        return Ok(old_domain);
+    }
+    if depth + 1 == old_len && old_len < new_len && is_extension(old_domain, new_domain, direction)
+    {
+        // Inserting a leaf, so old is before insertion, new is after insertion.
+        Ok(old_domain)
+    } else if depth + 1 == new_len
+        && new_len < old_len
+        && is_extension(new_domain, old_domain, direction)
+    {
+        // Deleting a leaf, so new is after deletion, old is before deletion.
+        Ok(new_domain)
+    } else {
+        Err(TraceError::NodeTypeMismatch { name, depth })
+    }
+}
+
+// Whether the child in direction of a node with domain before becomes a branch in after.
+fn is_extension(before: HashDomain, after: HashDomain, direction: bool) -> bool {
+    if direction {
+        before == HashDomain::Branch0 && after == HashDomain::Branch1
+            || before == HashDomain::Branch2 && after == HashDomain::Branch3
+    } else {
+        before == HashDomain::Branch0 && after == HashDomain::Branch2
+            || before == HashDomain::Branch1 && after == HashDomain::Branch3
+    }
+}
@@ -1,16 +1,31 @@
-use crate::{constraint_builder::Query, serde::HexBytes, types::HashDomain};
-use ethers_core::types::{Address, U256};
+use crate::{
+    constraint_builder::Query,
+    serde::HexBytes,
+    types::{HashDomain, TraceError},
+};
+use ethers_core::{
+    types::{Address, U256},
+    utils::keccak256,
+};
 use halo2_proofs::{
     arithmetic::Field,
     halo2curves::{bn256::Fr, ff::FromUniformBytes, group::ff::PrimeField},
 };
-use hash_circuit::hash::Hashable;
+use hash_circuit::hash::{Hashable, MessageHashable, HASHABLE_DOMAIN_SPEC};
 use num_bigint::BigUint;
 
 pub(crate) fn fr(x: HexBytes<32>) -> Fr {
     Fr::from_bytes(&x.0).unwrap()
 }
 
+pub(crate) fn fr_to_hash(x: Fr) -> HexBytes<32> {
+    HexBytes(x.to_bytes())
+}
+
+pub(crate) fn try_fr(x: HexBytes<32>, field: &'static str) -> Result<Fr, TraceError> {
+    Option::from(Fr::from_bytes(&x.0)).ok_or(TraceError::NonCanonical(field))
+}
+
 pub fn domain_hash(x: Fr, y: Fr, domain: HashDomain) -> Fr {
     Hashable::hash_with_domain([x, y], Fr::from(domain))
 }
@@ -33,6 +48,12 @@ pub(crate) fn u256_from_hex(x: HexBytes<32>) -> U256 {
     U256::from_big_endian(&x.0)
 }
 
+pub(crate) fn u256_to_hex(x: U256) -> HexBytes<32> {
+    let mut bytes = [0; 32];
+    x.to_big_endian(&mut bytes);
+    HexBytes(bytes)
+}
+
 pub(crate) fn split_word(x: U256) -> (Fr, Fr) {
     let mut bytes = [0; 32];
     x.to_big_endian(&mut bytes);
@@ -57,14 +78,6 @@ pub(crate) fn u256_hi_lo(x: &U256) -> (u128, u128) {
         (u128::from(u64_digits[1]) << 64) + u128::from(u64_digits[0]),
     )
 }
-pub(crate) fn fr_from_biguint(b: &BigUint) -> Fr {
-    b.to_u64_digits()
-        .iter()
-        .rev() // to_u64_digits has least significant digit first
-        .fold(Fr::zero(), |a, b| {
-            a * Fr::from(1 << 32).square() + Fr::from(*b)
-        })
-}
 
 pub fn rlc(be_bytes: &[u8], randomness: Fr) -> Fr {
     let x = be_bytes.iter().fold(Fr::zero(), |acc, byte| {
@@ -77,12 +90,38 @@ pub fn u256_from_biguint(x: &BigUint) -> U256 {
     U256::from_big_endian(&x.to_bytes_be())
 }
 
+pub(crate) fn u256_to_biguint(x: U256) -> BigUint {
+    BigUint::from_bytes_be(&u256_to_big_endian(&x))
+}
+
+pub(crate) fn fr_to_biguint(x: Fr) -> BigUint {
+    BigUint::from_bytes_le(&x.to_bytes())
+}
+
 pub fn u256_to_big_endian(x: &U256) -> Vec<u8> {
     let mut bytes = [0; 32];
     x.to_big_endian(&mut bytes);
     bytes.to_vec()
 }
 
+/// Keccak code hash of accounts without code.
+pub fn empty_keccak_code_hash() -> U256 {
+    U256::from_big_endian(
+        &hex::decode("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470").unwrap(),
+    )
+}
+
+/// Poseidon code hash of accounts without code.
+pub fn empty_poseidon_code_hash() -> Fr {
+    let mut bytes: [u8; 32] =
+        hex::decode("2098f5fb9e239eab3ceac3f27b81e481dc3124d55ffed523a839ee8446b64864")
+            .unwrap()
+            .try_into()
+            .unwrap();
+    bytes.reverse();
+    Fr::from_bytes(&bytes).unwrap()
+}
+
 pub fn storage_key_hash(key: U256) -> Fr {
     let (high, low) = split_word(key);
     domain_hash(high, low, HashDomain::Pair)
@@ -98,18 +137,26 @@ pub fn account_key(address: Address) -> Fr {
 }
 
 // Sanity check that before and after branch types match the direction
-pub fn check_domain_consistency(before: HashDomain, after: HashDomain, direction: bool) {
-    if direction {
-        assert!(
-            before == HashDomain::Branch0 && after == HashDomain::Branch1
-                || before == HashDomain::Branch2 && after == HashDomain::Branch3
-        );
-    } else {
-        assert!(
-            before == HashDomain::Branch0 && after == HashDomain::Branch2
-                || before == HashDomain::Branch1 && after == HashDomain::Branch3
-        );
+/// Keccak code hash of the code.
+pub fn keccak_code_hash(code: &[u8]) -> U256 {
+    U256::from_big_endian(&keccak256(code))
+}
+
+/// Poseidon code hash of the code, which is the hash of the code split into 31 byte big endian
+/// field elements, with the last one right padded with 0s, and the code length as capacity.
+pub fn poseidon_code_hash(code: &[u8]) -> Fr {
+    if code.is_empty() {
+        return empty_poseidon_code_hash();
     }
+    let elements: Vec<_> = code
+        .chunks(31)
+        .map(|chunk| {
+            let mut bytes = [0; 31];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            rlc(&bytes, Fr::from(256))
+        })
+        .collect();
+    Fr::hash_msg(&elements, Some(code.len() as u128 * HASHABLE_DOMAIN_SPEC))
 }
 
 pub fn lagrange_polynomial<F: FromUniformBytes<64> + Ord>(
@@ -152,4 +199,13 @@ mod test {
     fn test_u256_hi_lo() {
         assert_eq!(u256_hi_lo(&U256::one()), (0, 1));
     }
+
+    #[test]
+    fn test_empty_code_hashes() {
+        assert_eq!(keccak_code_hash(&[]), empty_keccak_code_hash());
+        assert_eq!(
+            empty_poseidon_code_hash(),
+            Fr::hash_with_domain([Fr::zero(), Fr::zero()], Fr::zero())
+        );
+    }
 }
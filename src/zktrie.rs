@@ -0,0 +1,462 @@
+//! Native sparse binary Poseidon trie, with the same layout as l2geth's zktrie, which produces the
+//! `SMTTrace` witnesses consumed by `Proof::from`.
+//!
+use crate::{
+    serde::{AccountData, HexBytes, SMTNode, SMTPath, SMTTrace, StateData},
+    types::{account_hash, HashDomain},
+    util::{account_key, domain_hash, fr_to_hash, split_word, storage_key_hash, u256_to_hex, Bit},
+};
+use ethers_core::types::{Address, U256};
+use halo2_proofs::halo2curves::bn256::Fr;
+use num_bigint::BigUint;
+use num_traits::identities::{One, Zero};
+use std::collections::HashMap;
+
+pub mod node;
+
+/// Sparse binary trie of (key, value hash) leaves. Leaves are placed at the shortest prefix of
+/// their key bits (least significant first) that distinguishes them from all other keys, so the
+/// trie, and hence its root, only depends on its leaves and not on the order of updates.
+#[derive(Clone, Debug, Default)]
+pub struct ZkTrie {
+    root: Node,
+}
+
+#[derive(Clone, Debug, Default)]
+enum Node {
+    #[default]
+    Empty,
+    Leaf {
+        key: Fr,
+        value_hash: Fr,
+    },
+    Branch {
+        left: Box<Node>,
+        right: Box<Node>,
+        hash: Fr,
+    },
+}
+
+impl Node {
+    fn leaf(key: Fr, value_hash: Fr) -> Self {
+        Self::Leaf { key, value_hash }
+    }
+
+    fn branch(left: Node, right: Node) -> Self {
+        let hash = domain_hash(left.hash(), right.hash(), branch_domain(&left, &right));
+        Self::Branch {
+            left: Box::new(left),
+            right: Box::new(right),
+            hash,
+        }
+    }
+
+    // Branch with the child on the side given by direction, and sibling on the other side.
+    fn branch_with(direction: bool, child: Node, sibling: Node) -> Self {
+        if direction {
+            Self::branch(sibling, child)
+        } else {
+            Self::branch(child, sibling)
+        }
+    }
+
+    fn hash(&self) -> Fr {
+        match self {
+            Self::Empty => Fr::zero(),
+            Self::Leaf { key, value_hash } => domain_hash(*key, *value_hash, HashDomain::Leaf),
+            Self::Branch { hash, .. } => *hash,
+        }
+    }
+
+    fn is_branch(&self) -> bool {
+        matches!(self, Self::Branch { .. })
+    }
+
+    fn insert(self, depth: usize, key: Fr, value_hash: Fr) -> Self {
+        match self {
+            Self::Empty => Self::leaf(key, value_hash),
+            Self::Leaf { key: other_key, .. } if other_key == key => Self::leaf(key, value_hash),
+            Self::Leaf { .. } => split(depth, self, Self::leaf(key, value_hash)),
+            Self::Branch { left, right, .. } => {
+                if key.bit(depth) {
+                    Self::branch(*left, right.insert(depth + 1, key, value_hash))
+                } else {
+                    Self::branch(left.insert(depth + 1, key, value_hash), *right)
+                }
+            }
+        }
+    }
+
+    fn delete(self, depth: usize, key: Fr) -> Self {
+        match self {
+            Self::Empty => Self::Empty,
+            Self::Leaf { key: other_key, .. } if other_key == key => Self::Empty,
+            Self::Leaf { .. } => self,
+            Self::Branch { left, right, .. } => {
+                let direction = key.bit(depth);
+                let (child, sibling) = if direction {
+                    (right.delete(depth + 1, key), *left)
+                } else {
+                    (left.delete(depth + 1, key), *right)
+                };
+                // A branch cannot have a single leaf below it, so the remaining leaf is moved up
+                // until it has a non-empty sibling.
+                match (child, sibling) {
+                    (Self::Empty, leaf @ Self::Leaf { .. })
+                    | (leaf @ Self::Leaf { .. }, Self::Empty) => leaf,
+                    (child, sibling) => Self::branch_with(direction, child, sibling),
+                }
+            }
+        }
+    }
+}
+
+// Branch domain, given by which of the children are also branches.
+fn branch_domain(left: &Node, right: &Node) -> HashDomain {
+    match (left.is_branch(), right.is_branch()) {
+        (false, false) => HashDomain::Branch0,
+        (false, true) => HashDomain::Branch1,
+        (true, false) => HashDomain::Branch2,
+        (true, true) => HashDomain::Branch3,
+    }
+}
+
+// Smallest subtrie at depth holding two leaves with different keys.
+fn split(depth: usize, old_leaf: Node, new_leaf: Node) -> Node {
+    let (old_key, new_key) = match (&old_leaf, &new_leaf) {
+        (Node::Leaf { key: old_key, .. }, Node::Leaf { key: new_key, .. }) => (*old_key, *new_key),
+        _ => unreachable!("can only split leaves"),
+    };
+    let direction = new_key.bit(depth);
+    if old_key.bit(depth) == direction {
+        Node::branch_with(direction, split(depth + 1, old_leaf, new_leaf), Node::Empty)
+    } else {
+        Node::branch_with(direction, new_leaf, old_leaf)
+    }
+}
+
+// Subtrie at depth holding the leaves, which are sorted by their path and have distinct keys.
+fn build(depth: usize, leaves: &[([u8; 32], Fr, Fr)]) -> Node {
+    match leaves {
+        [] => Node::Empty,
+        [(_, key, value_hash)] => Node::leaf(*key, *value_hash),
+        _ => {
+            let split = leaves.partition_point(|(_, key, _)| !key.bit(depth));
+            Node::branch(
+                build(depth + 1, &leaves[..split]),
+                build(depth + 1, &leaves[split..]),
+            )
+        }
+    }
+}
+
+// Key bits in the order they are used for the path, so that sorting by it puts the leaves in the
+// order they appear in the trie.
+fn path_order(key: Fr) -> [u8; 32] {
+    key.to_bytes().map(u8::reverse_bits)
+}
+
+impl ZkTrie {
+    /// Builds the trie from its leaves at once, hashing each node a single time instead of once per
+    /// insertion below it. Later leaves replace earlier ones with the same key.
+    pub fn from_leaves(leaves: impl IntoIterator<Item = (Fr, Fr)>) -> Self {
+        let mut leaves: Vec<_> = leaves
+            .into_iter()
+            .map(|(key, value_hash)| (path_order(key), key, value_hash))
+            .collect();
+        leaves.reverse();
+        leaves.sort_by_key(|(path, _, _)| *path);
+        leaves.dedup_by_key(|(path, _, _)| *path);
+        Self {
+            root: build(0, &leaves),
+        }
+    }
+
+    pub fn root(&self) -> Fr {
+        self.root.hash()
+    }
+
+    /// Value hash of the leaf with this key, if there is one.
+    pub fn get(&self, key: Fr) -> Option<Fr> {
+        let mut node = &self.root;
+        let mut depth = 0;
+        loop {
+            match node {
+                Node::Empty => return None,
+                Node::Leaf {
+                    key: leaf_key,
+                    value_hash,
+                } => return (*leaf_key == key).then_some(*value_hash),
+                Node::Branch { left, right, .. } => {
+                    node = if key.bit(depth) {
+                        right.as_ref()
+                    } else {
+                        left.as_ref()
+                    };
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Inserts a leaf, or updates its value hash if the key is already in the trie.
+    pub fn insert(&mut self, key: Fr, value_hash: Fr) {
+        self.root = std::mem::take(&mut self.root).insert(0, key, value_hash);
+    }
+
+    /// Deletes the leaf with this key, if there is one.
+    pub fn delete(&mut self, key: Fr) {
+        self.root = std::mem::take(&mut self.root).delete(0, key);
+    }
+
+    /// Path from the root to the key. The path ends at the leaf for the key, at a leaf for a
+    /// different key (type 1 nonexistence) or at an empty node (type 2 nonexistence).
+    pub fn path(&self, key: Fr) -> SMTPath {
+        let mut path = vec![];
+        let mut path_part = BigUint::zero();
+        let mut node = &self.root;
+        while let Node::Branch { left, right, .. } = node {
+            let depth = path.len();
+            let direction = key.bit(depth);
+            let (child, sibling) = if direction {
+                (right.as_ref(), left.as_ref())
+            } else {
+                (left.as_ref(), right.as_ref())
+            };
+            path.push(SMTNode {
+                value: fr_to_hash(child.hash()),
+                sibling: fr_to_hash(sibling.hash()),
+                node_type: branch_domain(left, right).into(),
+            });
+            if direction {
+                path_part += BigUint::one() << depth;
+            }
+            node = child;
+        }
+        let leaf = match node {
+            Node::Empty => None,
+            Node::Leaf { key, value_hash } => Some(SMTNode {
+                value: fr_to_hash(*value_hash),
+                sibling: fr_to_hash(*key),
+                node_type: HashDomain::Leaf.into(),
+            }),
+            Node::Branch { .. } => unreachable!(),
+        };
+        SMTPath {
+            root: fr_to_hash(self.root()),
+            leaf,
+            path,
+            path_part,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Account {
+    data: AccountData,
+    storage: ZkTrie,
+    values: HashMap<U256, U256>,
+}
+
+/// Account trie, with the storage trie of each account, whose updates return the `SMTTrace`s
+/// witnessing them.
+#[derive(Clone, Debug, Default)]
+pub struct AccountTrie {
+    trie: ZkTrie,
+    accounts: HashMap<Address, Account>,
+}
+
+impl AccountTrie {
+    /// Builds the account and storage tries at once from the accounts and their storage slots.
+    /// Storage slots with value 0 are skipped.
+    pub fn from_accounts(
+        accounts: impl IntoIterator<Item = (Address, AccountData, HashMap<U256, U256>)>,
+    ) -> Self {
+        let accounts: HashMap<_, _> = accounts
+            .into_iter()
+            .map(|(address, data, mut values)| {
+                values.retain(|_, value| !value.is_zero());
+                let storage = ZkTrie::from_leaves(
+                    values
+                        .iter()
+                        .map(|(key, value)| (storage_key_hash(*key), storage_value_hash(*value))),
+                );
+                let data = AccountData {
+                    storage_root: None,
+                    ..data
+                };
+                (
+                    address,
+                    Account {
+                        data,
+                        storage,
+                        values,
+                    },
+                )
+            })
+            .collect();
+        let trie = ZkTrie::from_leaves(accounts.iter().map(|(address, account)| {
+            (
+                account_key(*address),
+                account_hash(&account.data, account.storage.root()),
+            )
+        }));
+        Self { trie, accounts }
+    }
+
+    pub fn root(&self) -> Fr {
+        self.trie.root()
+    }
+
+    pub fn account(&self, address: Address) -> Option<&AccountData> {
+        self.accounts.get(&address).map(|account| &account.data)
+    }
+
+    /// Storage root of the account, which is 0 if it does not exist.
+    pub fn storage_root(&self, address: Address) -> Fr {
+        self.accounts
+            .get(&address)
+            .map_or_else(Fr::zero, |account| account.storage.root())
+    }
+
+    /// Storage value of the account, which is 0 if the slot or the account does not exist.
+    pub fn storage(&self, address: Address, key: U256) -> U256 {
+        self.accounts
+            .get(&address)
+            .and_then(|account| account.values.get(&key))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Trace reading the account, or proving that it does not exist.
+    pub fn read_account(&self, address: Address) -> SMTTrace {
+        let account_path = self.trie.path(account_key(address));
+        let account = self.account(address).cloned();
+        SMTTrace {
+            address: HexBytes(address.0),
+            account_key: fr_to_hash(account_key(address)),
+            account_path: [account_path.clone(), account_path],
+            account_update: [account.clone(), account],
+            state_path: [None, None],
+            common_state_root: Some(fr_to_hash(self.storage_root(address))),
+            state_key: None,
+            state_update: None,
+        }
+    }
+
+    /// Inserts the account, or updates its fields if it already exists. Its storage is unchanged,
+    /// and the storage root of the account data is ignored.
+    pub fn set_account(&mut self, address: Address, data: AccountData) -> SMTTrace {
+        let data = AccountData {
+            storage_root: None,
+            ..data
+        };
+        let mut trace = self.read_account(address);
+        let account = self.accounts.entry(address).or_default();
+        account.data = data.clone();
+        self.trie.insert(
+            account_key(address),
+            account_hash(&data, account.storage.root()),
+        );
+
+        trace.account_path[1] = self.trie.path(account_key(address));
+        trace.account_update[1] = Some(data);
+        trace
+    }
+
+    /// Deletes the account and its storage.
+    pub fn delete_account(&mut self, address: Address) -> SMTTrace {
+        let mut trace = self.read_account(address);
+        self.accounts.remove(&address);
+        self.trie.delete(account_key(address));
+
+        trace.account_path[1] = self.trie.path(account_key(address));
+        trace.account_update[1] = None;
+        trace
+    }
+
+    /// Trace reading a storage slot, or proving that it or the account does not exist.
+    pub fn read_storage(&self, address: Address, key: U256) -> SMTTrace {
+        let storage_path = self.storage_path(address, storage_key_hash(key));
+        let state_data = StateData {
+            key: u256_to_hex(key),
+            value: u256_to_hex(self.storage(address, key)),
+        };
+        SMTTrace {
+            state_path: [Some(storage_path.clone()), Some(storage_path)],
+            common_state_root: None,
+            state_key: Some(fr_to_hash(storage_key_hash(key))),
+            state_update: Some([Some(state_data), Some(state_data)]),
+            ..self.read_account(address)
+        }
+    }
+
+    /// Sets a storage slot, deleting it if the value is 0. Writing a non-zero value to the storage
+    /// of an account which does not exist creates it with all other fields empty.
+    pub fn set_storage(&mut self, address: Address, key: U256, value: U256) -> SMTTrace {
+        let mut trace = self.read_storage(address, key);
+        if value.is_zero() && !self.accounts.contains_key(&address) {
+            return trace;
+        }
+
+        let account = self.accounts.entry(address).or_default();
+        if value.is_zero() {
+            account.storage.delete(storage_key_hash(key));
+            account.values.remove(&key);
+        } else {
+            account
+                .storage
+                .insert(storage_key_hash(key), storage_value_hash(value));
+            account.values.insert(key, value);
+        }
+        self.trie.insert(
+            account_key(address),
+            account_hash(&account.data, account.storage.root()),
+        );
+
+        trace.account_path[1] = self.trie.path(account_key(address));
+        trace.account_update[1] = Some(account.data.clone());
+        trace.state_path[1] = Some(account.storage.path(storage_key_hash(key)));
+        trace.state_update = Some([
+            trace.state_update.and_then(|[old, _]| old),
+            Some(StateData {
+                key: u256_to_hex(key),
+                value: u256_to_hex(value),
+            }),
+        ]);
+        trace
+    }
+
+    /// Deletes all storage slots of the account.
+    pub fn wipe_storage(&mut self, address: Address) -> SMTTrace {
+        let key = storage_key_hash(U256::zero());
+        let mut trace = self.read_account(address);
+        trace.common_state_root = None;
+        trace.state_path = [Some(self.storage_path(address, key)), None];
+
+        if let Some(account) = self.accounts.get_mut(&address) {
+            account.storage = ZkTrie::default();
+            account.values.clear();
+            self.trie.insert(
+                account_key(address),
+                account_hash(&account.data, Fr::zero()),
+            );
+        }
+
+        trace.account_path[1] = self.trie.path(account_key(address));
+        trace.state_path[1] = Some(self.storage_path(address, key));
+        trace
+    }
+
+    fn storage_path(&self, address: Address, key: Fr) -> SMTPath {
+        self.accounts.get(&address).map_or_else(
+            || ZkTrie::default().path(key),
+            |account| account.storage.path(key),
+        )
+    }
+}
+
+fn storage_value_hash(value: U256) -> Fr {
+    let (high, low) = split_word(value);
+    domain_hash(high, low, HashDomain::Pair)
+}
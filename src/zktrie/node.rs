@@ -0,0 +1,515 @@
+//! Serialized zktrie nodes, as returned by scroll's eth_getProof
+//!
+use super::AccountTrie;
+use crate::{
+    serde::{AccountData, HexBytes, SMTNode, SMTPath, SMTTrace, StateData},
+    types::HashDomain,
+    util::{
+        account_key, domain_hash, fr, fr_to_hash, rlc, storage_key_hash, u256_to_big_endian,
+        u256_to_hex, Bit,
+    },
+};
+use ethers_core::types::{Address, Bytes, U256};
+use halo2_proofs::halo2curves::bn256::Fr;
+use hash_circuit::hash::Hashable;
+use num_bigint::BigUint;
+use num_traits::identities::{One, Zero};
+use serde::{Deserialize, Serialize};
+
+/// Bytes terminating the nodes of a proof.
+pub const PROOF_MAGIC_BYTES: &[u8] = b"THIS IS SOME MAGIC BYTES FOR SMT m1rRXgP2xpDI";
+
+const NODE_TYPE_LEAF: u8 = 4;
+const NODE_TYPE_EMPTY: u8 = 5;
+
+/// Errors decoding serialized zktrie nodes.
+#[derive(Debug, thiserror::Error)]
+pub enum NodeError {
+    #[error("unknown node type {0}")]
+    /// first byte of the node is not a leaf, empty or branch node type
+    UnknownNodeType(u8),
+    #[error("node has {0} bytes, which does not match its type")]
+    /// node is truncated or has trailing bytes
+    InvalidLength(usize),
+    #[error("{0} is not a canonical field element")]
+    /// hash or value bytes are not the big endian encoding of a field element
+    NonCanonical(&'static str),
+    #[error("proof is not terminated by the magic bytes")]
+    /// proof has no terminator
+    MissingMagicBytes,
+    #[error("proof node {0} is after a leaf or empty node")]
+    /// proof continues below a leaf or empty node
+    NodeAfterTerminal(usize),
+    #[error("proof node {0} does not match the child hash of its parent")]
+    /// node hash differs from the child of the branch above it
+    HashMismatch(usize),
+    #[error("proofs are for different {0}")]
+    /// old and new proofs are not for the same address or storage key
+    ProofMismatch(&'static str),
+    #[error("proof has {0} storage proofs, but a trace is for at most one storage slot")]
+    /// account and storage roots of a trace only chain if a single slot changes between the proofs
+    TooManyStorageProofs(usize),
+}
+
+/// Node of a zktrie, in the form it is serialized in proofs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ZkTrieNode {
+    Empty,
+    Branch {
+        /// Branch0..3, depending on which children are branches
+        node_type: HashDomain,
+        left: Fr,
+        right: Fr,
+    },
+    Leaf {
+        key: Fr,
+        /// bit i is set if value_preimage[i] is hashed as two 16 byte halves
+        compressed_flags: u32,
+        value_preimage: Vec<[u8; 32]>,
+        /// address or storage key, which hashes to the key
+        key_preimage: Option<Vec<u8>>,
+    },
+}
+
+impl ZkTrieNode {
+    /// Leaf of an account, whose value preimage is its fields.
+    pub fn account_leaf(address: Address, account: &AccountData, storage_root: Fr) -> Self {
+        let mut nonce_and_code_size = [0; 32];
+        nonce_and_code_size[16..24].copy_from_slice(&account.code_size.to_be_bytes());
+        nonce_and_code_size[24..].copy_from_slice(&account.nonce.to_be_bytes());
+        let mut key_preimage = vec![0; 12];
+        key_preimage.extend_from_slice(&address.0);
+        Self::Leaf {
+            key: account_key(address),
+            compressed_flags: 1 << 3,
+            value_preimage: vec![
+                nonce_and_code_size,
+                biguint_to_bytes(&account.balance),
+                fr_to_be_bytes(storage_root),
+                biguint_to_bytes(&account.code_hash),
+                biguint_to_bytes(&account.poseidon_code_hash),
+            ],
+            key_preimage: Some(key_preimage),
+        }
+    }
+
+    /// Leaf of a storage slot, whose value preimage is its value.
+    pub fn storage_leaf(key: U256, value: U256) -> Self {
+        Self::Leaf {
+            key: storage_key_hash(key),
+            compressed_flags: 1,
+            value_preimage: vec![u256_to_big_endian(&value).try_into().unwrap()],
+            key_preimage: Some(u256_to_big_endian(&key)),
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, NodeError> {
+        let invalid_length = || NodeError::InvalidLength(bytes.len());
+        let (&node_type, data) = bytes.split_first().ok_or_else(invalid_length)?;
+        match node_type {
+            NODE_TYPE_EMPTY => {
+                if !data.is_empty() {
+                    return Err(invalid_length());
+                }
+                Ok(Self::Empty)
+            }
+            NODE_TYPE_LEAF => {
+                if data.len() < 32 + 4 {
+                    return Err(invalid_length());
+                }
+                let key = fr_from_be_bytes(&data[..32], "leaf key")?;
+                let flags = u32::from_le_bytes(data[32..36].try_into().unwrap());
+                let (n_values, compressed_flags) = ((flags & 0xff) as usize, flags >> 8);
+
+                let rest = &data[36..];
+                if rest.len() < 32 * n_values + 1 {
+                    return Err(invalid_length());
+                }
+                let value_preimage = rest[..32 * n_values]
+                    .chunks(32)
+                    .map(|chunk| chunk.try_into().unwrap())
+                    .collect();
+                let key_preimage_length = usize::from(rest[32 * n_values]);
+                let key_preimage = &rest[32 * n_values + 1..];
+                if key_preimage.len() != key_preimage_length {
+                    return Err(invalid_length());
+                }
+                Ok(Self::Leaf {
+                    key,
+                    compressed_flags,
+                    value_preimage,
+                    key_preimage: (key_preimage_length != 0).then(|| key_preimage.to_vec()),
+                })
+            }
+            6..=9 => {
+                if data.len() != 64 {
+                    return Err(invalid_length());
+                }
+                Ok(Self::Branch {
+                    node_type: HashDomain::try_from(u64::from(node_type)).unwrap(),
+                    left: fr_from_be_bytes(&data[..32], "left child")?,
+                    right: fr_from_be_bytes(&data[32..], "right child")?,
+                })
+            }
+            _ => Err(NodeError::UnknownNodeType(node_type)),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::Empty => vec![NODE_TYPE_EMPTY],
+            Self::Branch {
+                node_type,
+                left,
+                right,
+            } => {
+                let mut bytes = vec![u8::try_from(node_type.into_u64()).unwrap()];
+                bytes.extend_from_slice(&fr_to_be_bytes(*left));
+                bytes.extend_from_slice(&fr_to_be_bytes(*right));
+                bytes
+            }
+            Self::Leaf {
+                key,
+                compressed_flags,
+                value_preimage,
+                key_preimage,
+            } => {
+                let mut bytes = vec![NODE_TYPE_LEAF];
+                bytes.extend_from_slice(&fr_to_be_bytes(*key));
+                let flags = (compressed_flags << 8) + u32::try_from(value_preimage.len()).unwrap();
+                bytes.extend_from_slice(&flags.to_le_bytes());
+                for value in value_preimage {
+                    bytes.extend_from_slice(value);
+                }
+                let key_preimage = key_preimage.as_deref().unwrap_or_default();
+                bytes.push(u8::try_from(key_preimage.len()).unwrap());
+                bytes.extend_from_slice(key_preimage);
+                bytes
+            }
+        }
+    }
+
+    pub fn hash(&self) -> Result<Fr, NodeError> {
+        Ok(match self {
+            Self::Empty => Fr::zero(),
+            Self::Branch {
+                node_type,
+                left,
+                right,
+            } => domain_hash(*left, *right, *node_type),
+            Self::Leaf { key, .. } => domain_hash(*key, self.value_hash()?, HashDomain::Leaf),
+        })
+    }
+
+    /// Hash of the value preimage of a leaf, or 0 for other nodes. Compressed values are hashed
+    /// from their 16 byte halves, and the values are then hashed pairwise, level by level, in the
+    /// domain given by their number.
+    pub fn value_hash(&self) -> Result<Fr, NodeError> {
+        let (compressed_flags, value_preimage) = match self {
+            Self::Leaf {
+                compressed_flags,
+                value_preimage,
+                ..
+            } => (compressed_flags, value_preimage),
+            _ => return Ok(Fr::zero()),
+        };
+        let mut values = value_preimage
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                if compressed_flags & (1 << i) != 0 {
+                    let high = rlc(&value[..16], Fr::from(256));
+                    let low = rlc(&value[16..], Fr::from(256));
+                    Ok(domain_hash(high, low, HashDomain::Pair))
+                } else {
+                    fr_from_be_bytes(value, "leaf value")
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let domain = Fr::from(256 * values.len() as u64);
+        while values.len() > 1 {
+            values = values
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => Fr::hash_with_domain([*left, *right], domain),
+                    _ => pair[0],
+                })
+                .collect();
+        }
+        values.pop().ok_or(NodeError::InvalidLength(0))
+    }
+
+    /// Account fields in the value preimage of an account leaf.
+    pub fn account_data(&self) -> Option<AccountData> {
+        let value_preimage = match self {
+            Self::Leaf { value_preimage, .. } if value_preimage.len() == 5 => value_preimage,
+            _ => return None,
+        };
+        Some(AccountData {
+            nonce: u64::from_be_bytes(value_preimage[0][24..].try_into().unwrap()),
+            balance: BigUint::from_bytes_be(&value_preimage[1]),
+            code_hash: BigUint::from_bytes_be(&value_preimage[3]),
+            poseidon_code_hash: BigUint::from_bytes_be(&value_preimage[4]),
+            code_size: u64::from_be_bytes(value_preimage[0][16..24].try_into().unwrap()),
+            storage_root: Some(HexBytes(reversed(value_preimage[2]))),
+        })
+    }
+}
+
+/// Decodes the nodes of a proof, up to the magic bytes terminating it.
+pub fn decode_proof(proof: &[impl AsRef<[u8]>]) -> Result<Vec<ZkTrieNode>, NodeError> {
+    let n_nodes = proof
+        .iter()
+        .position(|bytes| bytes.as_ref() == PROOF_MAGIC_BYTES)
+        .ok_or(NodeError::MissingMagicBytes)?;
+    proof[..n_nodes]
+        .iter()
+        .map(|bytes| ZkTrieNode::decode(bytes.as_ref()))
+        .collect()
+}
+
+/// Encodes the nodes of a proof, followed by the magic bytes.
+pub fn encode_proof(nodes: &[ZkTrieNode]) -> Vec<Vec<u8>> {
+    nodes
+        .iter()
+        .map(ZkTrieNode::encode)
+        .chain([PROOF_MAGIC_BYTES.to_vec()])
+        .collect()
+}
+
+/// Path to the key through the nodes of a proof, which are branches from the root followed by a
+/// leaf or an empty node.
+pub fn smt_path(key: Fr, nodes: &[ZkTrieNode]) -> Result<SMTPath, NodeError> {
+    let mut path = vec![];
+    let mut path_part = BigUint::zero();
+    let mut leaf = None;
+    let mut expected_hash = None;
+    for (depth, node) in nodes.iter().enumerate() {
+        if depth > path.len() {
+            return Err(NodeError::NodeAfterTerminal(depth));
+        }
+        if expected_hash.map_or(false, |expected_hash| expected_hash != node.hash()?) {
+            return Err(NodeError::HashMismatch(depth));
+        }
+        match node {
+            ZkTrieNode::Branch {
+                node_type,
+                left,
+                right,
+            } => {
+                let direction = key.bit(depth);
+                let (child, sibling) = if direction {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                path.push(SMTNode {
+                    value: fr_to_hash(*child),
+                    sibling: fr_to_hash(*sibling),
+                    node_type: node_type.into_u64(),
+                });
+                if direction {
+                    path_part += BigUint::one() << depth;
+                }
+                expected_hash = Some(*child);
+            }
+            ZkTrieNode::Leaf { key, .. } => {
+                leaf = Some(SMTNode {
+                    value: fr_to_hash(node.value_hash()?),
+                    sibling: fr_to_hash(*key),
+                    node_type: HashDomain::Leaf.into(),
+                });
+            }
+            ZkTrieNode::Empty => {}
+        }
+    }
+    let root = nodes.first().map_or(Ok(Fr::zero()), ZkTrieNode::hash)?;
+    Ok(SMTPath {
+        root: fr_to_hash(root),
+        leaf,
+        path,
+        path_part,
+    })
+}
+
+/// Nodes of the proof for the key along its path, ending with the given leaf, or an empty node if
+/// there is none. The path is from the native trie, so its node types are branches.
+pub(crate) fn proof_nodes(key: Fr, path: &SMTPath, leaf: Option<ZkTrieNode>) -> Vec<ZkTrieNode> {
+    path.path
+        .iter()
+        .enumerate()
+        .map(|(depth, node)| {
+            let (value, sibling) = (fr(node.value), fr(node.sibling));
+            let (left, right) = if key.bit(depth) {
+                (sibling, value)
+            } else {
+                (value, sibling)
+            };
+            ZkTrieNode::Branch {
+                node_type: HashDomain::try_from(node.node_type).unwrap(),
+                left,
+                right,
+            }
+        })
+        .chain([leaf.unwrap_or(ZkTrieNode::Empty)])
+        .collect()
+}
+
+/// Proof of an account and its storage slots, as returned by eth_getProof.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct EthProof {
+    pub address: Address,
+    pub account_proof: Vec<Bytes>,
+    #[serde(default)]
+    pub storage_proof: Vec<EthStorageProof>,
+}
+
+/// Proof of a storage slot, as returned by eth_getProof.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct EthStorageProof {
+    pub key: U256,
+    pub value: U256,
+    pub proof: Vec<Bytes>,
+}
+
+impl EthProof {
+    /// Proof of the account and the storage slots in the trie, with leaf preimages that are
+    /// looked up from the accounts and storage it holds.
+    pub fn new(trie: &AccountTrie, address: Address, storage_keys: &[U256]) -> Self {
+        let key = account_key(address);
+        let account_path = trie.trie.path(key);
+        let account_leaf = account_path.leaf.map(|leaf| {
+            let address = trie
+                .accounts
+                .keys()
+                .find(|address| fr_to_hash(account_key(**address)) == leaf.sibling)
+                .unwrap();
+            ZkTrieNode::account_leaf(
+                *address,
+                trie.account(*address).unwrap(),
+                trie.storage_root(*address),
+            )
+        });
+        let storage_proof = storage_keys
+            .iter()
+            .map(|&key| {
+                let storage_path = trie.storage_path(address, storage_key_hash(key));
+                let storage_leaf = storage_path.leaf.map(|leaf| {
+                    let values = &trie.accounts[&address].values;
+                    let (key, value) = values
+                        .iter()
+                        .find(|(key, _)| fr_to_hash(storage_key_hash(**key)) == leaf.sibling)
+                        .unwrap();
+                    ZkTrieNode::storage_leaf(*key, *value)
+                });
+                EthStorageProof {
+                    key,
+                    value: trie.storage(address, key),
+                    proof: encode_proof(&proof_nodes(
+                        storage_key_hash(key),
+                        &storage_path,
+                        storage_leaf,
+                    ))
+                    .into_iter()
+                    .map(Bytes::from)
+                    .collect(),
+                }
+            })
+            .collect();
+        Self {
+            address,
+            account_proof: encode_proof(&proof_nodes(key, &account_path, account_leaf))
+                .into_iter()
+                .map(Bytes::from)
+                .collect(),
+            storage_proof,
+        }
+    }
+
+    fn account_path(&self) -> Result<(SMTPath, Option<AccountData>), NodeError> {
+        let account_key = account_key(self.address);
+        let nodes = decode_proof(&self.account_proof)?;
+        let account = match nodes.last() {
+            Some(leaf @ ZkTrieNode::Leaf { key, .. }) if *key == account_key => leaf.account_data(),
+            _ => None,
+        };
+        Ok((smt_path(account_key, &nodes)?, account))
+    }
+}
+
+/// Trace of the update between the old and new proofs of an account. If the proofs have a storage
+/// proof, the trace is for the update of that storage slot. Proofs with several storage proofs are
+/// rejected, since the account path of the trace would include the updates of the other slots.
+pub fn trace([old, new]: [&EthProof; 2]) -> Result<SMTTrace, NodeError> {
+    if old.address != new.address {
+        return Err(NodeError::ProofMismatch("addresses"));
+    }
+    for proof in [old, new] {
+        if proof.storage_proof.len() > 1 {
+            return Err(NodeError::TooManyStorageProofs(proof.storage_proof.len()));
+        }
+    }
+    let (old_account_path, old_account) = old.account_path()?;
+    let (new_account_path, new_account) = new.account_path()?;
+    let storage_root = |account: &Option<AccountData>| {
+        account
+            .as_ref()
+            .and_then(|account| account.storage_root)
+            .unwrap_or_default()
+    };
+
+    let mut trace = SMTTrace {
+        address: HexBytes(old.address.0),
+        account_key: fr_to_hash(account_key(old.address)),
+        account_path: [old_account_path, new_account_path],
+        account_update: [old_account.clone(), new_account],
+        state_path: [None, None],
+        common_state_root: Some(storage_root(&old_account)),
+        state_key: None,
+        state_update: None,
+    };
+    match (old.storage_proof.first(), new.storage_proof.first()) {
+        (None, None) => {}
+        (Some(old), Some(new)) => {
+            if old.key != new.key {
+                return Err(NodeError::ProofMismatch("storage keys"));
+            }
+            let key = storage_key_hash(old.key);
+            let state_data = |proof: &EthStorageProof| StateData {
+                key: u256_to_hex(proof.key),
+                value: u256_to_hex(proof.value),
+            };
+            trace.state_path = [
+                Some(smt_path(key, &decode_proof(&old.proof)?)?),
+                Some(smt_path(key, &decode_proof(&new.proof)?)?),
+            ];
+            trace.common_state_root = None;
+            trace.state_key = Some(fr_to_hash(key));
+            trace.state_update = Some([Some(state_data(old)), Some(state_data(new))]);
+        }
+        _ => return Err(NodeError::ProofMismatch("storage keys")),
+    }
+    Ok(trace)
+}
+
+fn reversed(mut bytes: [u8; 32]) -> [u8; 32] {
+    bytes.reverse();
+    bytes
+}
+
+fn fr_to_be_bytes(x: Fr) -> [u8; 32] {
+    reversed(x.to_bytes())
+}
+
+fn fr_from_be_bytes(bytes: &[u8], name: &'static str) -> Result<Fr, NodeError> {
+    let bytes: [u8; 32] = bytes.try_into().unwrap();
+    Option::from(Fr::from_bytes(&reversed(bytes))).ok_or(NodeError::NonCanonical(name))
+}
+
+fn biguint_to_bytes(x: &BigUint) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    U256::from_big_endian(&x.to_bytes_be()).to_big_endian(&mut bytes);
+    bytes
+}